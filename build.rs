@@ -0,0 +1,52 @@
+//! Compiles every file under `assets/` (or `$OXIDASE_EMBED_DIR`) into the binary for
+//! `respond: { embedded: (name) }`; names are paths relative to that directory.
+
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=OXIDASE_EMBED_DIR");
+    let manifest = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let root = match env::var_os("OXIDASE_EMBED_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => manifest.join("assets"),
+    };
+    embed(&root, &out.join("embedded_assets.rs"));
+    // the same table for a fixture directory, so tests can check what a build embeds
+    embed(&manifest.join("tests/fixtures/embedded"), &out.join("embedded_fixture.rs"));
+}
+
+/// Write a `&[(name, bytes)]` table of the files under `root` to `dest`; a missing
+/// `root` gives an empty table.
+fn embed(root: &Path, dest: &Path) {
+    let mut files = Vec::new();
+    // watching a path that does not exist would rerun this script on every build
+    if root.is_dir() {
+        println!("cargo:rerun-if-changed={}", root.display());
+        collect(root, &mut files);
+    }
+    files.sort();
+
+    let mut out = String::from("&[\n");
+    for path in &files {
+        let name = path.strip_prefix(root).unwrap().components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        out.push_str(&format!("    ({name:?}, include_bytes!({:?})),\n", path.canonicalize().unwrap()));
+    }
+    out.push(']');
+    fs::write(dest, out).unwrap();
+}
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap().flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
@@ -1,21 +1,91 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use rustls_pki_types::pem::PemObject;
+use tokio_rustls::rustls::{self, RootCertStore, ServerConfig};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+
 use crate::config::error::ConfigError;
-use crate::config::http_server::HttpServer;
+use crate::config::http_server::{BufferSettings, HttpServer, RequestBodyPolicy};
+use crate::config::tls::TlsConfig;
 use crate::build::service::{LoadedService, build_service_ref};
+use crate::util::cidr::Cidr;
 
 #[derive(Debug, Clone)]
 pub struct BuiltHttpServer {
     pub bind: String,
-    pub tls: Option<crate::config::tls::TlsConfig>,
+    /// Present when `tls` is configured and enabled.
+    pub tls: Option<BuiltTls>,
     pub service: LoadedService,
+    pub request_body: RequestBodyPolicy,
+    pub buffers: BufferSettings,
+    pub trusted_proxies: Vec<Cidr>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BuiltTls {
+    pub config: Arc<ServerConfig>,
+    pub handshake_timeout: Duration,
 }
 
 pub fn build_http_server(cfg: HttpServer) -> Result<BuiltHttpServer, ConfigError> {
     cfg.validate()?;
     let base = cfg.base_dir.as_deref().unwrap_or(std::path::Path::new("."));
     let service = build_service_ref(&cfg.service, base)?;
+    let trusted_proxies = cfg.trusted_proxies.iter()
+        .map(|c| Cidr::parse(c).map_err(ConfigError::Invalid))
+        .collect::<Result<_, _>>()?;
+    let tls = match &cfg.tls {
+        Some(tls) if tls.enabled => Some(BuiltTls {
+            config: build_tls(tls, base)?,
+            handshake_timeout: Duration::from_millis(tls.handshake_timeout_ms),
+        }),
+        _ => None,
+    };
     Ok(BuiltHttpServer {
         bind: cfg.bind,
-        tls: cfg.tls,
+        tls,
         service,
+        request_body: cfg.request_body,
+        buffers: cfg.buffers,
+        trusted_proxies,
     })
 }
+
+/// Load the certificate chain and key, and the client CAs for mutual TLS, into a
+/// rustls server config.
+pub fn build_tls(tls: &TlsConfig, base: &Path) -> Result<Arc<ServerConfig>, ConfigError> {
+    let invalid = |what: &str, e: &dyn std::fmt::Display| ConfigError::Invalid(format!("`tls.{what}`: {e}"));
+    let certs = CertificateDer::pem_file_iter(base.join(&tls.cert_file))
+        .and_then(|it| it.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid("cert_file", &e))?;
+    if certs.is_empty() {
+        return Err(invalid("cert_file", &"no PEM certificates found"));
+    }
+    let key = PrivateKeyDer::from_pem_file(base.join(&tls.key_file)).map_err(|e| invalid("key_file", &e))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| invalid("cert_file", &e))?;
+    let builder = match &tls.client_auth {
+        None => builder.with_no_client_auth(),
+        Some(auth) => {
+            let mut roots = RootCertStore::empty();
+            for ca in CertificateDer::pem_file_iter(base.join(&auth.ca_file)).map_err(|e| invalid("client_auth.ca_file", &e))? {
+                let ca = ca.map_err(|e| invalid("client_auth.ca_file", &e))?;
+                roots.add(ca).map_err(|e| invalid("client_auth.ca_file", &e))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if auth.required { verifier } else { verifier.allow_unauthenticated() };
+            builder.with_client_cert_verifier(verifier.build().map_err(|e| invalid("client_auth.ca_file", &e))?)
+        }
+    };
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| invalid("key_file", &e))?;
+    config.alpn_protocols = tls.alpn.iter().map(|p| p.id().to_vec()).collect();
+    Ok(Arc::new(config))
+}
@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 
 use crate::build::service::LoadedService;
 use crate::config::error::ConfigError;
@@ -6,10 +6,11 @@ use crate::config::http_method::HttpMethod;
 use crate::pattern::{
     compile_host,
     compile_path,
+    compile_path_nocase,
     compile_value,
     CompiledPattern,
 };
-use crate::config::router::op::{CondNode, PatternCtxHint, RouterOp};
+use crate::config::router::op::{CondNode, LogLevel, PatternCtxHint, PreloadAs, RouterOp};
 use crate::config::router::r#match::{
     CookieCond,
     HeaderCond,
@@ -17,10 +18,15 @@ use crate::config::router::r#match::{
     RouterMatch,
     Scheme as RouterScheme,
 };
-use crate::config::router::{OnMatch, RouterRule};
+use crate::config::router::{OnMatch, RouterRule, evaluation_order};
 use crate::config::url_scheme::Scheme;
+use crate::handler::router::is_builtin_var;
 use crate::template::{CompiledTemplate, compile_template};
+use crate::util::jwt::JwtAlg;
+use hyper::http;
+use regex::Regex;
 use std::path::Path;
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
 pub struct LoadedRule {
@@ -33,6 +39,7 @@ pub struct LoadedRule {
 pub struct CompiledRouterMatch {
     pub host: Option<CompiledPattern>,
     pub path: Option<CompiledPattern>,
+    pub path_decoded: Option<CompiledPattern>,
     pub methods: Vec<HttpMethod>,
     pub headers: Vec<CompiledHeaderCond>,
     pub queries: Vec<CompiledQueryCond>,
@@ -43,21 +50,24 @@ pub struct CompiledRouterMatch {
 #[derive(Debug, Clone)]
 pub struct CompiledHeaderCond {
     pub name: String,
-    pub pattern: CompiledPattern,
+    /// `None` for an `absent` condition.
+    pub pattern: Option<CompiledPattern>,
     pub not: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct CompiledQueryCond {
     pub key: String,
-    pub pattern: CompiledPattern,
+    /// `None` for an `absent` condition.
+    pub pattern: Option<CompiledPattern>,
     pub not: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct CompiledCookieCond {
     pub name: String,
-    pub pattern: CompiledPattern,
+    /// `None` for an `absent` condition.
+    pub pattern: Option<CompiledPattern>,
     pub not: bool,
 }
 
@@ -68,20 +78,135 @@ pub enum LoadedOp {
     SetHost(CompiledTemplate),
     SetPort(u16),
     SetPath(CompiledTemplate),
+    /// Re-match the current path against `from`; its captures feed the `to` template.
+    RewritePath { from: CompiledPattern, to: CompiledTemplate },
     HeaderSet(BTreeMap<String, CompiledTemplate>),
     HeaderAdd(BTreeMap<String, CompiledTemplate>),
+    HeaderSetIfAbsent(BTreeMap<String, CompiledTemplate>),
     HeaderDelete(Vec<String>),
+    /// Collapse repeated identical values of each named header, keeping the first occurrence.
+    HeaderDedup(Vec<String>),
     HeaderClear,
     QuerySet(BTreeMap<String, CompiledTemplate>),
     QueryAdd(BTreeMap<String, CompiledTemplate>),
     QueryDelete(Vec<String>),
     QueryClear,
+    SortQuery,
+    DecodePath,
+    /// `to` is validated at build time: `header.*`, `query.*`, `cookie.*` or a capture name.
+    CopyValue { from: String, to: String },
+    RequireUserAgent { pattern: Regex, deny: bool },
+    /// Lowercased media types.
+    RequireContentType(Vec<String>),
+    VerifyJwt { key: Vec<u8>, alg: JwtAlg, claims: Vec<String>, leeway_secs: u64 },
+    Delay(Duration),
+    InjectError { status: http::StatusCode, probability: f64 },
     InternalRewrite,
-    Redirect { status: crate::config::router::op::RedirectCode, location: CompiledTemplate },
-    Respond { status: u16, body: Option<CompiledTemplate>, headers: BTreeMap<String, CompiledTemplate> },
+    Redirect {
+        status: crate::config::router::op::RedirectCode,
+        location: CompiledTemplate,
+        preserve_query: bool,
+    },
+    Respond {
+        status: u16,
+        body: Option<CompiledTemplate>,
+        embedded: Option<EmbeddedBody>,
+        headers: BTreeMap<String, CompiledTemplate>,
+        auto_content_type: bool,
+        stream: Option<LoadedRespondStream>,
+    },
+    /// `respond: { passthrough: true }`: replay the `sub.*` captures, then set `headers`.
+    RespondPassthrough { headers: BTreeMap<String, CompiledTemplate> },
+    Metrics,
+    ServerTiming,
+    Preload(Vec<LoadedPreload>),
+    GotoNext,
+    GenNonce { name: String },
+    DebugLog { level: LogLevel, include_body: bool },
+    SignCookie { name: String, value_template: CompiledTemplate, key: Vec<u8>, attributes: String },
+    VerifyCookie { name: String, key: Vec<u8> },
+    Subrequest(Box<LoadedService>),
     Use(Box<LoadedService>),
 }
 
+impl LoadedOp {
+    /// The op's config keyword, used as its metrics label.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LoadedOp::Branch(..) => "branch",
+            LoadedOp::SetScheme(_) => "set_scheme",
+            LoadedOp::SetHost(_) => "set_host",
+            LoadedOp::SetPort(_) => "set_port",
+            LoadedOp::SetPath(_) => "set_path",
+            LoadedOp::RewritePath { .. } => "rewrite_path",
+            LoadedOp::HeaderSet(_) => "header_set",
+            LoadedOp::HeaderAdd(_) => "header_add",
+            LoadedOp::HeaderSetIfAbsent(_) => "header_set_if_absent",
+            LoadedOp::HeaderDelete(_) => "header_delete",
+            LoadedOp::HeaderDedup(_) => "header_dedup",
+            LoadedOp::HeaderClear => "header_clear",
+            LoadedOp::QuerySet(_) => "query_set",
+            LoadedOp::QueryAdd(_) => "query_add",
+            LoadedOp::QueryDelete(_) => "query_delete",
+            LoadedOp::QueryClear => "query_clear",
+            LoadedOp::SortQuery => "sort_query",
+            LoadedOp::DecodePath => "decode_path",
+            LoadedOp::CopyValue { .. } => "copy_value",
+            LoadedOp::RequireUserAgent { .. } => "require_user_agent",
+            LoadedOp::RequireContentType(_) => "require_content_type",
+            LoadedOp::VerifyJwt { .. } => "verify_jwt",
+            LoadedOp::Delay(_) => "delay",
+            LoadedOp::InjectError { .. } => "inject_error",
+            LoadedOp::InternalRewrite => "internal_rewrite",
+            LoadedOp::Redirect { .. } => "redirect",
+            LoadedOp::Respond { .. } | LoadedOp::RespondPassthrough { .. } => "respond",
+            LoadedOp::Metrics => "metrics",
+            LoadedOp::ServerTiming => "server_timing",
+            LoadedOp::Preload(_) => "preload",
+            LoadedOp::GotoNext => "goto_next",
+            LoadedOp::GenNonce { .. } => "gen_nonce",
+            LoadedOp::DebugLog { .. } => "debug_log",
+            LoadedOp::SignCookie { .. } => "sign_cookie",
+            LoadedOp::VerifyCookie { .. } => "verify_cookie",
+            LoadedOp::Subrequest(_) => "subrequest",
+            LoadedOp::Use(_) => "use",
+        }
+    }
+}
+
+/// Names of every op in `ops`, branches included.
+pub fn op_names(ops: &[LoadedOp], out: &mut Vec<&'static str>) {
+    for op in ops {
+        out.push(op.name());
+        if let LoadedOp::Branch(_, then, r#else) = op {
+            op_names(then, out);
+            op_names(r#else, out);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadedRespondStream {
+    pub interval: Duration,
+    pub line: CompiledTemplate,
+    pub count: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadedPreload {
+    pub href: CompiledTemplate,
+    pub r#as: PreloadAs,
+    pub crossorigin: bool,
+}
+
+/// A registered embedded asset, resolved at build time.
+#[derive(Debug, Clone)]
+pub struct EmbeddedBody {
+    pub bytes: &'static [u8],
+    /// Guessed from the asset name, used by `auto_content_type`.
+    pub content_type: String,
+}
+
 #[derive(Debug, Clone)]
 pub enum CompiledCondNode {
     All(Vec<CompiledCondNode>),
@@ -95,7 +220,9 @@ pub enum CompiledBasicCond {
     Equals(serde_yaml::Value),
     In(Vec<serde_yaml::Value>),
     Present(bool),
+    Empty(bool),
     Pattern(CompiledPattern),
+    Compare { gt: Option<f64>, gte: Option<f64>, lt: Option<f64>, lte: Option<f64> },
 }
 
 #[derive(Debug, Clone)]
@@ -104,22 +231,85 @@ pub struct CompiledTestCond {
     pub cond: CompiledBasicCond,
 }
 
+/// Whether any op in `ops`, branches included, reads a variable whose name starts with `prefix`.
+pub fn ops_read_var(ops: &[LoadedOp], prefix: &str) -> bool {
+    let tpl = |t: &CompiledTemplate| t.reads_var(prefix);
+    let map = |m: &BTreeMap<String, CompiledTemplate>| m.values().any(tpl);
+    ops.iter().any(|op| match op {
+        LoadedOp::Branch(cond, then, r#else) =>
+            cond_reads_var(cond, prefix) || ops_read_var(then, prefix) || ops_read_var(r#else, prefix),
+        LoadedOp::SetHost(t) | LoadedOp::SetPath(t) => tpl(t),
+        LoadedOp::RewritePath { to, .. } => tpl(to),
+        LoadedOp::HeaderSet(m)
+        | LoadedOp::HeaderAdd(m)
+        | LoadedOp::HeaderSetIfAbsent(m)
+        | LoadedOp::QuerySet(m)
+        | LoadedOp::QueryAdd(m)
+        | LoadedOp::RespondPassthrough { headers: m } => map(m),
+        LoadedOp::CopyValue { from, .. } => from.starts_with(prefix),
+        LoadedOp::Redirect { location, .. } => tpl(location),
+        LoadedOp::Respond { body, headers, stream, .. } =>
+            body.as_ref().is_some_and(tpl) || map(headers) || stream.as_ref().is_some_and(|s| tpl(&s.line)),
+        LoadedOp::Preload(links) => links.iter().any(|l| tpl(&l.href)),
+        LoadedOp::SignCookie { value_template, .. } => tpl(value_template),
+        _ => false,
+    })
+}
+
+fn cond_reads_var(node: &CompiledCondNode, prefix: &str) -> bool {
+    match node {
+        CompiledCondNode::All(nodes) | CompiledCondNode::Any(nodes) => nodes.iter().any(|n| cond_reads_var(n, prefix)),
+        CompiledCondNode::Not(inner) => cond_reads_var(inner, prefix),
+        CompiledCondNode::Test(test) => test.var.starts_with(prefix),
+    }
+}
+
+/// Compile `rules` in evaluation order (see [`evaluation_order`]).
 pub fn compile_rules(rules: &[RouterRule], base_dir: &Path) -> Result<Vec<LoadedRule>, ConfigError> {
-    rules.iter().map(|r| compile_rule(r, base_dir)).collect()
+    evaluation_order(rules).into_iter().map(|i| compile_rule(i, &rules[i], base_dir)).collect()
 }
 
-fn compile_rule(rule: &RouterRule, base_dir: &Path) -> Result<LoadedRule, ConfigError> {
+fn compile_rule(idx: usize, rule: &RouterRule, base_dir: &Path) -> Result<LoadedRule, ConfigError> {
+    let when = compile_match(rule.when.as_ref().unwrap_or(&RouterMatch::default()))?;
+    if let Some((name, first, second)) = capture_collision(&when) {
+        return Err(ConfigError::Invalid(format!(
+            "router rule {} captures `{name}` in both {first} and {second}",
+            rule.name.clone().unwrap_or_else(|| format!("#{idx}")),
+        )));
+    }
     Ok(LoadedRule {
-        when: compile_match(rule.when.as_ref().unwrap_or(&RouterMatch::default()))?,
+        when,
         ops: compile_ops(&rule.ops, base_dir)?,
         on_match: rule.on_match.clone(),
     })
 }
 
+/// First capture name bound by two patterns of one `when`, with both sources.
+/// The later one would silently overwrite the earlier at match time.
+fn capture_collision(m: &CompiledRouterMatch) -> Option<(String, String, String)> {
+    let sources = m.host.iter().map(|p| ("`host`".to_string(), p))
+        .chain(m.path.iter().map(|p| ("`path`".to_string(), p)))
+        .chain(m.path_decoded.iter().map(|p| ("`path_decoded`".to_string(), p)))
+        .chain(m.headers.iter().filter_map(|h| Some((format!("header `{}`", h.name), h.pattern.as_ref()?))))
+        .chain(m.queries.iter().filter_map(|q| Some((format!("query `{}`", q.key), q.pattern.as_ref()?))))
+        .chain(m.cookies.iter().filter_map(|c| Some((format!("cookie `{}`", c.name), c.pattern.as_ref()?))));
+    let mut seen: HashMap<&str, String> = HashMap::new();
+    for (source, pattern) in sources {
+        for name in pattern.names() {
+            if let Some(first) = seen.get(name.as_str()) {
+                return Some((name.clone(), first.clone(), source));
+            }
+            seen.insert(name, source.clone());
+        }
+    }
+    None
+}
+
 fn compile_match(m: &RouterMatch) -> Result<CompiledRouterMatch, ConfigError> {
     Ok(CompiledRouterMatch {
         host: compile_opt_pattern(m.host.as_deref(), compile_host)?,
-        path: compile_opt_pattern(m.path.as_deref(), compile_path)?,
+        path: compile_path_pattern(m.path.as_deref(), m.path_case_insensitive)?,
+        path_decoded: compile_path_pattern(m.path_decoded.as_deref(), m.path_case_insensitive)?,
         methods: m.methods.clone(),
         headers: compile_headers(&m.headers)?,
         queries: compile_queries(&m.queries)?,
@@ -128,11 +318,19 @@ fn compile_match(m: &RouterMatch) -> Result<CompiledRouterMatch, ConfigError> {
     })
 }
 
+fn compile_path_pattern(input: Option<&str>, nocase: bool) -> Result<Option<CompiledPattern>, ConfigError> {
+    if nocase {
+        compile_opt_pattern(input, compile_path_nocase)
+    } else {
+        compile_opt_pattern(input, compile_path)
+    }
+}
+
 fn compile_headers(headers: &[HeaderCond]) -> Result<Vec<CompiledHeaderCond>, ConfigError> {
     headers.iter().map(|hc| {
         Ok(CompiledHeaderCond {
             name: hc.name.to_ascii_lowercase(),
-            pattern: compile_value(&hc.pattern).map_err(to_config_err)?,
+            pattern: compile_cond_pattern("header", &hc.name, hc.pattern.as_deref(), hc.absent)?,
             not: hc.not,
         })
     }).collect()
@@ -142,7 +340,7 @@ fn compile_queries(queries: &[QueryCond]) -> Result<Vec<CompiledQueryCond>, Conf
     queries.iter().map(|qc| {
         Ok(CompiledQueryCond {
             key: qc.key.clone(),
-            pattern: compile_value(&qc.pattern).map_err(to_config_err)?,
+            pattern: compile_cond_pattern("query", &qc.key, qc.pattern.as_deref(), qc.absent)?,
             not: qc.not,
         })
     }).collect()
@@ -152,12 +350,31 @@ fn compile_cookies(cookies: &[CookieCond]) -> Result<Vec<CompiledCookieCond>, Co
     cookies.iter().map(|cc| {
         Ok(CompiledCookieCond {
             name: cc.name.clone(),
-            pattern: compile_value(&cc.pattern).map_err(to_config_err)?,
+            pattern: compile_cond_pattern("cookie", &cc.name, cc.pattern.as_deref(), cc.absent)?,
             not: cc.not,
         })
     }).collect()
 }
 
+/// A header/query/cookie condition needs exactly one of `pattern` and `absent`.
+fn compile_cond_pattern(
+    kind: &str,
+    name: &str,
+    pattern: Option<&str>,
+    absent: bool,
+) -> Result<Option<CompiledPattern>, ConfigError> {
+    match (pattern, absent) {
+        (Some(p), false) => Ok(Some(compile_value(p).map_err(to_config_err)?)),
+        (None, true) => Ok(None),
+        (Some(_), true) => Err(ConfigError::Invalid(format!(
+            "{kind} condition `{name}` cannot have both `pattern` and `absent`"
+        ))),
+        (None, false) => Err(ConfigError::Invalid(format!(
+            "{kind} condition `{name}` needs a `pattern` or `absent: true`"
+        ))),
+    }
+}
+
 fn compile_opt_pattern<F>(
     input: Option<&str>,
     f: F,
@@ -184,6 +401,10 @@ fn compile_op(op: &RouterOp, base_dir: &Path) -> Result<LoadedOp, ConfigError> {
         RouterOp::SetHost(h) => LoadedOp::SetHost(compile_template(h).map_err(to_config_err)?),
         RouterOp::SetPort(p) => LoadedOp::SetPort(*p),
         RouterOp::SetPath(p) => LoadedOp::SetPath(compile_template(p).map_err(to_config_err)?),
+        RouterOp::RewritePath { from, to } => LoadedOp::RewritePath {
+            from: compile_path(from).map_err(to_config_err)?,
+            to: compile_template(to).map_err(to_config_err)?,
+        },
         RouterOp::HeaderSet(m) => {
             let mut compiled = BTreeMap::new();
             for (k, v) in m {
@@ -198,7 +419,15 @@ fn compile_op(op: &RouterOp, base_dir: &Path) -> Result<LoadedOp, ConfigError> {
             }
             LoadedOp::HeaderAdd(compiled)
         }
+        RouterOp::HeaderSetIfAbsent(m) => {
+            let mut compiled = BTreeMap::new();
+            for (k, v) in m {
+                compiled.insert(k.clone(), compile_template(v).map_err(to_config_err)?);
+            }
+            LoadedOp::HeaderSetIfAbsent(compiled)
+        }
         RouterOp::HeaderDelete(v) => LoadedOp::HeaderDelete(v.clone()),
+        RouterOp::HeaderDedup(v) => LoadedOp::HeaderDedup(v.clone()),
         RouterOp::HeaderClear => LoadedOp::HeaderClear,
         RouterOp::QuerySet(m) => {
             let mut compiled = BTreeMap::new();
@@ -216,19 +445,137 @@ fn compile_op(op: &RouterOp, base_dir: &Path) -> Result<LoadedOp, ConfigError> {
         }
         RouterOp::QueryDelete(v) => LoadedOp::QueryDelete(v.clone()),
         RouterOp::QueryClear => LoadedOp::QueryClear,
+        RouterOp::SortQuery => LoadedOp::SortQuery,
+        RouterOp::DecodePath => LoadedOp::DecodePath,
+        RouterOp::CopyValue { from, to } => {
+            validate_copy_target(to)?;
+            LoadedOp::CopyValue { from: from.clone(), to: to.clone() }
+        }
+        RouterOp::RequireUserAgent { pattern, deny } => LoadedOp::RequireUserAgent {
+            pattern: Regex::new(pattern).map_err(to_config_err)?,
+            deny: *deny,
+        },
+        RouterOp::RequireContentType(types) => {
+            if types.is_empty() {
+                return Err(ConfigError::Invalid("require_content_type: list at least one media type".into()));
+            }
+            LoadedOp::RequireContentType(types.iter().map(|t| t.trim().to_ascii_lowercase()).collect())
+        }
+        RouterOp::VerifyJwt { key, alg, claims, leeway_secs } => LoadedOp::VerifyJwt {
+            key: crate::build::service::expand_env(key)?.into_bytes(),
+            alg: *alg,
+            claims: claims.clone(),
+            leeway_secs: *leeway_secs,
+        },
+        RouterOp::Delay(d) => LoadedOp::Delay(*d),
+        RouterOp::InjectError { status, probability } => {
+            if !(0.0..=1.0).contains(probability) {
+                return Err(ConfigError::Invalid(format!(
+                    "`inject_error.probability` must be within [0, 1], got {probability}"
+                )));
+            }
+            LoadedOp::InjectError {
+                status: http::StatusCode::from_u16(*status).map_err(to_config_err)?,
+                probability: *probability,
+            }
+        }
         RouterOp::InternalRewrite => LoadedOp::InternalRewrite,
-        RouterOp::Redirect { status, location } =>
-            LoadedOp::Redirect { status: *status, location: compile_template(location).map_err(to_config_err)? },
-        RouterOp::Respond { status, body, headers } => {
+        RouterOp::Redirect { status, location, preserve_query } => LoadedOp::Redirect {
+            status: *status,
+            location: compile_template(location).map_err(to_config_err)?,
+            preserve_query: *preserve_query,
+        },
+        RouterOp::Respond { status, body, embedded, headers, auto_content_type, stream, passthrough: true } => {
+            if status.is_some() || body.is_some() || embedded.is_some() || stream.is_some() || *auto_content_type {
+                return Err(ConfigError::Invalid(
+                    "respond: `passthrough` takes only `headers`; status and body come from the subrequest".into(),
+                ));
+            }
+            let mut compiled_headers = BTreeMap::new();
+            for (k, v) in headers {
+                compiled_headers.insert(k.clone(), compile_template(v).map_err(to_config_err)?);
+            }
+            LoadedOp::RespondPassthrough { headers: compiled_headers }
+        }
+        RouterOp::Respond { status, body, embedded, headers, auto_content_type, stream, passthrough: false } => {
+            let Some(status) = status else {
+                return Err(ConfigError::Invalid("respond: `status` is required".into()));
+            };
+            if body.is_some() && embedded.is_some() {
+                return Err(ConfigError::Invalid("respond: set either body or embedded, not both".into()));
+            }
             let compiled_body = match body {
                 Some(b) => Some(compile_template(b).map_err(to_config_err)?),
                 None => None,
             };
+            let embedded = match embedded {
+                Some(name) => Some(EmbeddedBody {
+                    bytes: crate::embedded::get(name).ok_or_else(|| {
+                        ConfigError::Invalid(format!("respond: embedded asset `{name}` is not registered"))
+                    })?,
+                    content_type: mime_guess::from_path(name).first_or_octet_stream().to_string(),
+                }),
+                None => None,
+            };
             let mut compiled_headers = BTreeMap::new();
             for (k, v) in headers {
                 compiled_headers.insert(k.clone(), compile_template(v).map_err(to_config_err)?);
             }
-            LoadedOp::Respond { status: *status, body: compiled_body, headers: compiled_headers }
+            LoadedOp::Respond {
+                status: *status,
+                body: compiled_body,
+                embedded,
+                headers: compiled_headers,
+                auto_content_type: *auto_content_type,
+                stream: match stream {
+                    Some(st) if st.interval_ms == 0 => {
+                        return Err(ConfigError::Invalid("respond: `stream.interval_ms` must be positive".into()));
+                    }
+                    Some(st) => Some(LoadedRespondStream {
+                        interval: Duration::from_millis(st.interval_ms),
+                        line: compile_template(&st.line_template).map_err(to_config_err)?,
+                        count: st.count,
+                    }),
+                    None => None,
+                },
+            }
+        }
+        RouterOp::Metrics => LoadedOp::Metrics,
+        RouterOp::ServerTiming => LoadedOp::ServerTiming,
+        RouterOp::Preload(links) => LoadedOp::Preload(
+            links.iter().map(|l| Ok(LoadedPreload {
+                href: compile_template(&l.href).map_err(to_config_err)?,
+                r#as: l.r#as,
+                crossorigin: l.crossorigin,
+            })).collect::<Result<_, ConfigError>>()?,
+        ),
+        RouterOp::GotoNext => LoadedOp::GotoNext,
+        RouterOp::GenNonce { name } => {
+            if name.trim().is_empty() {
+                return Err(ConfigError::Invalid("gen_nonce: `name` cannot be empty".into()));
+            }
+            LoadedOp::GenNonce { name: name.clone() }
+        }
+        RouterOp::DebugLog { level, include_body } =>
+            LoadedOp::DebugLog { level: *level, include_body: *include_body },
+        RouterOp::SignCookie { name, value_template, key, attributes } => {
+            if http::HeaderValue::from_str(attributes).is_err() {
+                return Err(ConfigError::Invalid(format!("sign_cookie: invalid `attributes` `{attributes}`")));
+            }
+            LoadedOp::SignCookie {
+                name: cookie_name(name, "sign_cookie")?,
+                value_template: compile_template(value_template).map_err(to_config_err)?,
+                key: cookie_key(key, "sign_cookie")?,
+                attributes: attributes.clone(),
+            }
+        }
+        RouterOp::VerifyCookie { name, key } => LoadedOp::VerifyCookie {
+            name: cookie_name(name, "verify_cookie")?,
+            key: cookie_key(key, "verify_cookie")?,
+        },
+        RouterOp::Subrequest(svc) => {
+            let built = crate::build::service::build_service_ref(svc, base_dir)?;
+            LoadedOp::Subrequest(Box::new(built))
         }
         RouterOp::Use(svc) => {
             let built = crate::build::service::build_service_ref(svc, base_dir)?;
@@ -237,6 +584,39 @@ fn compile_op(op: &RouterOp, base_dir: &Path) -> Result<LoadedOp, ConfigError> {
     })
 }
 
+fn validate_copy_target(to: &str) -> Result<(), ConfigError> {
+    let invalid = |why: &str| Err(ConfigError::Invalid(format!("copy_value: cannot write to `{to}`: {why}")));
+    if let Some(name) = to.strip_prefix("header.") {
+        if http::HeaderName::try_from(name).is_err() {
+            return invalid("not a valid header name");
+        }
+    } else if to.is_empty() || to.ends_with('.') {
+        return invalid("empty name");
+    } else if !to.starts_with("query.") && !to.starts_with("cookie.") && is_builtin_var(to) {
+        // built-in variables are derived from the request; a capture of that name would be shadowed
+        return invalid("read-only variable");
+    }
+    Ok(())
+}
+
+/// A cookie name must be an RFC 6265 token.
+fn cookie_name(name: &str, op: &str) -> Result<String, ConfigError> {
+    let is_token = !name.is_empty()
+        && name.bytes().all(|b| b.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?={}".contains(&b));
+    if !is_token {
+        return Err(ConfigError::Invalid(format!("{op}: `{name}` is not a valid cookie name")));
+    }
+    Ok(name.to_string())
+}
+
+fn cookie_key(key: &str, op: &str) -> Result<Vec<u8>, ConfigError> {
+    let key = crate::build::service::expand_env(key)?;
+    if key.is_empty() {
+        return Err(ConfigError::Invalid(format!("{op}: `key` cannot be empty")));
+    }
+    Ok(key.into_bytes())
+}
+
 fn compile_cond(node: &CondNode) -> Result<CompiledCondNode, ConfigError> {
     Ok(match node {
         CondNode::All { all } => CompiledCondNode::All(
@@ -258,6 +638,7 @@ fn compile_basic_cond(var: &str, cond: &crate::config::router::op::BasicCond) ->
         crate::config::router::op::BasicCond::Equals { is } => CompiledBasicCond::Equals(is.clone()),
         crate::config::router::op::BasicCond::In { r#in } => CompiledBasicCond::In(r#in.clone()),
         crate::config::router::op::BasicCond::Present { present } => CompiledBasicCond::Present(*present),
+        crate::config::router::op::BasicCond::Empty { empty } => CompiledBasicCond::Empty(*empty),
         crate::config::router::op::BasicCond::Pattern { pattern, ctx } => {
             let pat = match select_pattern_ctx(var, ctx) {
                 PatternSelect::Host => compile_host(pattern),
@@ -266,6 +647,14 @@ fn compile_basic_cond(var: &str, cond: &crate::config::router::op::BasicCond) ->
             }.map_err(to_config_err)?;
             CompiledBasicCond::Pattern(pat)
         }
+        crate::config::router::op::BasicCond::Compare { gt, gte, lt, lte } => {
+            if gt.is_none() && gte.is_none() && lt.is_none() && lte.is_none() {
+                return Err(ConfigError::Invalid(format!(
+                    "test on `{var}` needs one of `is`, `in`, `present`, `empty`, `pattern`, `gt`, `gte`, `lt`, `lte`"
+                )));
+            }
+            CompiledBasicCond::Compare { gt: *gt, gte: *gte, lt: *lt, lte: *lte }
+        }
     })
 }
 
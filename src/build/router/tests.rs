@@ -6,12 +6,14 @@ use crate::config::router::op::RouterOp;
 #[test]
 fn compile_simple_rule() {
     let rule = RouterRule {
+        name: None,
         when: Some(RouterMatch {
             host: Some("example.com".into()),
             ..RouterMatch::default()
         }),
         ops: vec![RouterOp::SetHost("upstream".into())],
         on_match: OnMatch::default(),
+        priority: 0,
     };
 
     let compiled = compile_rules(&[rule], std::path::Path::new(".")).expect("compile failed");
@@ -19,3 +21,179 @@ fn compile_simple_rule() {
     assert!(compiled[0].when.host.is_some());
     assert_eq!(compiled[0].ops.len(), 1);
 }
+
+fn build_router_yaml(yaml: &str) -> Result<LoadedService, ConfigError> {
+    let cfg: crate::config::service::Service = serde_yaml::from_str(yaml).expect("invalid yaml");
+    crate::build::service::build_service(&cfg, std::path::Path::new("."))
+}
+
+#[test]
+fn testing_ops_require_flag() {
+    let err = build_router_yaml(r#"
+handler: router
+rules:
+  - ops:
+      - branch:
+          if: { var: path, present: true }
+          then:
+            - delay: { ms: 10 }
+"#).unwrap_err();
+    assert!(err.to_string().contains("enable_testing_ops"));
+
+    build_router_yaml(r#"
+handler: router
+enable_testing_ops: true
+rules:
+  - ops:
+      - delay: { ms: 10 }
+"#).expect("flagged router should build");
+}
+
+#[test]
+fn inject_error_probability_is_validated() {
+    let err = build_router_yaml(r#"
+handler: router
+enable_testing_ops: true
+rules:
+  - ops:
+      - inject_error: { probability: 1.5 }
+"#).unwrap_err();
+    assert!(err.to_string().contains("probability"));
+}
+
+#[test]
+fn condition_needs_pattern_or_absent() {
+    let err = build_router_yaml(r#"
+handler: router
+rules:
+  - when: { headers: [{ name: x-a }] }
+"#).unwrap_err();
+    assert!(err.to_string().contains("needs a `pattern` or `absent: true`"), "{err}");
+
+    let err = build_router_yaml(r#"
+handler: router
+rules:
+  - when: { cookies: [{ name: sid, pattern: "*", absent: true }] }
+"#).unwrap_err();
+    assert!(err.to_string().contains("cannot have both"), "{err}");
+}
+
+#[test]
+fn capture_bound_twice_in_one_rule_is_rejected() {
+    let err = build_router_yaml(r#"
+handler: router
+rules:
+  - name: items
+    when: { host: "<id:label>.example.com", path: "/items/<id:uint>" }
+    ops:
+      - respond: { status: 200, body: "${id}" }
+"#).unwrap_err();
+    assert!(err.to_string().contains("rule items captures `id` in both `host` and `path`"), "{err}");
+
+    let err = build_router_yaml(r#"
+handler: router
+rules:
+  - when:
+      path: "/<v:segment>"
+      headers: [{ name: x-version, pattern: "<v:uint>" }]
+    ops:
+      - respond: { status: 200 }
+"#).unwrap_err();
+    assert!(err.to_string().contains("rule #0 captures `v` in both `path` and header `x-version`"), "{err}");
+}
+
+#[test]
+fn respond_embedded_must_be_registered() {
+    let err = build_router_yaml(r#"
+handler: router
+rules:
+  - ops:
+      - respond: { status: 200, embedded: never-registered.html }
+"#).unwrap_err();
+    assert!(err.to_string().contains("never-registered.html"));
+}
+
+#[test]
+fn copy_value_rejects_read_only_targets() {
+    let err = build_router_yaml(r#"
+handler: router
+rules:
+  - ops:
+      - copy_value: { from: header.x-host, to: host }
+"#).unwrap_err();
+    assert!(err.to_string().contains("read-only"));
+
+    let copy_to = |to: &str| build_router_yaml(&format!(r#"
+handler: router
+rules:
+  - ops:
+      - copy_value: {{ from: header.x-v, to: "{to}" }}
+"#));
+    for to in [
+        "remote.ip", "cert.cn", "cert.san", "cert.fingerprint", "target_form", "now.unix", "now.hour",
+        "now.weekday", "path_decoded", "query_string", "query_count", "header_count", "cookie_count",
+        "multipart.has.file", "form.action", "captures_json",
+    ] {
+        let err = copy_to(to).unwrap_err();
+        assert!(err.to_string().contains("read-only"), "{to}: {err}");
+    }
+    for to in ["header.x-id", "query.id", "cookie.id", "tenant"] {
+        assert!(copy_to(to).is_ok(), "{to}");
+    }
+}
+
+#[test]
+fn nested_static_default_content_type_is_checked_at_build() {
+    let err = build_router_yaml(r#"
+handler: router
+rules:
+  - ops:
+      - use:
+          handler: static
+          source_dir: "."
+          default_content_type: "text/plain\u0001"
+"#).unwrap_err();
+    assert!(err.to_string().contains("default_content_type"));
+}
+
+#[test]
+fn method_names_are_normalised_and_checked() {
+    use crate::config::http_method::HttpMethod;
+    let methods = |yaml: &str| serde_yaml::from_str::<RouterMatch>(yaml).map(|m| m.methods);
+
+    assert_eq!(
+        methods("methods: [get, Post, PURGE, purge]").unwrap(),
+        [HttpMethod::Get, HttpMethod::Post, HttpMethod::Other("PURGE".into()), HttpMethod::Other("purge".into())],
+    );
+    assert!(methods("methods: [\"GET POST\"]").is_err());
+    assert!(methods("methods: [\"\"]").is_err());
+}
+
+#[test]
+fn multipart_scan_is_enabled_only_when_read() {
+    let scans = |yaml: &str| match build_router_yaml(yaml).unwrap() {
+        LoadedService::Router(r) => r.scan_multipart,
+        _ => unreachable!(),
+    };
+    assert!(!scans(r#"
+handler: router
+rules:
+  - ops:
+      - respond: { status: 200, body: "${path}" }
+"#));
+    assert!(scans(r#"
+handler: router
+rules:
+  - ops:
+      - branch:
+          if: { var: multipart.has.file, is: "true" }
+          then:
+            - respond: { status: 200 }
+"#));
+    assert!(scans(r#"
+handler: router
+rules:
+  - ops:
+      - respond: { status: 200, body: "${multipart.has.avatar ?? \"no\"}" }
+"#));
+}
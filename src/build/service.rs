@@ -1,14 +1,30 @@
 use crate::config::error::ConfigError;
-use crate::config::forward::ForwardService;
-use crate::config::router::RouterService;
+use crate::config::forward::{AuthScheme, ForwardAuth, ForwardService, ReadinessProbe};
+use crate::config::url_scheme::Scheme;
+use crate::config::router::{RouterService, evaluation_order};
+use crate::config::router::op::RouterOp;
 use crate::config::service::{Service, ServiceRef, resolve_service_ref};
 use crate::config::r#static::StaticService;
 use crate::build::router::{
     LoadedRule,
     compile_rules,
+    op_names,
+    ops_read_var,
 };
+use crate::handler::r#static::FileCache;
+use crate::handler::forward::pool::{UpstreamClient, upstream_client};
+use crate::handler::forward::ready::Readiness;
+use crate::metrics::{PoolStats, RouterMetrics};
+use crate::template::ExpandOptions;
+use crate::util::clock::{Clock, SystemClock};
 use std::collections::HashSet;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use base64::Engine;
+use regex::Regex;
+use hyper::http::HeaderValue;
 
 const DEFAULT_MAX_STEPS: u32 = 16;
 
@@ -21,12 +37,28 @@ pub enum LoadedService {
 
 #[derive(Debug, Clone)]
 pub struct LoadedStatic {
-    pub config: StaticService,
+    pub config: Box<StaticService>,
+    pub cache: Option<Arc<FileCache>>,
+    /// Canonical `config.source_dir`, present when `follow_symlinks` is off.
+    pub root: Option<PathBuf>,
+    /// Parsed `config.default_content_type`.
+    pub default_type: Option<HeaderValue>,
+    /// Compiled `config.immutable_pattern`.
+    pub immutable: Option<Regex>,
+    pub fallback: Option<Box<LoadedService>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct LoadedForward {
-    pub config: ForwardService,
+    pub config: Box<ForwardService>,
+    /// Pre-built `Authorization` value from `config.auth`.
+    pub auth_header: Option<HeaderValue>,
+    pub client: Box<UpstreamClient>,
+    pub pool: Arc<PoolStats>,
+    /// Present when `config.max_concurrent` is set.
+    pub limiter: Option<Arc<Semaphore>>,
+    /// Present when `config.wait_for_ready` is set.
+    pub ready: Option<Arc<Readiness>>,
 }
 
 #[derive(Debug, Clone)]
@@ -34,6 +66,15 @@ pub struct LoadedRouter {
     pub rules: Vec<LoadedRule>,
     pub next: Option<Box<LoadedService>>,
     pub max_steps: u32,
+    pub metrics: Arc<RouterMetrics>,
+    pub rewrite_host: bool,
+    pub redirect_loop_guard: bool,
+    pub decode_cookies: bool,
+    /// Whether any rule reads `multipart.has.*`; when none does, bodies are not scanned.
+    pub scan_multipart: bool,
+    pub template: ExpandOptions,
+    /// Read once per request into `RouterCtx::now`.
+    pub clock: Arc<dyn Clock>,
 }
 
 pub fn build_service_ref(cfg: &ServiceRef, base_dir: &Path) -> Result<LoadedService, ConfigError> {
@@ -44,8 +85,51 @@ pub fn build_service_ref(cfg: &ServiceRef, base_dir: &Path) -> Result<LoadedServ
 
 pub fn build_service(cfg: &Service, base_dir: &Path) -> Result<LoadedService, ConfigError> {
     Ok(match cfg {
-        Service::Static(st) => LoadedService::Static(LoadedStatic { config: st.clone() }),
-        Service::Forward(fw) => LoadedService::Forward(LoadedForward { config: fw.clone() }),
+        Service::Static(st) => LoadedService::Static(LoadedStatic {
+            config: Box::new(st.clone()),
+            cache: st.cache.clone().map(|c| Arc::new(FileCache::new(c))),
+            root: if st.follow_symlinks {
+                None
+            } else {
+                Some(std::fs::canonicalize(&st.source_dir).map_err(|e| {
+                    ConfigError::Invalid(format!("`static.source_dir` cannot be resolved: {e}"))
+                })?)
+            },
+            default_type: match &st.default_content_type {
+                Some(ct) => Some(HeaderValue::from_str(ct).map_err(|_| {
+                    ConfigError::Invalid("`static.default_content_type` is not a valid header value".into())
+                })?),
+                None => None,
+            },
+            immutable: match &st.immutable_pattern {
+                Some(p) => Some(Regex::new(p).map_err(|e| {
+                    ConfigError::Invalid(format!("`static.immutable_pattern` is not a valid regex: {e}"))
+                })?),
+                None => None,
+            },
+            fallback: match &st.fallback {
+                Some(f) => Some(Box::new(build_service_ref(f, base_dir)?)),
+                None => None,
+            },
+        }),
+        Service::Forward(fw) => {
+            let pool = PoolStats::register(format!("{}:{}", fw.target.host, fw.target.port));
+            let client = build_upstream_client(fw, pool.clone())?;
+            let ready = fw.wait_for_ready.as_ref().map(|probe| build_readiness(fw, probe)).transpose()?;
+            if let Some(gate) = &ready
+                && tokio::runtime::Handle::try_current().is_ok()
+            {
+                gate.start(client.clone());
+            }
+            LoadedService::Forward(LoadedForward {
+                config: fw.clone(),
+                auth_header: fw.auth.as_deref().map(build_auth_header).transpose()?,
+                client: Box::new(client),
+                pool,
+                limiter: fw.max_concurrent.map(|n| Arc::new(Semaphore::new(n))),
+                ready,
+            })
+        }
         Service::Router(rt) => build_router(rt, base_dir)?,
     })
 }
@@ -57,11 +141,95 @@ fn build_router(rt: &RouterService, base_dir: &Path) -> Result<LoadedService, Co
     };
     let max_steps = rt.max_steps.unwrap_or(DEFAULT_MAX_STEPS);
 
+    if !rt.enable_testing_ops
+        && let Some(name) = rt.rules.iter().find_map(|r| find_testing_op(&r.ops))
+    {
+        return Err(ConfigError::Invalid(format!(
+            "router op `{name}` requires `enable_testing_ops: true`"
+        )));
+    }
+
     let rules = compile_rules(&rt.rules, base_dir)?;
+    let scan_multipart = rules.iter().any(|r| ops_read_var(&r.ops, "multipart.has."));
+    let mut ops = Vec::new();
+    for rule in &rules {
+        op_names(&rule.ops, &mut ops);
+    }
+    let metrics = RouterMetrics::new(
+        evaluation_order(&rt.rules).into_iter().map(|i| rt.rules[i].name.clone().unwrap_or_else(|| i.to_string())),
+        ops,
+    );
 
     Ok(LoadedService::Router(LoadedRouter {
         rules,
         next,
         max_steps,
+        metrics: Arc::new(metrics),
+        rewrite_host: rt.rewrite_host,
+        redirect_loop_guard: rt.redirect_loop_guard,
+        decode_cookies: rt.decode_cookies,
+        scan_multipart,
+        template: ExpandOptions {
+            max_len: rt.max_template_len,
+            missing_default: rt.template_missing_default.clone(),
+        },
+        clock: Arc::new(SystemClock),
     }))
 }
+
+fn find_testing_op(ops: &[RouterOp]) -> Option<&'static str> {
+    ops.iter().find_map(|op| match op {
+        RouterOp::Branch(b) => find_testing_op(&b.then).or_else(|| find_testing_op(&b.r#else)),
+        _ => op.testing_only_name(),
+    })
+}
+
+fn build_auth_header(auth: &ForwardAuth) -> Result<HeaderValue, ConfigError> {
+    let raw = match &auth.scheme {
+        AuthScheme::Bearer(token) => format!("Bearer {}", expand_env(token)?),
+        AuthScheme::Basic { username, password } => {
+            let creds = format!("{}:{}", expand_env(username)?, expand_env(password)?);
+            format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(creds))
+        }
+    };
+    let mut value = HeaderValue::from_str(&raw)
+        .map_err(|_| ConfigError::Invalid("`forward.auth` is not a valid header value".into()))?;
+    value.set_sensitive(true);
+    Ok(value)
+}
+
+fn build_upstream_client(fw: &ForwardService, pool: Arc<PoolStats>) -> Result<UpstreamClient, ConfigError> {
+    let proxy = fw.upstream_proxy.as_deref().map(expand_env).transpose()?;
+    upstream_client(fw, proxy.as_deref(), pool).map_err(ConfigError::Invalid)
+}
+
+fn build_readiness(fw: &ForwardService, probe: &ReadinessProbe) -> Result<Arc<Readiness>, ConfigError> {
+    let scheme = match fw.target.scheme {
+        Scheme::Http => "http",
+        Scheme::Https => "https",
+    };
+    let uri = format!("{scheme}://{}:{}{}", fw.target.host, fw.target.port, probe.path)
+        .parse()
+        .map_err(|e| ConfigError::Invalid(format!("invalid `forward.wait_for_ready.path`: {e}")))?;
+    Ok(Readiness::new(uri, Duration::from_millis(probe.interval_ms)))
+}
+
+/// Substitute `${env.NAME}` references with the named environment variable.
+pub(crate) fn expand_env(s: &str) -> Result<String, ConfigError> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("${env.") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + "${env.".len()..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| ConfigError::Invalid(format!("unterminated `${{env.` in `{s}`")))?;
+        let name = &after[..end];
+        let value = std::env::var(name)
+            .map_err(|_| ConfigError::Invalid(format!("environment variable `{name}` is not set")))?;
+        out.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
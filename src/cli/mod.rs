@@ -4,7 +4,7 @@ use std::path::{Path, PathBuf};
 use clap::{Parser, ArgGroup};
 
 use crate::config::error::ConfigError;
-use crate::config::http_server::{HttpServer, ServersFile};
+use crate::config::http_server::{HttpServer, ServersFile, check_document_versions};
 use crate::config::service::{ServiceRef};
 
 // Why port 7589? oxidase -> 0x1da5e (121438, too large) -> 0x1da5 -> 7589 (bingo!)
@@ -65,13 +65,15 @@ pub fn load_http_servers(args: &Args) -> Result<Vec<HttpServer>, ConfigError> {
 }
 
 fn load_from_config(path: &Path) -> Result<Vec<HttpServer>, ConfigError> {
+    let raw = fs::read_to_string(path)?;
+    check_document_versions(&serde_yaml::from_str(&raw)?)?;
+
     // single server
     if let Ok(svc) = HttpServer::load_from_file(path) {
         return Ok(vec![svc]);
     }
 
     // servers wrapper
-    let raw = fs::read_to_string(path)?;
     if let Ok(wrapper) = serde_yaml::from_str::<ServersFile>(&raw) {
         let base = path.parent().unwrap_or(Path::new("."));
         let mut servers = Vec::new();
@@ -99,10 +101,14 @@ fn load_from_config(path: &Path) -> Result<Vec<HttpServer>, ConfigError> {
 fn load_from_service_file(path: &Path, bind: &str) -> Result<Vec<HttpServer>, ConfigError> {
     let svc_ref = ServiceRef::Import { import: path.to_path_buf() };
     let hs = HttpServer {
+        version: Default::default(),
         name: None,
         bind: bind.to_string(),
         tls: None,
         service: svc_ref,
+        request_body: Default::default(),
+        buffers: Default::default(),
+        trusted_proxies: Vec::new(),
         base_dir: path.parent().map(|p| p.to_path_buf()),
     };
     hs.validate()?;
@@ -112,10 +118,14 @@ fn load_from_service_file(path: &Path, bind: &str) -> Result<Vec<HttpServer>, Co
 fn load_from_inline(data: &str, bind: &str) -> Result<Vec<HttpServer>, ConfigError> {
     let svc_ref: ServiceRef = serde_yaml::from_str(data)?;
     let hs = HttpServer {
+        version: Default::default(),
         name: None,
         bind: bind.to_string(),
         tls: None,
         service: svc_ref,
+        request_body: Default::default(),
+        buffers: Default::default(),
+        trusted_proxies: Vec::new(),
         base_dir: Some(std::env::current_dir().unwrap_or_default()),
     };
     hs.validate()?;
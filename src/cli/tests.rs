@@ -93,3 +93,140 @@ source_dir: /tmp
     assert_eq!(servers.len(), 1);
     assert_eq!(servers[0].bind, "127.0.0.1:12345");
 }
+
+#[test]
+fn rules_after_a_stopping_catch_all_are_rejected() {
+    let inline = r#"
+handler: router
+rules:
+  - ops:
+      - respond: { status: 200 }
+  - name: never
+    when: { path: /x }
+    ops:
+      - respond: { status: 404 }
+"#;
+    let args = Args {
+        config: None,
+        service_file: None,
+        service_inline: Some(inline.to_string()),
+        bind: "127.0.0.1:12345".into(),
+        pick: None,
+        validate_only: false,
+        watch: false,
+    };
+    let err = load_http_servers(&args).expect_err("should reject unreachable rule");
+    assert!(err.to_string().contains("rule never is unreachable"), "{err}");
+}
+
+#[test]
+fn catch_all_reachability_follows_priority_order() {
+    let yaml = |priority: i32| format!(r#"
+handler: router
+rules:
+  - name: fallback
+    ops:
+      - respond: {{ status: 200 }}
+  - name: special
+    priority: {priority}
+    when: {{ path: /x }}
+    ops:
+      - respond: {{ status: 404 }}
+"#);
+    assert!(load_inline(&yaml(1)).is_ok());
+    let err = load_inline(&yaml(0)).expect_err("should reject unreachable rule");
+    assert!(err.to_string().contains("rule special is unreachable"), "{err}");
+}
+
+fn load_inline(inline: &str) -> Result<Vec<crate::config::http_server::HttpServer>, crate::config::error::ConfigError> {
+    load_http_servers(&Args {
+        config: None,
+        service_file: None,
+        service_inline: Some(inline.to_string()),
+        bind: "127.0.0.1:12345".into(),
+        pick: None,
+        validate_only: false,
+        watch: false,
+    })
+}
+
+#[test]
+fn rule_without_ops_that_continues_is_rejected() {
+    let err = load_inline(r#"
+handler: router
+rules:
+  - name: noop
+    on_match: continue
+    ops: []
+  - ops:
+      - respond: { status: 200 }
+"#).expect_err("should reject no-op rule");
+    assert!(err.to_string().contains("rule noop has no effect"), "{err}");
+}
+
+#[test]
+fn router_that_can_never_respond_is_rejected() {
+    let err = load_inline(r#"
+handler: router
+rules:
+  - on_match: continue
+    ops:
+      - header_set: { x-a: "1" }
+"#).expect_err("should reject router without a response");
+    assert!(err.to_string().contains("can never respond"), "{err}");
+
+    // a response inside a branch, or a `next`, is enough
+    load_inline(r#"
+handler: router
+rules:
+  - ops:
+      - branch:
+          if: { var: path, is: /x }
+          then:
+            - respond: { status: 200 }
+"#).expect("branch response is accepted");
+    load_inline(r#"
+handler: router
+rules:
+  - on_match: continue
+    ops:
+      - header_set: { x-a: "1" }
+next:
+  handler: static
+  source_dir: /tmp
+  file_index: index.html
+"#).expect("next is accepted");
+}
+
+fn load_config_text(yaml: &str) -> Result<Vec<crate::config::http_server::HttpServer>, crate::config::error::ConfigError> {
+    let dir = crate::testing::temp_dir(&[("oxidase.yaml", yaml)]);
+    load_http_servers(&Args {
+        config: Some(dir.join("oxidase.yaml")),
+        service_file: None,
+        service_inline: None,
+        bind: "0.0.0.0:0".into(),
+        pick: None,
+        validate_only: false,
+        watch: false,
+    })
+}
+
+#[test]
+fn config_version_is_checked_before_parsing() {
+    let server = |version: &str| format!("{version}bind: \"127.0.0.1:0\"\nservice:\n  handler: static\n  source_dir: \"/tmp\"\n");
+
+    let servers = load_config_text(&server("version: 1\n")).expect("version 1 loads");
+    assert_eq!(servers[0].version.0, "1");
+    assert!(load_config_text(&server("version: \"1.3\"\n")).is_ok());
+
+    let missing = load_config_text(&server("")).expect("missing version loads");
+    assert_eq!(missing[0].version, crate::config::http_server::ConfigVersion::default());
+
+    // a future schema is named as such even when its fields no longer parse
+    let future = format!("{}  renamed_in_v2: true\nlisten: {{ port: 80 }}\n", server("version: 2\n"));
+    let err = load_config_text(&future).unwrap_err().to_string();
+    assert!(err.contains("unsupported config version `2`"), "{err}");
+    let err = load_config_text("servers:\n  - version: 3\n    bind: x\n").unwrap_err().to_string();
+    assert!(err.contains("unsupported config version `3`"), "{err}");
+    assert!(load_config_text(&server("version: one\n")).is_err());
+}
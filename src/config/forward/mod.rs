@@ -6,17 +6,66 @@ use super::http_version::{HttpVersion, default_http_version};
 use super::url_scheme::Scheme;
 
 fn default_true() -> bool { true }
+fn default_via_pseudonym() -> String { "oxidase".into() }
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub struct ForwardService {
     pub target: ForwardTarget,
+    /// Tried in order after `target` when an attempt fails or hits `retry_statuses`.
+    #[serde(default)]
+    pub failover_targets: Vec<ForwardTarget>,
+    #[serde(default)]
+    pub retry_statuses: Vec<u16>,
     #[serde(default)]
     pub pass_host: PassHost,
     #[serde(default = "default_true")]
     pub x_forwarded: bool,
+    #[serde(default)]
+    pub rewrite_redirects: bool,
+    /// Point the `Domain` of upstream `Set-Cookie` headers at the host the client used.
+    #[serde(default)]
+    pub rewrite_set_cookie: bool,
+    /// With `rewrite_set_cookie`, also strip `target.path_prefix` from their `Path`.
+    #[serde(default)]
+    pub rewrite_set_cookie_path: bool,
+    /// Append `Via: <version> <via_pseudonym>` to forwarded requests and responses.
+    #[serde(default = "default_true")]
+    pub via: bool,
+    #[serde(default = "default_via_pseudonym")]
+    pub via_pseudonym: String,
+    #[serde(default)]
+    pub auth: Option<Box<ForwardAuth>>,
+    /// Header names (or globs such as `x-internal-*`) removed before the request goes upstream.
+    #[serde(default)]
+    pub strip_request_headers: Vec<String>,
+    /// Header names (or globs) removed from the upstream response before it reaches the client.
+    #[serde(default)]
+    pub strip_response_headers: Vec<String>,
     #[serde(default, flatten)]
     pub timeouts: Timeouts,
+    #[serde(default, flatten)]
+    pub pool: PoolSettings,
+    /// Cap on requests in flight through this service, response bodies included.
+    #[serde(default)]
+    pub max_concurrent: Option<usize>,
+    /// Wait for a free slot when `max_concurrent` is reached instead of answering 503.
+    #[serde(default)]
+    pub queue_when_full: bool,
+    /// Largest upstream response body passed on; bigger ones become a 502.
+    /// Setting it buffers each response instead of streaming it.
+    #[serde(default)]
+    pub max_response_bytes: Option<usize>,
+    /// Answer 503 until `target` first answers a probe.
+    #[serde(default)]
+    pub wait_for_ready: Option<ReadinessProbe>,
+    /// `http://` (CONNECT) or `socks5://` proxy for upstream connections, with
+    /// optional `user:pass@` credentials; may use `${env.NAME}`.
+    #[serde(default)]
+    pub upstream_proxy: Option<String>,
+    /// Protocol spoken to the upstream.
+    #[serde(default)]
+    pub upstream_http_version: UpstreamHttpVersion,
     #[serde(default = "default_http_version")]
     pub http_version: HttpVersion,
     #[serde(default)]
@@ -32,6 +81,24 @@ pub struct ForwardTarget {
     pub path_prefix: String,
 }
 
+/// Fixed `Authorization` header sent upstream. Values may reference
+/// environment variables as `${env.NAME}`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ForwardAuth {
+    #[serde(flatten)]
+    pub scheme: AuthScheme,
+    /// Replace an `Authorization` header sent by the client.
+    #[serde(default)]
+    pub r#override: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthScheme {
+    Bearer(String),
+    Basic { username: String, password: String },
+}
+
 #[derive(Debug, Deserialize, Clone)]
 #[serde(untagged)]
 pub enum PassHost { Mode(PassHostMode), Custom { custom: String } }
@@ -44,9 +111,50 @@ impl Default for PassHost {
     fn default() -> Self { PassHost::Mode(PassHostMode::Incoming) }
 }
 
+/// `http2` uses prior knowledge (h2c) on plain connections; `auto` lets TLS ALPN
+/// pick and otherwise speaks HTTP/1.1.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamHttpVersion {
+    Http1,
+    Http2,
+    #[default]
+    Auto,
+}
+
+fn default_probe_path() -> String { "/".into() }
+fn default_probe_interval_ms() -> u64 { 1000 }
+
+/// `GET path` on the target every `interval_ms` until it answers with a status below 500.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReadinessProbe {
+    #[serde(default = "default_probe_path")]
+    pub path: String,
+    #[serde(default = "default_probe_interval_ms")]
+    pub interval_ms: u64,
+}
+
+/// Tuning for the pooled upstream connections; unset fields keep hyper's defaults.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct PoolSettings {
+    /// Close pooled connections left idle this long.
+    pub pool_idle_timeout_ms: Option<u64>,
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Re-resolve the target host name once a lookup is this old; unset resolves
+    /// on every new connection.
+    pub dns_refresh_ms: Option<u64>,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Timeouts {
+    /// Limit on establishing one upstream TCP connection.
     pub connect_ms: Option<u32>,
+    /// Head start for the preferred address family before the other is tried
+    /// in parallel (happy eyeballs); defaults to 300 ms.
+    pub happy_eyeballs_ms: Option<u32>,
+    /// Abort a response once the upstream sends nothing for this long in the
+    /// middle of its body.
+    pub idle_timeout_ms: Option<u32>,
     pub read_ms: Option<u32>,
     pub write_ms: Option<u32>,
 }
@@ -55,6 +163,8 @@ impl Default for Timeouts {
     fn default() -> Self {
         Timeouts {
             connect_ms: None,
+            happy_eyeballs_ms: None,
+            idle_timeout_ms: None,
             read_ms: None,
             write_ms: None,
         }
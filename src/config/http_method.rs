@@ -1,5 +1,58 @@
 use serde::Deserialize;
 
+/// Request method; anything non-standard is kept as `Other`. Request methods are
+/// case-sensitive, but config names for the standard ones are accepted in any case.
 #[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
-#[serde(rename_all = "UPPERCASE")]
-pub enum HttpMethod { Get, Post, Put, Patch, Delete, Head, Options }
+#[serde(try_from = "String")]
+pub enum HttpMethod { Get, Post, Put, Patch, Delete, Head, Options, Connect, Trace, Other(String) }
+
+impl HttpMethod {
+    pub fn as_str(&self) -> &str {
+        match self {
+            HttpMethod::Get => "GET",
+            HttpMethod::Post => "POST",
+            HttpMethod::Put => "PUT",
+            HttpMethod::Patch => "PATCH",
+            HttpMethod::Delete => "DELETE",
+            HttpMethod::Head => "HEAD",
+            HttpMethod::Options => "OPTIONS",
+            HttpMethod::Connect => "CONNECT",
+            HttpMethod::Trace => "TRACE",
+            HttpMethod::Other(m) => m,
+        }
+    }
+}
+
+impl From<&str> for HttpMethod {
+    fn from(value: &str) -> Self {
+        match value {
+            "GET" => HttpMethod::Get,
+            "POST" => HttpMethod::Post,
+            "PUT" => HttpMethod::Put,
+            "PATCH" => HttpMethod::Patch,
+            "DELETE" => HttpMethod::Delete,
+            "HEAD" => HttpMethod::Head,
+            "OPTIONS" => HttpMethod::Options,
+            "CONNECT" => HttpMethod::Connect,
+            "TRACE" => HttpMethod::Trace,
+            other => HttpMethod::Other(other.to_string()),
+        }
+    }
+}
+
+/// Config form: `get` means `GET`, and names that are not RFC 9110 tokens are rejected.
+impl TryFrom<String> for HttpMethod {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        let is_token = !value.is_empty()
+            && value.bytes().all(|b| b.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?={}".contains(&b));
+        if !is_token {
+            return Err(format!("`{value}` is not a valid HTTP method"));
+        }
+        Ok(match HttpMethod::from(value.to_ascii_uppercase().as_str()) {
+            HttpMethod::Other(_) => HttpMethod::Other(value),
+            known => known,
+        })
+    }
+}
@@ -3,23 +3,154 @@ use serde::Deserialize;
 
 use super::error::ConfigError;
 use std::collections::HashSet;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 
+use super::http_version::AlpnProto;
 use super::service::{validate_service, resolve_service_ref, ServiceRef};
+use crate::util::cidr::Cidr;
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub struct HttpServer {
+    /// Config schema version; omitted means the current one.
+    #[serde(default)]
+    pub version: ConfigVersion,
     #[serde(default)]
     pub name: Option<String>,
     pub bind: String, // listened host + port
     #[serde(default)]
     pub tls: Option<super::tls::TlsConfig>,
     pub service: ServiceRef,
+    #[serde(default)]
+    pub request_body: RequestBodyPolicy,
+    #[serde(default)]
+    pub buffers: BufferSettings,
+    /// Peers (CIDRs or addresses) whose `X-Forwarded-For` is believed when working
+    /// out `remote.ip`. Empty, the default, trusts no one.
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
     #[serde(skip)]
     pub base_dir: Option<PathBuf>,
 }
 
+/// Major version of the config schema this build understands.
+pub const CONFIG_VERSION: u32 = 1;
+
+/// A config's `version`, written `1` or `"1.0"`. Only the major part is checked,
+/// so minor revisions stay loadable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigVersion(pub String);
+
+impl Default for ConfigVersion {
+    fn default() -> Self { ConfigVersion(CONFIG_VERSION.to_string()) }
+}
+
+impl<'de> Deserialize<'de> for ConfigVersion {
+    fn deserialize<D: serde::Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        match serde_yaml::Value::deserialize(de)? {
+            serde_yaml::Value::Number(n) => Ok(ConfigVersion(n.to_string())),
+            serde_yaml::Value::String(s) => Ok(ConfigVersion(s)),
+            _ => Err(serde::de::Error::custom("`version` must be a number such as 1 or a string such as \"1.0\"")),
+        }
+    }
+}
+
+impl ConfigVersion {
+    pub fn check(&self) -> Result<(), ConfigError> {
+        let major = self.0.trim().split('.').next().and_then(|m| m.parse::<u32>().ok());
+        match major {
+            Some(CONFIG_VERSION) => Ok(()),
+            Some(_) => Err(ConfigError::Invalid(format!(
+                "unsupported config version `{}`: this build of oxidase reads version {CONFIG_VERSION} configs; \
+                 upgrade oxidase or migrate the config",
+                self.0,
+            ))),
+            None => Err(ConfigError::Invalid(format!("invalid config version `{}`", self.0))),
+        }
+    }
+}
+
+/// Check the `version` of every server in a config document before it is
+/// deserialized, so a config written for another schema is reported as such
+/// rather than as whichever field no longer parses.
+pub fn check_document_versions(doc: &serde_yaml::Value) -> Result<(), ConfigError> {
+    let servers: Vec<&serde_yaml::Value> = match doc {
+        serde_yaml::Value::Sequence(list) => list.iter().collect(),
+        serde_yaml::Value::Mapping(m) => match m.get("servers") {
+            Some(serde_yaml::Value::Sequence(list)) => list.iter().collect(),
+            _ => vec![doc],
+        },
+        _ => Vec::new(),
+    };
+    for server in servers {
+        if let Some(v) = server.get("version") {
+            ConfigVersion::deserialize(v.clone()).map_err(|e| ConfigError::Invalid(e.to_string()))?.check()?;
+        }
+    }
+    Ok(())
+}
+
+/// How much of a request body is buffered before dispatch, and what happens past that.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct RequestBodyPolicy {
+    #[serde(default = "default_max_buffer_bytes")]
+    pub max_buffer_bytes: usize,
+    #[serde(default)]
+    pub over_limit: OverLimit,
+}
+
+fn default_max_buffer_bytes() -> usize { 1024 * 1024 }
+
+impl Default for RequestBodyPolicy {
+    fn default() -> Self {
+        RequestBodyPolicy {
+            max_buffer_bytes: default_max_buffer_bytes(),
+            over_limit: OverLimit::default(),
+        }
+    }
+}
+
+/// What to do with a body larger than `max_buffer_bytes`.
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OverLimit {
+    /// Pass it through unbuffered; body-based features see no body.
+    #[default]
+    Stream,
+    /// Answer 413 Payload Too Large.
+    Reject,
+}
+
+/// Socket and response-body buffer sizes. Unset socket sizes keep the OS defaults
+/// (typically a few hundred KiB on Linux, auto-tuned).
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub struct BufferSettings {
+    /// `SO_SNDBUF` of accepted connections.
+    #[serde(default)]
+    pub send_buffer_bytes: Option<usize>,
+    /// `SO_RCVBUF` of accepted connections.
+    #[serde(default)]
+    pub recv_buffer_bytes: Option<usize>,
+    /// Largest frame an in-memory response body (such as a static file) is split into.
+    #[serde(default = "default_body_chunk_bytes")]
+    pub body_chunk_bytes: usize,
+}
+
+pub fn default_body_chunk_bytes() -> usize { 64 * 1024 }
+
+impl Default for BufferSettings {
+    fn default() -> Self {
+        BufferSettings {
+            send_buffer_bytes: None,
+            recv_buffer_bytes: None,
+            body_chunk_bytes: default_body_chunk_bytes(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct ServersFile {
     pub servers: Vec<HttpServer>,
@@ -29,30 +160,61 @@ impl HttpServer {
     pub fn load_from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
         let file_path = path.as_ref();
         let file = File::open(file_path)?;
-        let mut cfg: HttpServer = serde_yaml::from_reader(file)?;
+        let doc: serde_yaml::Value = serde_yaml::from_reader(file)?;
+        check_document_versions(&doc)?;
+        let mut cfg: HttpServer = serde_yaml::from_value(doc)?;
         cfg.base_dir = file_path.parent().map(|p| p.to_path_buf());
         cfg.validate()?;
         Ok(cfg)
     }
 
     pub fn validate(&self) -> Result<(), ConfigError> {
+        self.version.check()?;
         if self.bind.trim().is_empty() {
             return Err(ConfigError::Invalid("`bind` cannot be empty".into()));
         }
+        parse_bind_addr(&self.bind)?;
         if let Some(name) = &self.name {
             if name.trim().is_empty() {
                 return Err(ConfigError::Invalid("`name` cannot be empty if provided".into()));
             }
         }
-        if let Some(tls) = &self.tls {
-            if tls.enabled && (tls.cert_file.exists() || tls.key_file.exists()) {
-                return Err(ConfigError::Invalid("`tls.enabled=true` requires `cert_file` & `key_file`".into()));
-            }
+        if self.buffers.body_chunk_bytes == 0 {
+            return Err(ConfigError::Invalid("`buffers.body_chunk_bytes` must be at least 1".into()));
+        }
+        for cidr in &self.trusted_proxies {
+            Cidr::parse(cidr).map_err(|e| ConfigError::Invalid(format!("`trusted_proxies`: {e}")))?;
         }
         let base = self.base_dir.as_deref().unwrap_or(Path::new("."));
+        if let Some(tls) = &self.tls
+            && tls.enabled
+        {
+            let ca_file = tls.client_auth.as_ref().map(|c| ("client_auth.ca_file", &c.ca_file));
+            for (key, file) in [("cert_file", &tls.cert_file), ("key_file", &tls.key_file)].into_iter().chain(ca_file) {
+                if !base.join(file).is_file() {
+                    return Err(ConfigError::Invalid(format!("`tls.{key}` `{}` does not exist", file.display())));
+                }
+            }
+            // connections are served as HTTP/1.1 only, so h2 must not be negotiated
+            if tls.handshake_timeout_ms == 0 {
+                return Err(ConfigError::Invalid("`tls.handshake_timeout_ms` must be greater than 0".into()));
+            }
+            if tls.alpn.iter().any(|p| matches!(p, AlpnProto::Http2)) {
+                return Err(ConfigError::Invalid("`tls.alpn`: only `http/1.1` is supported".into()));
+            }
+        }
         let mut stack = HashSet::new();
         let resolved = resolve_service_ref(&self.service, base, &mut stack)?;
         validate_service(&resolved, base)?;
         Ok(())
     }
 }
+
+/// Parse a `bind` value as `ip:port`; IPv6 addresses must be bracketed (`[::1]:8080`).
+pub fn parse_bind_addr(bind: &str) -> Result<SocketAddr, ConfigError> {
+    bind.trim().parse().map_err(|_| {
+        ConfigError::Invalid(format!(
+            "`bind` must be `ip:port` with IPv6 in brackets (e.g. `0.0.0.0:8080` or `[::]:8080`), got `{bind}`"
+        ))
+    })
+}
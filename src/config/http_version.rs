@@ -21,3 +21,13 @@ pub enum AlpnProto {
     #[serde(rename = "http/2", alias = "h2")]
     Http2,
 }
+
+impl AlpnProto {
+    /// Protocol ID as sent in the TLS ALPN extension.
+    pub fn id(self) -> &'static [u8] {
+        match self {
+            AlpnProto::Http1_1 => b"http/1.1",
+            AlpnProto::Http2 => b"h2",
+        }
+    }
+}
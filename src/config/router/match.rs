@@ -6,6 +6,11 @@ use super::super::http_method::HttpMethod;
 pub struct RouterMatch {
     pub host: Option<String>,
     pub path: Option<String>,
+    /// Matched against the percent-decoded path (`%2F` stays encoded); `path` sees it raw.
+    pub path_decoded: Option<String>,
+    /// Match `path` and `path_decoded` without regard to letter case.
+    #[serde(default)]
+    pub path_case_insensitive: bool,
     #[serde(default)]
     pub methods: Vec<HttpMethod>,
     #[serde(default)]
@@ -17,28 +22,54 @@ pub struct RouterMatch {
     pub scheme: Option<Scheme>,
 }
 
+impl RouterMatch {
+    /// True when no condition is set, so every request matches.
+    pub fn is_catch_all(&self) -> bool {
+        self.host.is_none()
+            && self.path.is_none()
+            && self.path_decoded.is_none()
+            && self.methods.is_empty()
+            && self.headers.is_empty()
+            && self.queries.is_empty()
+            && self.cookies.is_empty()
+            && self.scheme.is_none()
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct HeaderCond {
     pub name: String, // case-insensitive
-    pub pattern: String,
+    #[serde(default)]
+    pub pattern: Option<String>,
     #[serde(default)]
     pub not: bool,
+    /// Match on presence alone; takes no `pattern` (`not` then requires presence).
+    #[serde(default)]
+    pub absent: bool,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct QueryCond {
     pub key: String, // case-sensitive
-    pub pattern: String,
+    #[serde(default)]
+    pub pattern: Option<String>,
     #[serde(default)]
     pub not: bool,
+    /// Match on presence alone; takes no `pattern` (`not` then requires presence).
+    #[serde(default)]
+    pub absent: bool,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct CookieCond {
     pub name: String, // case-sensitive
-    pub pattern: String,
+    #[serde(default)]
+    pub pattern: Option<String>,
     #[serde(default)]
     pub not: bool,
+    /// Match on presence alone; takes no `pattern` (`not` then requires presence).
+    #[serde(default)]
+    pub absent: bool,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -14,16 +14,50 @@ pub struct RouterService {
     pub next: Option<Box<ServiceRef>>,
     #[serde(default)]
     pub max_steps: Option<u32>,
+    /// Allow fault-injection ops such as `delay`; never enable in production.
+    #[serde(default)]
+    pub enable_testing_ops: bool,
+    /// Write `ctx.host` back to the `Host` header when handing off to another service.
+    #[serde(default = "default_true")]
+    pub rewrite_host: bool,
+    /// Answer 500 instead of a redirect whose `Location` is the request's own path.
+    #[serde(default)]
+    pub redirect_loop_guard: bool,
+    /// Percent-decode cookie values; turn off for raw or base64 cookies.
+    #[serde(default = "default_true")]
+    pub decode_cookies: bool,
+    /// Longest string, in bytes, a single template may expand to; longer expansions fail the request.
+    #[serde(default = "default_max_template_len")]
+    pub max_template_len: usize,
+    /// Expanded in place of any template variable that is not set, instead of an empty string.
+    #[serde(default)]
+    pub template_missing_default: Option<String>,
 }
 
+fn default_true() -> bool { true }
+
+fn default_max_template_len() -> usize { crate::template::DEFAULT_MAX_EXPANSION }
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct RouterRule {
+    #[serde(default)]
+    pub name: Option<String>,
     #[serde(default)]
     pub when: Option<RouterMatch>,
     #[serde(default)]
     pub ops: Vec<RouterOp>,
     #[serde(default)]
     pub on_match: OnMatch,
+    /// Higher priorities are evaluated first; equal priorities keep file order.
+    #[serde(default)]
+    pub priority: i32,
+}
+
+/// Indices of `rules` in evaluation order: descending `priority`, stable for ties.
+pub fn evaluation_order(rules: &[RouterRule]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..rules.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(rules[i].priority));
+    order
 }
 
 #[derive(Debug, Deserialize, Clone, Default)]
@@ -1,8 +1,10 @@
 use serde::{Deserialize, Deserializer};
 use std::collections::BTreeMap;
+use std::time::Duration;
 
 use super::super::url_scheme::Scheme;
 use super::super::service::ServiceRef;
+use crate::util::jwt::JwtAlg;
 
 #[derive(Debug, Clone)]
 pub enum RouterOp {
@@ -12,27 +14,171 @@ pub enum RouterOp {
     SetHost(String),
     SetPort(u16),
     SetPath(String),
+    RewritePath { from: String, to: String },
 
     HeaderSet(BTreeMap<String, String>),
     HeaderAdd(BTreeMap<String, String>),
+    HeaderSetIfAbsent(BTreeMap<String, String>),
     HeaderDelete(Vec<String>),
+    HeaderDedup(Vec<String>),
     HeaderClear,
 
     QuerySet(BTreeMap<String, String>),
     QueryAdd(BTreeMap<String, String>),
     QueryDelete(Vec<String>),
     QueryClear,
+    SortQuery,
+    DecodePath,
+
+    CopyValue { from: String, to: String },
+
+    RequireUserAgent { pattern: String, deny: bool },
+    /// Answer 415 unless the request's media type, parameters aside, is one of these.
+    RequireContentType(Vec<String>),
+    VerifyJwt { key: String, alg: JwtAlg, claims: Vec<String>, leeway_secs: u64 },
+
+    Delay(Duration),
+    InjectError { status: u16, probability: f64 },
 
     InternalRewrite,
-    Redirect { status: RedirectCode, location: String },
-    Respond { status: u16, body: Option<String>, headers: BTreeMap<String, String> },
+    Redirect { status: RedirectCode, location: String, preserve_query: bool },
+    Respond {
+        /// Required unless `passthrough` is set.
+        status: Option<u16>,
+        body: Option<String>,
+        embedded: Option<String>,
+        headers: BTreeMap<String, String>,
+        auto_content_type: bool,
+        stream: Option<RespondStream>,
+        /// Relay the status, headers and body bound by the last `subrequest`.
+        passthrough: bool,
+    },
+    Metrics,
+    ServerTiming,
+    /// Add a `Link: <href>; rel=preload; as=...` header to the final response.
+    Preload(Vec<PreloadLink>),
+    /// Skip the remaining ops and rules and hand the request to the router's `next`.
+    GotoNext,
+    /// Bind a fresh base64 random value to the capture `name`, e.g. for a CSP nonce.
+    GenNonce { name: String },
+    /// Log the request's method, URI, headers and, optionally, the start of its body.
+    DebugLog { level: LogLevel, include_body: bool },
+    /// Add `Set-Cookie: <name>=<value>.<hmac>` to the response; `key` may use `${env.NAME}`.
+    SignCookie { name: String, value_template: String, key: String, attributes: String },
+    /// Bind `signed.<name>` to the value of cookie `name` when its signature checks out.
+    VerifyCookie { name: String, key: String },
+
+    /// Fetch from a service and bind `sub.status`, `sub.body` and `sub.header.<name>` as captures.
+    Subrequest(Box<ServiceRef>),
 
     Use(Box<ServiceRef>),
 }
 
+impl RouterOp {
+    /// Name of this op if it only exists for fault-injection testing.
+    pub fn testing_only_name(&self) -> Option<&'static str> {
+        match self {
+            RouterOp::Delay(_) => Some("delay"),
+            RouterOp::InjectError { .. } => Some("inject_error"),
+            _ => None,
+        }
+    }
+
+    /// Whether running this op can end routing with a response, directly or in a branch.
+    pub fn can_respond(&self) -> bool {
+        match self {
+            RouterOp::Branch(b) => b.then.iter().chain(&b.r#else).any(RouterOp::can_respond),
+            RouterOp::Respond { .. }
+            | RouterOp::Redirect { .. }
+            | RouterOp::Use(_)
+            | RouterOp::Metrics
+            | RouterOp::GotoNext
+            | RouterOp::InjectError { .. }
+            | RouterOp::RequireUserAgent { .. }
+            | RouterOp::RequireContentType(_)
+            | RouterOp::VerifyJwt { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+fn default_inject_status() -> u16 { 500 }
+fn default_nonce_name() -> String { "nonce".into() }
+fn default_cookie_attributes() -> String { "Path=/; HttpOnly".into() }
+
 #[derive(Debug, Deserialize, Clone, Copy)]
 pub enum RedirectCode { _301=301, _302=302, _307=307, _308=308 }
 
+/// Keep a `respond` body open, appending one expanded `line_template` and a newline
+/// every `interval_ms`, `count` times or until the client goes away.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RespondStream {
+    pub interval_ms: u64,
+    pub line_template: String,
+    #[serde(default)]
+    pub count: Option<u64>,
+}
+
+/// One resource of a `preload` op.
+#[derive(Debug, Deserialize, Clone)]
+pub struct PreloadLink {
+    /// Template for the URL placed between `<` and `>`.
+    pub href: String,
+    pub r#as: PreloadAs,
+    /// Emit `crossorigin`, which fonts and cross-origin fetches need to reuse the preload.
+    #[serde(default)]
+    pub crossorigin: bool,
+}
+
+/// Destinations a `Link: rel=preload` may declare with `as`.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PreloadAs {
+    Audio,
+    Document,
+    Embed,
+    Fetch,
+    Font,
+    Image,
+    Object,
+    Script,
+    Style,
+    Track,
+    Video,
+    Worker,
+}
+
+impl PreloadAs {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PreloadAs::Audio => "audio",
+            PreloadAs::Document => "document",
+            PreloadAs::Embed => "embed",
+            PreloadAs::Fetch => "fetch",
+            PreloadAs::Font => "font",
+            PreloadAs::Image => "image",
+            PreloadAs::Object => "object",
+            PreloadAs::Script => "script",
+            PreloadAs::Style => "style",
+            PreloadAs::Track => "track",
+            PreloadAs::Video => "video",
+            PreloadAs::Worker => "worker",
+        }
+    }
+}
+
+/// `tracing` level a `debug_log` op emits at.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Trace,
+    #[default]
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct BranchOp {
     pub r#if: CondNode,
@@ -50,24 +196,77 @@ enum RouterOpFull {
     SetHost(String),
     SetPort(u16),
     SetPath(String),
+    RewritePath { from: String, to: String },
 
     HeaderSet(BTreeMap<String, String>),
     HeaderAdd(BTreeMap<String, String>),
+    HeaderSetIfAbsent(BTreeMap<String, String>),
     HeaderDelete(Vec<String>),
+    HeaderDedup(Vec<String>),
     HeaderClear,
 
     QuerySet(BTreeMap<String, String>),
     QueryAdd(BTreeMap<String, String>),
     QueryDelete(Vec<String>),
     QueryClear,
+    SortQuery,
+    DecodePath,
+
+    CopyValue { from: String, to: String },
+
+    RequireUserAgent {
+        pattern: String,
+        #[serde(default)] deny: bool,
+    },
+    RequireContentType(Vec<String>),
+    VerifyJwt {
+        key: String,
+        #[serde(default)] alg: JwtAlg,
+        #[serde(default)] claims: Vec<String>,
+        #[serde(default)] leeway_secs: u64,
+    },
+
+    Delay { ms: u64 },
+    InjectError {
+        #[serde(default = "default_inject_status")] status: u16,
+        probability: f64,
+    },
 
     InternalRewrite,
-    Redirect { status: RedirectCode, location: String },
+    Redirect {
+        status: RedirectCode,
+        location: String,
+        #[serde(default)] preserve_query: bool,
+    },
     Respond {
-        status: u16,
+        #[serde(default)] status: Option<u16>,
         #[serde(default)] body: Option<String>,
+        #[serde(default)] embedded: Option<String>,
         #[serde(default)] headers: BTreeMap<String, String>,
+        #[serde(default)] auto_content_type: bool,
+        #[serde(default)] stream: Option<RespondStream>,
+        #[serde(default)] passthrough: bool,
     },
+    Metrics,
+    ServerTiming,
+    Preload(Vec<PreloadLink>),
+    GotoNext,
+    GenNonce {
+        #[serde(default = "default_nonce_name")] name: String,
+    },
+    DebugLog {
+        #[serde(default)] level: LogLevel,
+        #[serde(default)] include_body: bool,
+    },
+    SignCookie {
+        name: String,
+        value_template: String,
+        key: String,
+        #[serde(default = "default_cookie_attributes")] attributes: String,
+    },
+    VerifyCookie { name: String, key: String },
+
+    Subrequest(Box<ServiceRef>),
 
     Use(Box<ServiceRef>),
 }
@@ -77,7 +276,14 @@ enum RouterOpFull {
 enum RouterOpUnitKeyword {
     HeaderClear,
     QueryClear,
+    SortQuery,
+    DecodePath,
     InternalRewrite,
+    Metrics,
+    ServerTiming,
+    GotoNext,
+    GenNonce,
+    DebugLog,
 }
 
 #[derive(Deserialize)]
@@ -93,7 +299,15 @@ impl<'de> Deserialize<'de> for RouterOp {
             RouterOpDe::Unit(u) => match u {
                 RouterOpUnitKeyword::HeaderClear => RouterOp::HeaderClear,
                 RouterOpUnitKeyword::QueryClear => RouterOp::QueryClear,
+                RouterOpUnitKeyword::SortQuery => RouterOp::SortQuery,
+                RouterOpUnitKeyword::DecodePath => RouterOp::DecodePath,
                 RouterOpUnitKeyword::InternalRewrite => RouterOp::InternalRewrite,
+                RouterOpUnitKeyword::Metrics => RouterOp::Metrics,
+                RouterOpUnitKeyword::ServerTiming => RouterOp::ServerTiming,
+                RouterOpUnitKeyword::GotoNext => RouterOp::GotoNext,
+                RouterOpUnitKeyword::GenNonce => RouterOp::GenNonce { name: default_nonce_name() },
+                RouterOpUnitKeyword::DebugLog =>
+                    RouterOp::DebugLog { level: LogLevel::default(), include_body: false },
             },
             RouterOpDe::Full(f) => match f {
                 RouterOpFull::Branch(x) => RouterOp::Branch(x),
@@ -101,19 +315,44 @@ impl<'de> Deserialize<'de> for RouterOp {
                 RouterOpFull::SetHost(x) => RouterOp::SetHost(x),
                 RouterOpFull::SetPort(x) => RouterOp::SetPort(x),
                 RouterOpFull::SetPath(x) => RouterOp::SetPath(x),
+                RouterOpFull::RewritePath { from, to } => RouterOp::RewritePath { from, to },
                 RouterOpFull::HeaderSet(x) => RouterOp::HeaderSet(x),
                 RouterOpFull::HeaderAdd(x) => RouterOp::HeaderAdd(x),
+                RouterOpFull::HeaderSetIfAbsent(x) => RouterOp::HeaderSetIfAbsent(x),
                 RouterOpFull::QuerySet(x) => RouterOp::QuerySet(x),
                 RouterOpFull::QueryAdd(x) => RouterOp::QueryAdd(x),
                 RouterOpFull::HeaderDelete(x) => RouterOp::HeaderDelete(x),
+                RouterOpFull::HeaderDedup(x) => RouterOp::HeaderDedup(x),
                 RouterOpFull::QueryDelete(x) => RouterOp::QueryDelete(x),
                 RouterOpFull::HeaderClear => RouterOp::HeaderClear,
                 RouterOpFull::QueryClear => RouterOp::QueryClear,
+                RouterOpFull::SortQuery => RouterOp::SortQuery,
+                RouterOpFull::DecodePath => RouterOp::DecodePath,
+                RouterOpFull::CopyValue { from, to } => RouterOp::CopyValue { from, to },
+                RouterOpFull::RequireUserAgent { pattern, deny } =>
+                    RouterOp::RequireUserAgent { pattern, deny },
+                RouterOpFull::RequireContentType(x) => RouterOp::RequireContentType(x),
+                RouterOpFull::VerifyJwt { key, alg, claims, leeway_secs } =>
+                    RouterOp::VerifyJwt { key, alg, claims, leeway_secs },
+                RouterOpFull::Delay { ms } => RouterOp::Delay(Duration::from_millis(ms)),
+                RouterOpFull::InjectError { status, probability } =>
+                    RouterOp::InjectError { status, probability },
                 RouterOpFull::InternalRewrite => RouterOp::InternalRewrite,
-                RouterOpFull::Redirect { status, location } =>
-                    RouterOp::Redirect { status, location },
-                RouterOpFull::Respond { status, body, headers } =>
-                    RouterOp::Respond { status, body, headers },
+                RouterOpFull::Redirect { status, location, preserve_query } =>
+                    RouterOp::Redirect { status, location, preserve_query },
+                RouterOpFull::Respond { status, body, embedded, headers, auto_content_type, stream, passthrough } =>
+                    RouterOp::Respond { status, body, embedded, headers, auto_content_type, stream, passthrough },
+                RouterOpFull::Metrics => RouterOp::Metrics,
+                RouterOpFull::ServerTiming => RouterOp::ServerTiming,
+                RouterOpFull::Preload(links) => RouterOp::Preload(links),
+                RouterOpFull::GotoNext => RouterOp::GotoNext,
+                RouterOpFull::GenNonce { name } => RouterOp::GenNonce { name },
+                RouterOpFull::DebugLog { level, include_body } =>
+                    RouterOp::DebugLog { level, include_body },
+                RouterOpFull::SignCookie { name, value_template, key, attributes } =>
+                    RouterOp::SignCookie { name, value_template, key, attributes },
+                RouterOpFull::VerifyCookie { name, key } => RouterOp::VerifyCookie { name, key },
+                RouterOpFull::Subrequest(svc) => RouterOp::Subrequest(svc),
                 RouterOpFull::Use(svc) => RouterOp::Use(svc),
             },
         })
@@ -144,10 +383,19 @@ pub enum BasicCond {
     Equals { is: serde_yaml::Value },
     In { r#in: Vec<serde_yaml::Value> },
     Present { present: bool },
+    /// Absent values count as empty; pair with `present` to tell the two apart.
+    Empty { #[serde(alias = "blank")] empty: bool },
     Pattern {
         pattern: String,
         #[serde(default)] ctx: Option<PatternCtxHint>,
     },
+    /// Numeric bounds; the value must parse as a number and satisfy every bound given.
+    Compare {
+        #[serde(default)] gt: Option<f64>,
+        #[serde(default)] gte: Option<f64>,
+        #[serde(default)] lt: Option<f64>,
+        #[serde(default)] lte: Option<f64>,
+    },
 }
 
 #[derive(Debug, Deserialize, Clone, Copy)]
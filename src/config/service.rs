@@ -4,7 +4,7 @@ use super::error::ConfigError;
 
 use super::{
     r#static::StaticService,
-    router::RouterService,
+    router::{OnMatch, RouterService, evaluation_order},
     forward::ForwardService,
 };
 use std::collections::HashSet;
@@ -16,7 +16,7 @@ use std::path::{Path, PathBuf};
 pub enum Service {
     Static(StaticService),
     Router(RouterService),
-    Forward(ForwardService),
+    Forward(Box<ForwardService>),
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -53,17 +53,65 @@ pub fn resolve_service_ref(
     }
 }
 
+/// Reject rules that follow a catch-all rule which always stops: they can never run.
+fn check_unreachable_rules(rt: &RouterService) -> Result<(), ConfigError> {
+    let label = |i: usize| rt.rules[i].name.clone().unwrap_or_else(|| format!("#{i}"));
+    let order = evaluation_order(&rt.rules);
+    let catch_all = order.iter().position(|&i| {
+        let r = &rt.rules[i];
+        matches!(r.on_match, OnMatch::Stop) && r.when.as_ref().is_none_or(|w| w.is_catch_all())
+    });
+    match catch_all {
+        Some(pos) if pos + 1 < order.len() => Err(ConfigError::Invalid(format!(
+            "router rule {} is unreachable: rule {} matches every request and stops",
+            label(order[pos + 1]),
+            label(order[pos]),
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Reject routers that can only ever answer 404: rules that do nothing, or no rule
+/// able to respond and no `next` to fall through to.
+fn check_degenerate_router(rt: &RouterService) -> Result<(), ConfigError> {
+    let label = |i: usize| rt.rules[i].name.clone().unwrap_or_else(|| format!("#{i}"));
+    if let Some(i) = rt.rules.iter().position(|r| r.ops.is_empty() && matches!(r.on_match, OnMatch::Continue)) {
+        return Err(ConfigError::Invalid(format!(
+            "router rule {} has no effect: it has no ops and continues to the next rule",
+            label(i),
+        )));
+    }
+    if rt.next.is_none() && !rt.rules.iter().flat_map(|r| &r.ops).any(|op| op.can_respond()) {
+        return Err(ConfigError::Invalid(
+            "router can never respond: no rule produces a response and there is no `next`".into(),
+        ));
+    }
+    Ok(())
+}
+
 pub fn validate_service(svc: &Service, base_dir: &Path) -> Result<(), ConfigError> {
     match svc {
         Service::Static(st) => {
             if st.source_dir.trim().is_empty() {
                 return Err(ConfigError::Invalid("`static.source_dir` cannot be empty".into()));
             }
+            if let Some(ct) = &st.default_content_type
+                && hyper::http::HeaderValue::from_str(ct).is_err()
+            {
+                return Err(ConfigError::Invalid("`static.default_content_type` is not a valid header value".into()));
+            }
+            if let Some(f) = &st.fallback {
+                let mut stack = HashSet::new();
+                let resolved = resolve_service_ref(f, base_dir, &mut stack)?;
+                validate_service(&resolved, base_dir)?;
+            }
         }
         Service::Router(rt) => {
             if rt.rules.is_empty() {
                 return Err(ConfigError::Invalid("`router.rules` cannot be empty".into()));
             }
+            check_unreachable_rules(rt)?;
+            check_degenerate_router(rt)?;
             if let Some(n) = &rt.next {
                 let mut stack = HashSet::new();
                 let resolved = resolve_service_ref(n, base_dir, &mut stack)?;
@@ -74,6 +122,15 @@ pub fn validate_service(svc: &Service, base_dir: &Path) -> Result<(), ConfigErro
             if fw.target.host.trim().is_empty() {
                 return Err(ConfigError::Invalid("`forward.target.host` cannot be empty".into()));
             }
+            if fw.max_concurrent == Some(0) {
+                return Err(ConfigError::Invalid("`forward.max_concurrent` must be at least 1".into()));
+            }
+            if fw.timeouts.idle_timeout_ms == Some(0) {
+                return Err(ConfigError::Invalid("`forward.idle_timeout_ms` must be at least 1".into()));
+            }
+            if fw.via_pseudonym.is_empty() || !fw.via_pseudonym.bytes().all(|b| b.is_ascii_graphic() && b != b',') {
+                return Err(ConfigError::Invalid("`forward.via_pseudonym` must be a single word without commas".into()));
+            }
         }
     }
     Ok(())
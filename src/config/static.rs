@@ -1,5 +1,9 @@
 use serde::Deserialize;
+use std::collections::BTreeMap;
 
+use super::service::ServiceRef;
+
+fn default_true() -> bool { true }
 fn default_file_index() -> String { "index.html".into() }
 fn default_file_404() -> String { "404.html".into() }
 fn default_file_500() -> String { "500.html".into() }
@@ -18,6 +22,58 @@ pub struct StaticService {
     pub index_strategy: IndexStrategy,
     #[serde(default)]
     pub evil_dir_strategy: EvilDirStrategy,
+    #[serde(default)]
+    pub rules: Vec<StaticRule>,
+    #[serde(default)]
+    pub cache: Option<StaticCacheConfig>,
+    /// Serve every file as `Content-Disposition: attachment` so browsers download it.
+    #[serde(default)]
+    pub force_download: bool,
+    /// Regex over the served file name (e.g. `\.[0-9a-f]{8,}\.`) marking content-hashed
+    /// files: those get `public, max-age=31536000, immutable`, every other file `no-cache`.
+    /// A matching rule's `cache_control` still wins.
+    #[serde(default)]
+    pub immutable_pattern: Option<String>,
+    /// Serve files whose real path (after resolving symlinks) lies outside `source_dir`.
+    /// When off such files answer 404; links that stay inside `source_dir` still work.
+    #[serde(default = "default_true")]
+    pub follow_symlinks: bool,
+    /// `Content-Type` for files whose extension is missing or unknown, instead of
+    /// `application/octet-stream`.
+    #[serde(default)]
+    pub default_content_type: Option<String>,
+    /// Service to hand the request to instead of answering 404, e.g. a `forward` to an origin.
+    #[serde(default)]
+    pub fallback: Option<Box<ServiceRef>>,
+}
+
+fn default_cache_max_entries() -> usize { 1024 }
+fn default_cache_max_bytes() -> usize { 16 * 1024 * 1024 }
+fn default_cache_max_file_size() -> usize { 256 * 1024 }
+
+/// In-memory LRU for small files; omitted means every request reads from disk.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct StaticCacheConfig {
+    #[serde(default = "default_cache_max_entries")]
+    pub max_entries: usize,
+    #[serde(default = "default_cache_max_bytes")]
+    pub max_bytes: usize,
+    #[serde(default = "default_cache_max_file_size")]
+    pub max_file_size: usize,
+}
+
+/// Extra response headers for served files whose request path matches `glob`; first match wins.
+#[derive(Debug, Deserialize, Clone)]
+pub struct StaticRule {
+    pub glob: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub cache_control: Option<String>,
+    /// Like the service-level `force_download`, for matching files only.
+    #[serde(default)]
+    pub force_download: bool,
 }
 
 fn default_redirect_code() -> u16 { 308 }
@@ -4,14 +4,37 @@ use serde::Deserialize;
 use super::http_version::{default_alpn, AlpnProto};
 
 fn default_true() -> bool { true }
+fn default_handshake_timeout_ms() -> u64 { 10_000 }
 
+/// TLS termination for a listener. Relative paths are resolved against the config
+/// file's directory.
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub struct TlsConfig {
     #[serde(default = "default_true")]
     pub enabled: bool,
+    /// PEM certificate chain, leaf first.
     pub cert_file: PathBuf,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1).
     pub key_file: PathBuf,
+    /// Protocols offered through ALPN, in order of preference.
     #[serde(default = "default_alpn")]
     pub alpn: Vec<AlpnProto>,
+    /// Ask clients for a certificate and verify it (mutual TLS).
+    #[serde(default)]
+    pub client_auth: Option<ClientAuth>,
+    /// How long a client has to finish the handshake before the connection is dropped.
+    #[serde(default = "default_handshake_timeout_ms")]
+    pub handshake_timeout_ms: u64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct ClientAuth {
+    /// PEM bundle of the CAs client certificates must chain to.
+    pub ca_file: PathBuf,
+    /// Refuse the handshake without a certificate; when off, such clients are
+    /// served with the `cert.*` variables unset.
+    #[serde(default = "default_true")]
+    pub required: bool,
 }
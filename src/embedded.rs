@@ -0,0 +1,36 @@
+//! Assets compiled into the binary, served by `respond: { embedded: (name) }`.
+//!
+//! Files under `assets/` (or `$OXIDASE_EMBED_DIR` at build time) are included by
+//! `build.rs` and registered by [`register_builtin`] before the config is built.
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
+
+static ASSETS: LazyLock<RwLock<HashMap<String, &'static [u8]>>> = LazyLock::new(Default::default);
+
+/// `(name, bytes)` for every file the build script found, sorted by name.
+static BUILTIN: &[(&str, &[u8])] = include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs"));
+
+/// Make `bytes` available under `name`, replacing any earlier asset with that name.
+pub fn register(name: &str, bytes: &'static [u8]) {
+    ASSETS.write().unwrap_or_else(|e| e.into_inner()).insert(name.to_string(), bytes);
+}
+
+/// The table `build.rs` generates for `tests/fixtures/embedded`.
+#[cfg(test)]
+pub static FIXTURE: &[(&str, &[u8])] = include!(concat!(env!("OUT_DIR"), "/embedded_fixture.rs"));
+
+/// Register every asset compiled in by the build script.
+pub fn register_builtin() {
+    register_all(BUILTIN);
+}
+
+pub fn register_all(assets: &[(&str, &'static [u8])]) {
+    for (name, bytes) in assets {
+        register(name, bytes);
+    }
+}
+
+pub fn get(name: &str) -> Option<&'static [u8]> {
+    ASSETS.read().unwrap_or_else(|e| e.into_inner()).get(name).copied()
+}
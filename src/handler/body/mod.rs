@@ -0,0 +1,155 @@
+//! Request body buffering shared by every handler.
+//!
+//! The server reads up to `max_buffer_bytes` of each request body before dispatch.
+//! Bodies that fit are replayed from memory and exposed as [`BufferedBody`] for
+//! body-aware features; larger ones are either streamed through untouched or
+//! rejected with 413, depending on [`OverLimit`].
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::{Bytes, BytesMut};
+use http_body_util::{BodyExt, Empty, Full, combinators::BoxBody};
+use hyper::body::{Body, Frame, Incoming, SizeHint};
+use hyper::http;
+
+use crate::config::http_server::{OverLimit, RequestBodyPolicy};
+use crate::handler::ResponseBody;
+use crate::util::http::make_error_resp;
+
+/// Body of every request seen by the handlers.
+pub type RequestBody = BoxBody<Bytes, hyper::Error>;
+
+/// Request extension holding the complete body when it fit under the buffering cap.
+/// Absent when the body was streamed through.
+#[derive(Debug, Clone)]
+pub struct BufferedBody(pub Bytes);
+
+/// Apply `policy` to an incoming request, buffering its body when it is small enough.
+pub async fn buffer_request(
+    req: http::Request<Incoming>,
+    policy: &RequestBodyPolicy,
+) -> Result<http::Request<RequestBody>, http::Response<ResponseBody>> {
+    let (mut parts, mut body) = req.into_parts();
+    let limit = policy.max_buffer_bytes as u64;
+
+    let mut buf = BytesMut::new();
+    let over_limit = loop {
+        if body.size_hint().lower() > limit || buf.len() as u64 > limit {
+            break true;
+        }
+        match body.frame().await {
+            None => break false,
+            Some(Err(e)) => return Err(make_error_resp(
+                http::StatusCode::BAD_REQUEST,
+                &format!("failed to read request body: {e}"),
+            )),
+            Some(Ok(frame)) => match frame.into_data() {
+                Ok(data) => buf.extend_from_slice(&data),
+                Err(frame) => {
+                    // trailers end the body
+                    let bytes = buf.freeze();
+                    parts.extensions.insert(BufferedBody(bytes.clone()));
+                    let trailers = frame.into_trailers().ok();
+                    let replay = Full::new(bytes)
+                        .map_err(|never| match never {})
+                        .with_trailers(async move { trailers.map(Ok) })
+                        .boxed();
+                    return Ok(http::Request::from_parts(parts, replay));
+                }
+            },
+        }
+    };
+
+    if !over_limit {
+        let bytes = buf.freeze();
+        parts.extensions.insert(BufferedBody(bytes.clone()));
+        let replay = Full::new(bytes).map_err(|never| match never {}).boxed();
+        return Ok(http::Request::from_parts(parts, replay));
+    }
+
+    match policy.over_limit {
+        OverLimit::Reject => Err(make_error_resp(
+            http::StatusCode::PAYLOAD_TOO_LARGE,
+            "request body exceeds the buffering limit",
+        )),
+        OverLimit::Stream => {
+            let body = Prefixed { prefix: Some(buf.freeze()), rest: body }.boxed();
+            Ok(http::Request::from_parts(parts, body))
+        }
+    }
+}
+
+/// Read at least `min` bytes (or the whole body, if shorter) from the front of `req`'s
+/// body and re-attach them, so the handlers downstream still see the complete body.
+pub async fn peek_request_body(
+    req: &mut http::Request<RequestBody>,
+    min: usize,
+) -> Result<Bytes, hyper::Error> {
+    let empty = Empty::new().map_err(|never| match never {}).boxed();
+    let mut body = std::mem::replace(req.body_mut(), empty);
+    let mut buf = BytesMut::new();
+    while buf.len() < min {
+        match body.frame().await {
+            None => break,
+            Some(Err(e)) => return Err(e),
+            Some(Ok(frame)) => match frame.into_data() {
+                Ok(data) => buf.extend_from_slice(&data),
+                Err(frame) => {
+                    let bytes = buf.freeze();
+                    let trailers = frame.into_trailers().ok();
+                    *req.body_mut() = Full::new(bytes.clone())
+                        .map_err(|never| match never {})
+                        .with_trailers(async move { trailers.map(Ok) })
+                        .boxed();
+                    return Ok(bytes);
+                }
+            },
+        }
+    }
+    let bytes = buf.freeze();
+    *req.body_mut() = Prefixed { prefix: Some(bytes.clone()), rest: body }.boxed();
+    Ok(bytes)
+}
+
+/// Replays the bytes already read, then streams the rest of the body.
+struct Prefixed<B> {
+    prefix: Option<Bytes>,
+    rest: B,
+}
+
+impl<B> Body for Prefixed<B>
+where
+    B: Body<Data = Bytes, Error = hyper::Error> + Unpin,
+{
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, hyper::Error>>> {
+        if let Some(prefix) = self.prefix.take().filter(|p| !p.is_empty()) {
+            return Poll::Ready(Some(Ok(Frame::data(prefix))));
+        }
+        Pin::new(&mut self.rest).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.prefix.as_ref().is_none_or(Bytes::is_empty) && self.rest.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let prefix = self.prefix.as_ref().map_or(0, |p| p.len() as u64);
+        let rest = self.rest.size_hint();
+        let mut hint = SizeHint::new();
+        hint.set_lower(rest.lower() + prefix);
+        if let Some(upper) = rest.upper() {
+            hint.set_upper(upper + prefix);
+        }
+        hint
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,77 @@
+use std::net::SocketAddr;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::http;
+
+use super::{BufferedBody, buffer_request};
+use crate::config::http_server::{OverLimit, RequestBodyPolicy};
+use crate::testing;
+
+/// Serve `buffer_request` directly, answering with what the handler would see.
+async fn spawn_buffering(policy: RequestBodyPolicy) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let policy = policy.clone();
+            let svc = hyper::service::service_fn(move |req| {
+                let policy = policy.clone();
+                async move {
+                    let req = match buffer_request(req, &policy).await {
+                        Ok(req) => req,
+                        Err(resp) => return Ok::<_, hyper::Error>(resp),
+                    };
+                    let buffered = req.extensions().get::<BufferedBody>().map(|b| b.0.len());
+                    let body = req.into_body().collect().await?.to_bytes();
+                    let report = format!("buffered={buffered:?};body={}", body.len());
+                    Ok(http::Response::new(crate::handler::full(report)))
+                }
+            });
+            tokio::spawn(hyper::server::conn::http1::Builder::new()
+                .serve_connection(hyper_util::rt::TokioIo::new(stream), svc));
+        }
+    });
+    addr
+}
+
+fn post(body: &'static str) -> http::Request<Full<Bytes>> {
+    http::Request::post("/").body(Full::from(body)).unwrap()
+}
+
+fn policy(max_buffer_bytes: usize, over_limit: OverLimit) -> RequestBodyPolicy {
+    RequestBodyPolicy { max_buffer_bytes, over_limit }
+}
+
+#[tokio::test]
+async fn body_under_cap_is_buffered() {
+    let addr = spawn_buffering(policy(8, OverLimit::Reject)).await;
+    let resp = testing::send(addr, post("12345678")).await;
+    assert_eq!(resp.body(), "buffered=Some(8);body=8");
+}
+
+#[tokio::test]
+async fn body_over_cap_streams_through_unbuffered() {
+    let addr = spawn_buffering(policy(4, OverLimit::Stream)).await;
+    let resp = testing::send(addr, post("123456789")).await;
+    assert_eq!(resp.body(), "buffered=None;body=9");
+}
+
+#[tokio::test]
+async fn body_over_cap_can_be_rejected() {
+    let addr = spawn_buffering(policy(4, OverLimit::Reject)).await;
+    let resp = testing::send(addr, post("123456789")).await;
+    assert_eq!(resp.status(), http::StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn chunked_body_over_cap_keeps_every_byte() {
+    let addr = spawn_buffering(policy(4, OverLimit::Stream)).await;
+    let req = http::Request::post("/")
+        .header(http::header::TRANSFER_ENCODING, "chunked")
+        .body(Full::from("abcdefghijklmnop"))
+        .unwrap();
+    let resp = testing::send(addr, req).await;
+    assert_eq!(resp.body(), "buffered=None;body=16");
+}
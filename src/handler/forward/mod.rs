@@ -0,0 +1,487 @@
+pub mod idle;
+pub mod pool;
+pub mod ready;
+
+use std::time::Duration;
+
+use http_body_util::{BodyExt, Empty, LengthLimitError, Limited};
+use hyper::{http, Uri};
+
+use crate::build::service::LoadedForward;
+use crate::config::forward::{ForwardTarget, PassHost, PassHostMode};
+use crate::config::url_scheme::Scheme;
+use crate::handler::forward::idle::{IdleTimeout, STALLED, is_stalled};
+use crate::handler::{BoxResponseFuture, RequestBody, ResponseBody, ServiceHandler, full};
+use crate::handler::body::BufferedBody;
+use crate::http_server::ConnInfo;
+use crate::util::glob::glob_match;
+use crate::util::http::{make_error_resp, parse_host_header, set_content_length};
+
+pub type ForwardResult<T> = Result<T, String>;
+
+/// Request body sent upstream: replayed from the buffer, or streamed when over the cap.
+type UpstreamBody = RequestBody;
+
+const RESPONSE_TOO_LARGE: &str = "upstream response exceeds max_response_bytes";
+
+impl ServiceHandler for LoadedForward {
+    fn handle_request<'a>(
+        &'a self,
+        req: &'a mut http::Request<RequestBody>,
+    ) -> BoxResponseFuture<'a> {
+        Box::pin(async move {
+            if let Some(gate) = &self.ready
+                && !gate.is_ready()
+            {
+                gate.start((*self.client).clone());
+                return make_error_resp(http::StatusCode::SERVICE_UNAVAILABLE, "upstream not ready");
+            }
+            let permit = match &self.limiter {
+                None => None,
+                Some(limiter) if self.config.queue_when_full => limiter.clone().acquire_owned().await.ok(),
+                Some(limiter) => match limiter.clone().try_acquire_owned() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => return make_error_resp(http::StatusCode::SERVICE_UNAVAILABLE, "too many concurrent upstream requests"),
+                },
+            };
+            let resp = match self.forward(req).await {
+                Ok(resp) => resp,
+                Err(msg) => make_error_resp(http::StatusCode::BAD_GATEWAY, &msg),
+            };
+            // the slot stays taken until the response body is done
+            resp.map(|body| body.map_frame(move |frame| {
+                let _ = &permit;
+                frame
+            }).boxed())
+        })
+    }
+}
+
+impl LoadedForward {
+    /// Try `target`, then each of `failover_targets` while the attempt fails or
+    /// answers with one of `retry_statuses`. Only idempotent requests whose body
+    /// was buffered can be replayed; anything else gets a single attempt.
+    async fn forward(
+        &self,
+        req: &mut http::Request<RequestBody>,
+    ) -> ForwardResult<http::Response<ResponseBody>> {
+        let replay = req.extensions().get::<BufferedBody>().map(|b| b.0.clone())
+            .filter(|_| is_idempotent(req.method()));
+        let targets: Vec<&ForwardTarget> = std::iter::once(&self.config.target)
+            .chain(&self.config.failover_targets)
+            .collect();
+        let attempts = if replay.is_some() { targets.len() } else { 1 };
+
+        let mut body = std::mem::replace(req.body_mut(), empty_body());
+        for (i, target) in targets[..attempts].iter().enumerate() {
+            let last = i + 1 == attempts;
+            let result = self.forward_to(target, req, body).await;
+            body = replay.clone().map_or_else(empty_body, full);
+            match result {
+                Ok(resp) if !last && self.config.retry_statuses.contains(&resp.status().as_u16()) => {}
+                Err(_) if !last => {}
+                other => return other,
+            }
+        }
+        unreachable!("the last attempt always returns")
+    }
+
+    async fn forward_to(
+        &self,
+        target: &ForwardTarget,
+        req: &http::Request<RequestBody>,
+        body: UpstreamBody,
+    ) -> ForwardResult<http::Response<ResponseBody>> {
+        // TODO: https upstream, timeouts, http version
+        if matches!(target.scheme, Scheme::Https) {
+            return Err("TODO: https upstream not yet implemented".to_string());
+        }
+
+        let upstream_uri = build_upstream_uri(target, req)?;
+
+        let mut upstream_req = http::Request::builder()
+            .method(req.method())
+            .uri(upstream_uri)
+            .body(body)
+            .map_err(|e| format!("failed to build upstream request: {e}"))?;
+
+        // copy rest of headers
+        let host_header = self.host_header(target, req)?;
+        let sent_host = host_header.as_ref().and_then(|h| h.to_str().ok()).map(str::to_string);
+        copy_headers(req, &mut upstream_req, host_header, self.config.x_forwarded);
+        // strip first, so dropping the client's `authorization` keeps the injected one
+        strip_headers(upstream_req.headers_mut(), &self.config.strip_request_headers);
+        self.apply_auth(&mut upstream_req);
+        if self.config.via {
+            append_via(upstream_req.headers_mut(), req.version(), &self.config.via_pseudonym);
+        }
+
+        let in_flight = self.pool.request_started();
+        let upstream_resp = self.client
+            .request(upstream_req)
+            .await
+            .map_err(|e| format!("upstream request failed: {e}"))?;
+
+        // the body (and any trailers) is streamed through as it arrives
+        let (mut parts, body) = upstream_resp.into_parts();
+
+        if self.config.rewrite_redirects {
+            self.rewrite_location(target, req, sent_host.as_deref(), &mut parts.headers);
+        }
+        if self.config.rewrite_set_cookie {
+            self.rewrite_set_cookies(target, req, &mut parts.headers);
+        }
+
+        // downstream response builder
+        let mut builder = http::Response::builder().status(parts.status);
+        strip_headers(&mut parts.headers, &self.config.strip_response_headers);
+        if self.config.via {
+            append_via(&mut parts.headers, parts.version, &self.config.via_pseudonym);
+        }
+        for (name, value) in parts.headers.iter() {
+            builder = builder.header(name, value);
+        }
+        let body: ResponseBody = match self.config.timeouts.idle_timeout_ms {
+            Some(ms) => IdleTimeout::new(body, Duration::from_millis(ms.into())).boxed(),
+            None => body.map_err(std::io::Error::other).boxed(),
+        };
+
+        let Some(max) = self.config.max_response_bytes else {
+            // the request stays active in the pool stats until its body is done
+            let body = body.map_frame(move |frame| {
+                let _ = &in_flight;
+                frame
+            });
+            return builder
+                .body(body.boxed())
+                .map_err(|e| format!("failed to build downstream response: {e}"));
+        };
+
+        // capped responses are read in full first so an oversized one can still become a 502
+        let declared = parts.headers.get(http::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        if declared.is_some_and(|len| len > max as u64) {
+            return Err(RESPONSE_TOO_LARGE.to_string());
+        }
+        let collected = match Limited::new(body, max).collect().await {
+            Ok(collected) => collected,
+            Err(e) if is_stalled(&*e) => return Ok(make_error_resp(http::StatusCode::GATEWAY_TIMEOUT, STALLED)),
+            Err(e) if e.is::<LengthLimitError>() => return Err(RESPONSE_TOO_LARGE.to_string()),
+            Err(e) => return Err(format!("failed to read upstream response: {e}")),
+        };
+        drop(in_flight);
+        let bytes = collected.to_bytes();
+        let len = bytes.len();
+        let mut resp = builder
+            .body(full(bytes))
+            .map_err(|e| format!("failed to build downstream response: {e}"))?;
+        resp.headers_mut().remove(http::header::TRANSFER_ENCODING);
+        set_content_length(&mut resp, len);
+        Ok(resp)
+    }
+
+    /// Set the configured `Authorization` header, keeping the client's unless `override` is set.
+    fn apply_auth(&self, upstream: &mut http::Request<UpstreamBody>) {
+        let (Some(value), Some(auth)) = (&self.auth_header, &self.config.auth) else {
+            return;
+        };
+        let headers = upstream.headers_mut();
+        if auth.r#override || !headers.contains_key(http::header::AUTHORIZATION) {
+            headers.insert(http::header::AUTHORIZATION, value.clone());
+        }
+    }
+
+    /// Point an upstream `Location` back at the public origin the client used.
+    fn rewrite_location(
+        &self,
+        target: &ForwardTarget,
+        req: &http::Request<RequestBody>,
+        sent_host: Option<&str>,
+        headers: &mut http::HeaderMap,
+    ) {
+        let Some(location) = headers.get(http::header::LOCATION).and_then(|v| v.to_str().ok()) else {
+            return;
+        };
+        let Some(public_host) = req.headers().get(http::header::HOST).and_then(|v| v.to_str().ok()) else {
+            return;
+        };
+        let public_scheme = req.uri().scheme_str().unwrap_or(
+            match req.extensions().get::<ConnInfo>() {
+                Some(c) if c.is_tls => "https",
+                _ => "http",
+            },
+        );
+
+        let mut upstream_hosts = vec![format_host(&target.host, target.port, target.scheme)];
+        upstream_hosts.extend(sent_host.map(str::to_string));
+
+        let rewritten = rewrite_location(
+            location,
+            &upstream_hosts,
+            &target.path_prefix,
+            &format!("{public_scheme}://{public_host}"),
+        );
+        if let Some(v) = rewritten.and_then(|l| http::HeaderValue::from_str(&l).ok()) {
+            headers.insert(http::header::LOCATION, v);
+        }
+    }
+
+    /// Point upstream `Set-Cookie` headers at the public host (and path, if enabled).
+    fn rewrite_set_cookies(
+        &self,
+        target: &ForwardTarget,
+        req: &http::Request<RequestBody>,
+        headers: &mut http::HeaderMap,
+    ) {
+        let public_host = req.headers().get(http::header::HOST)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_host_header)
+            .map(|(host, _)| host);
+        let path_prefix = Some(target.path_prefix.as_str()).filter(|_| self.config.rewrite_set_cookie_path);
+        let rewritten: Vec<http::HeaderValue> = headers.get_all(http::header::SET_COOKIE)
+            .iter()
+            .map(|v| match v.to_str() {
+                Ok(cookie) => http::HeaderValue::from_str(&rewrite_set_cookie(cookie, public_host.as_deref(), path_prefix))
+                    .unwrap_or_else(|_| v.clone()),
+                Err(_) => v.clone(),
+            })
+            .collect();
+        headers.remove(http::header::SET_COOKIE);
+        for v in rewritten {
+            headers.append(http::header::SET_COOKIE, v);
+        }
+    }
+
+    /// Decide the Host header value based on pass_host strategy.
+    fn host_header(
+        &self,
+        target: &ForwardTarget,
+        req: &http::Request<RequestBody>,
+    ) -> ForwardResult<Option<http::HeaderValue>> {
+        match &self.config.pass_host {
+            PassHost::Mode(PassHostMode::Incoming) =>
+                req.headers().get(http::header::HOST)
+                    .and_then(|v| v.to_str().ok())
+                    .map(|s| s.to_string()),
+            PassHost::Mode(PassHostMode::Target) =>
+                Some(format_host(&target.host, target.port, target.scheme)),
+            PassHost::Custom { custom } => Some(custom.clone()),
+        }.map(|h| http::HeaderValue::from_str(&h)
+            .map_err(|e| format!("invalid host header value: {e}")))
+            .transpose()
+    }
+}
+
+fn build_upstream_uri(
+    target: &ForwardTarget,
+    req: &http::Request<RequestBody>,
+) -> ForwardResult<Uri> {
+    let scheme = match target.scheme {
+        Scheme::Http => "http",
+        Scheme::Https => "https",
+    };
+
+    let mut path = target.path_prefix.clone();
+
+    if path.ends_with('/') && req.uri().path().starts_with('/') {
+        path.pop();
+    }
+
+    path.push_str(req.uri().path());
+
+    if !path.starts_with('/') {
+        path.insert(0, '/');
+    }
+
+    let mut uri = format!("{scheme}://{}:{}{}", target.host, target.port, path);
+    if let Some(q) = req.uri().query() {
+        uri.push('?');
+        uri.push_str(q);
+    }
+
+    uri.parse::<Uri>()
+        .map_err(|e| format!("failed to build upstream URI: {e}"))
+}
+
+fn empty_body() -> UpstreamBody {
+    Empty::new().map_err(|never| match never {}).boxed()
+}
+
+/// Methods that may be sent again to another target without changing their effect.
+fn is_idempotent(method: &http::Method) -> bool {
+    matches!(
+        *method,
+        http::Method::GET | http::Method::HEAD | http::Method::OPTIONS
+            | http::Method::PUT | http::Method::DELETE | http::Method::TRACE
+    )
+}
+
+/// Remove every header whose name matches one of `patterns` (case-insensitive, `*` globs).
+fn strip_headers(headers: &mut http::HeaderMap, patterns: &[String]) {
+    if patterns.is_empty() {
+        return;
+    }
+    let patterns: Vec<String> = patterns.iter().map(|p| p.to_ascii_lowercase()).collect();
+    let doomed: Vec<http::HeaderName> = headers
+        .keys()
+        .filter(|name| patterns.iter().any(|p| glob_match(p, name.as_str())))
+        .cloned()
+        .collect();
+    for name in doomed {
+        headers.remove(name);
+    }
+}
+
+/// Add this hop to the `Via` chain (RFC 9110, section 7.6.3), folding any earlier
+/// `Via` lines into one value so the order is kept.
+fn append_via(headers: &mut http::HeaderMap, version: http::Version, pseudonym: &str) {
+    let protocol = match version {
+        http::Version::HTTP_09 => "0.9",
+        http::Version::HTTP_10 => "1.0",
+        http::Version::HTTP_2 => "2",
+        http::Version::HTTP_3 => "3",
+        _ => "1.1",
+    };
+    let mut chain: Vec<&str> = headers.get_all(http::header::VIA).iter().filter_map(|v| v.to_str().ok()).collect();
+    let hop = format!("{protocol} {pseudonym}");
+    chain.push(&hop);
+    if let Ok(value) = http::HeaderValue::from_str(&chain.join(", ")) {
+        headers.insert(http::header::VIA, value);
+    }
+}
+
+/// Copy downstream headers into the upstream request, then apply Host and X-Forwarded-* if enabled.
+fn copy_headers(
+    downstream: &http::Request<RequestBody>,
+    upstream: &mut http::Request<UpstreamBody>,
+    host_header: Option<http::HeaderValue>,
+    x_forwarded: bool,
+) {
+    let headers = upstream.headers_mut();
+
+    for (name, value) in downstream.headers() {
+        // The server starts reading the body before dispatch (hyper answers the
+        // client's `Expect: 100-continue` at that point), so there is nothing left
+        // for the upstream to negotiate.
+        if name == http::header::HOST || name == http::header::EXPECT {
+            continue;
+        }
+        headers.append(name, value.clone());
+    }
+
+    if let Some(host) = host_header {
+        headers.insert(http::header::HOST, host);
+    }
+
+    if x_forwarded {
+        if let Some(host) = downstream.headers().get(http::header::HOST) {
+            headers.insert(
+                http::header::HeaderName::from_static("x-forwarded-host"),
+                host.clone(),
+            );
+        }
+
+        let proto = downstream.uri().scheme_str().unwrap_or("http");
+        if let Ok(xfp) = http::HeaderValue::from_str(proto) {
+            headers.insert(
+                http::header::HeaderName::from_static("x-forwarded-proto"),
+                xfp,
+            );
+        }
+
+        if let Some(xff) = downstream.headers().get(
+            http::header::HeaderName::from_static("x-forwarded-for"),
+        ) {
+            headers.insert(
+                http::header::HeaderName::from_static("x-forwarded-for"),
+                xff.clone(),
+            );
+        }
+    }
+}
+
+/// Drop default ports for http/https when formatting host header.
+fn format_host(host: &str, port: u16, scheme: Scheme) -> String {
+    let default_port = matches!((scheme, port), (Scheme::Http, 80) | (Scheme::Https, 443));
+    if default_port {
+        host.to_string()
+    } else {
+        format!("{host}:{port}")
+    }
+}
+
+/// Rewrite a `Location` that points at the upstream (by absolute URL on one of
+/// `upstream_hosts`, or by an absolute path under `path_prefix`) so it points at
+/// `public_origin` instead. Returns `None` when the location is left untouched.
+pub(crate) fn rewrite_location(
+    location: &str,
+    upstream_hosts: &[String],
+    path_prefix: &str,
+    public_origin: &str,
+) -> Option<String> {
+    let path = if let Some((_, rest)) = location.split_once("://") {
+        let (authority, path) = rest.find('/').map_or((rest, ""), |i| rest.split_at(i));
+        let authority = authority.to_ascii_lowercase();
+        let is_upstream = upstream_hosts.iter().any(|h| {
+            let h = h.to_ascii_lowercase();
+            authority == h
+                || authority.strip_suffix(":80") == Some(h.as_str())
+                || authority.strip_suffix(":443") == Some(h.as_str())
+        });
+        if !is_upstream {
+            return None;
+        }
+        path
+    } else if location.starts_with('/') && !location.starts_with("//") {
+        location
+    } else {
+        // relative to the current path, already resolves against the public URL
+        return None;
+    };
+
+    let prefix = path_prefix.trim_end_matches('/');
+    let path = match path.strip_prefix(prefix) {
+        Some(rest) if !prefix.is_empty() && (rest.is_empty() || rest.starts_with(['/', '?', '#'])) => rest,
+        _ => path,
+    };
+    let path = if path.starts_with('/') { path.to_string() } else { format!("/{path}") };
+
+    if location.starts_with('/') {
+        Some(path)
+    } else {
+        Some(format!("{public_origin}{path}"))
+    }
+}
+
+/// Rewrite the `Domain` attribute of one `Set-Cookie` value to `public_host` and strip
+/// `path_prefix` from its `Path`. The cookie itself and every other attribute
+/// (`Secure`, `HttpOnly`, `SameSite`, ...) are kept as sent.
+pub(crate) fn rewrite_set_cookie(cookie: &str, public_host: Option<&str>, path_prefix: Option<&str>) -> String {
+    let mut parts = cookie.split(';');
+    let mut out = parts.next().unwrap_or("").to_string();
+    for attr in parts {
+        let attr = attr.trim();
+        let (name, value) = attr.split_once('=').unwrap_or((attr, ""));
+        out.push_str("; ");
+        if let Some(host) = public_host
+            && name.trim().eq_ignore_ascii_case("domain")
+        {
+            out.push_str("Domain=");
+            out.push_str(host);
+        } else if let Some(prefix) = path_prefix.map(|p| p.trim_end_matches('/')).filter(|p| !p.is_empty())
+            && name.trim().eq_ignore_ascii_case("path")
+            && let Some(rest) = value.trim().strip_prefix(prefix)
+            && (rest.is_empty() || rest.starts_with('/'))
+        {
+            out.push_str("Path=");
+            out.push_str(if rest.is_empty() { "/" } else { rest });
+        } else {
+            out.push_str(attr);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,291 @@
+//! Upstream client shared by every request of one forward service.
+//!
+//! Connections are counted as they are opened and dropped by the pool, so idle
+//! reaping shows up in [`PoolStats`]. With `upstream_proxy` set, every connection
+//! is opened through an HTTP CONNECT tunnel or a SOCKSv5 proxy. Host names are
+//! looked up again once `dns_refresh_ms` has passed.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use hyper::Uri;
+use hyper::http::HeaderValue;
+use hyper::rt::{Read, ReadBufCursor, Write};
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::proxy::{SocksV5, Tunnel};
+use hyper_util::client::legacy::connect::dns::{GaiResolver, Name};
+use hyper_util::client::legacy::connect::{Connected, Connection, HttpConnector};
+use hyper_util::rt::{TokioExecutor, TokioTimer};
+use percent_encoding::percent_decode_str;
+use tower_service::Service;
+
+use crate::config::forward::{ForwardService, Timeouts, UpstreamHttpVersion};
+use crate::handler::RequestBody;
+use crate::metrics::PoolStats;
+
+pub type UpstreamClient = Client<CountingConnector, RequestBody>;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+type ConnectFuture = Pin<Box<dyn Future<Output = Result<InnerConn, BoxError>> + Send>>;
+type InnerConn = <HttpConnector as Service<Uri>>::Response;
+
+/// Plain TCP dialer used directly or to reach the proxy.
+pub type Dialer = HttpConnector<RefreshingResolver<GaiResolver>>;
+
+/// Build the pooled client for `cfg`; `proxy_url` is `cfg.upstream_proxy` after env expansion.
+pub fn upstream_client(
+    cfg: &ForwardService,
+    proxy_url: Option<&str>,
+    stats: Arc<PoolStats>,
+) -> Result<UpstreamClient, String> {
+    let resolver = RefreshingResolver::new(GaiResolver::new(), cfg.pool.dns_refresh_ms.map(Duration::from_millis));
+    let mut direct = HttpConnector::new_with_resolver(resolver);
+    configure_dialer(&mut direct, &cfg.timeouts);
+    let inner = match proxy_url {
+        Some(url) => proxy_connector(url, direct)?,
+        None => UpstreamConnector::Direct(direct),
+    };
+
+    let mut builder = Client::builder(TokioExecutor::new());
+    builder.pool_timer(TokioTimer::new());
+    if let Some(ms) = cfg.pool.pool_idle_timeout_ms {
+        builder.pool_idle_timeout(Duration::from_millis(ms));
+    }
+    if let Some(max) = cfg.pool.pool_max_idle_per_host {
+        builder.pool_max_idle_per_host(max);
+    }
+    // `http1` and `auto` differ only once a TLS connector negotiates ALPN
+    if cfg.upstream_http_version == UpstreamHttpVersion::Http2 {
+        builder.http2_only(true);
+    }
+    Ok(builder.build(CountingConnector { inner, stats }))
+}
+
+/// Default head start for the preferred address family, as recommended by RFC 8305.
+const HAPPY_EYEBALLS_DELAY: Duration = Duration::from_millis(300);
+
+/// Apply dial settings: hosts resolving to both IPv6 and IPv4 race the two
+/// families, the second starting after the happy-eyeballs delay.
+pub(crate) fn configure_dialer<R>(http: &mut HttpConnector<R>, timeouts: &Timeouts) {
+    http.enforce_http(true); // TODO: later switch to false for HTTPS support
+    http.set_connect_timeout(timeouts.connect_ms.map(|ms| Duration::from_millis(ms.into())));
+    http.set_happy_eyeballs_timeout(Some(
+        timeouts.happy_eyeballs_ms.map_or(HAPPY_EYEBALLS_DELAY, |ms| Duration::from_millis(ms.into())),
+    ));
+}
+
+/// Parse `http://[user:pass@]host[:port]` or `socks5://[user:pass@]host[:port]`.
+fn proxy_connector(url: &str, direct: Dialer) -> Result<UpstreamConnector, String> {
+    let invalid = |why: &str| format!("invalid upstream_proxy `{url}`: {why}");
+    let uri: Uri = url.parse().map_err(|_| invalid("not a URL"))?;
+    let authority = uri.authority().ok_or_else(|| invalid("missing host"))?;
+    let credentials = authority.as_str().rsplit_once('@').map(|(info, _)| {
+        let (user, pass) = info.split_once(':').unwrap_or((info, ""));
+        let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().into_owned();
+        (decode(user), decode(pass))
+    });
+    let default_port = match uri.scheme_str() {
+        Some("http") => 80,
+        Some("socks5") => 1080,
+        _ => return Err(invalid("scheme must be http or socks5")),
+    };
+    // the proxy itself is always reached over plain TCP
+    let port = authority.port_u16().unwrap_or(default_port);
+    let proxy_dst: Uri = format!("http://{}:{port}", authority.host()).parse().map_err(|_| invalid("bad host"))?;
+
+    Ok(if uri.scheme_str() == Some("http") {
+        let mut tunnel = Tunnel::new(proxy_dst, direct);
+        if let Some((user, pass)) = credentials {
+            let token = base64::engine::general_purpose::STANDARD.encode(format!("{user}:{pass}"));
+            let auth = HeaderValue::from_str(&format!("Basic {token}")).map_err(|_| invalid("bad credentials"))?;
+            tunnel = tunnel.with_auth(auth);
+        }
+        UpstreamConnector::Tunnel(tunnel)
+    } else {
+        let mut socks = SocksV5::new(proxy_dst, direct);
+        if let Some((user, pass)) = credentials {
+            socks = socks.with_auth(user, pass);
+        }
+        UpstreamConnector::Socks(socks)
+    })
+}
+
+/// Resolver that reuses an answer for `ttl`, then looks the name up again.
+/// Without a `ttl` every connection attempt resolves afresh.
+#[derive(Debug, Clone)]
+pub struct RefreshingResolver<R> {
+    inner: R,
+    cache: Arc<DnsCache>,
+}
+
+/// Answers shared by every clone of one resolver, with when each was looked up.
+#[derive(Debug, Default)]
+struct DnsCache {
+    ttl: Option<Duration>,
+    entries: Mutex<HashMap<Name, (Instant, Vec<SocketAddr>)>>,
+}
+
+impl<R> RefreshingResolver<R> {
+    pub fn new(inner: R, ttl: Option<Duration>) -> Self {
+        RefreshingResolver { inner, cache: Arc::new(DnsCache { ttl, ..Default::default() }) }
+    }
+}
+
+impl<R> Service<Name> for RefreshingResolver<R>
+where
+    R: Service<Name>,
+    R::Response: Iterator<Item = SocketAddr>,
+    R::Error: Into<BoxError>,
+    R::Future: Send + 'static,
+{
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        if let Some(ttl) = self.cache.ttl
+            && let Some((at, addrs)) = self.cache.entries.lock().unwrap().get(&name)
+            && at.elapsed() < ttl
+        {
+            let addrs = addrs.clone();
+            return Box::pin(async move { Ok(addrs.into_iter()) });
+        }
+        let resolving = self.inner.call(name.clone());
+        let cache = self.cache.ttl.map(|_| self.cache.clone());
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = resolving.await.map_err(Into::into)?.collect();
+            if let Some(cache) = cache {
+                cache.entries.lock().unwrap().insert(name, (Instant::now(), addrs.clone()));
+            }
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+/// How connections to the upstream are opened.
+#[derive(Debug, Clone)]
+pub enum UpstreamConnector {
+    Direct(Dialer),
+    Tunnel(Tunnel<Dialer>),
+    Socks(SocksV5<Dialer>),
+}
+
+impl Service<Uri> for UpstreamConnector {
+    type Response = InnerConn;
+    type Error = BoxError;
+    type Future = ConnectFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self {
+            UpstreamConnector::Direct(c) => c.poll_ready(cx).map_err(Into::into),
+            UpstreamConnector::Tunnel(c) => c.poll_ready(cx).map_err(Into::into),
+            UpstreamConnector::Socks(c) => c.poll_ready(cx).map_err(Into::into),
+        }
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        match self {
+            UpstreamConnector::Direct(c) => {
+                let fut = c.call(uri);
+                Box::pin(async move { fut.await.map_err(Into::into) })
+            }
+            UpstreamConnector::Tunnel(c) => {
+                let fut = c.call(uri);
+                Box::pin(async move { fut.await.map_err(Into::into) })
+            }
+            UpstreamConnector::Socks(c) => {
+                let fut = c.call(uri);
+                Box::pin(async move { fut.await.map_err(Into::into) })
+            }
+        }
+    }
+}
+
+/// [`UpstreamConnector`] that records every connection it opens in [`PoolStats`].
+#[derive(Debug, Clone)]
+pub struct CountingConnector {
+    inner: UpstreamConnector,
+    stats: Arc<PoolStats>,
+}
+
+impl Service<Uri> for CountingConnector {
+    type Response = Counted<InnerConn>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connecting = self.inner.call(uri);
+        let stats = self.stats.clone();
+        Box::pin(async move {
+            let io = connecting.await?;
+            stats.connection_opened();
+            Ok(Counted { io, stats })
+        })
+    }
+}
+
+/// A connection that un-counts itself when the pool drops it.
+#[derive(Debug)]
+pub struct Counted<T> {
+    io: T,
+    stats: Arc<PoolStats>,
+}
+
+impl<T> Drop for Counted<T> {
+    fn drop(&mut self) {
+        self.stats.connection_closed();
+    }
+}
+
+impl<T: Read + Unpin> Read for Counted<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: ReadBufCursor<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_read(cx, buf)
+    }
+}
+
+impl<T: Write + Unpin> Write for Counted<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().io).poll_write_vectored(cx, bufs)
+    }
+}
+
+impl<T: Connection> Connection for Counted<T> {
+    fn connected(&self) -> Connected {
+        self.io.connected()
+    }
+}
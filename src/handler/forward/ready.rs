@@ -0,0 +1,61 @@
+//! `wait_for_ready`: hold traffic back from a forward service until its target
+//! first answers a probe. Once open, the gate stays open.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use hyper::{Uri, http};
+
+use super::pool::UpstreamClient;
+use crate::handler::full;
+
+#[derive(Debug)]
+pub struct Readiness {
+    ready: AtomicBool,
+    started: AtomicBool,
+    probe: Uri,
+    interval: Duration,
+}
+
+impl Readiness {
+    pub fn new(probe: Uri, interval: Duration) -> Arc<Self> {
+        Arc::new(Readiness {
+            ready: AtomicBool::new(false),
+            started: AtomicBool::new(false),
+            probe,
+            interval,
+        })
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    /// Start probing in the background unless already started; needs a Tokio runtime.
+    /// The task ends on the first success or once the service is dropped.
+    pub fn start(self: &Arc<Self>, client: UpstreamClient) {
+        if self.started.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        tokio::spawn(probe_until_ready(Arc::downgrade(self), client));
+    }
+}
+
+/// Each probe gets `interval` to answer. The gate is held only while it is read or
+/// updated, so dropping the service ends the task after the probe in flight.
+async fn probe_until_ready(gate: Weak<Readiness>, client: UpstreamClient) {
+    while let Some((probe, interval)) = gate.upgrade().map(|g| (g.probe.clone(), g.interval)) {
+        let req = http::Request::get(probe).body(full(bytes::Bytes::new()));
+        if let Ok(req) = req
+            && let Ok(Ok(resp)) = tokio::time::timeout(interval, client.request(req)).await
+            && !resp.status().is_server_error()
+        {
+            if let Some(gate) = gate.upgrade() {
+                gate.ready.store(true, Ordering::Release);
+            }
+            return;
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
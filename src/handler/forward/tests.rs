@@ -0,0 +1,899 @@
+use hyper::http;
+
+use super::{rewrite_location, rewrite_set_cookie};
+use crate::testing;
+
+fn hosts(h: &[&str]) -> Vec<String> {
+    h.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn rewrites_absolute_and_relative_locations() {
+    let up = hosts(&["10.0.0.5:8080", "internal"]);
+    let origin = "https://example.com";
+
+    assert_eq!(rewrite_location("http://internal/x", &up, "", origin).as_deref(), Some("https://example.com/x"));
+    assert_eq!(rewrite_location("http://10.0.0.5:8080/a?b=1", &up, "", origin).as_deref(), Some("https://example.com/a?b=1"));
+    assert_eq!(rewrite_location("http://internal:80", &up, "", origin).as_deref(), Some("https://example.com/"));
+    assert_eq!(rewrite_location("http://other.org/x", &up, "", origin), None);
+    assert_eq!(rewrite_location("next", &up, "", origin), None);
+
+    // path_prefix is stripped so the location lines up with the public path
+    assert_eq!(rewrite_location("/app/login", &up, "/app/", origin).as_deref(), Some("/login"));
+    assert_eq!(rewrite_location("http://internal/app", &up, "/app", origin).as_deref(), Some("https://example.com/"));
+    assert_eq!(rewrite_location("/application", &up, "/app", origin).as_deref(), Some("/application"));
+}
+
+#[tokio::test]
+async fn upstream_redirect_is_rewritten_to_public_origin() {
+    let upstream = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - redirect: { status: _302, location: "http://internal/x" }
+"#)).await;
+
+    let forward = |rewrite: bool| testing::service(&format!(r#"
+handler: forward
+target: {{ scheme: http, host: 127.0.0.1, port: {} }}
+pass_host: {{ custom: internal }}
+rewrite_redirects: {rewrite}
+"#, upstream.port()));
+
+    let addr = testing::spawn(forward(true)).await;
+    let resp = testing::get(addr, "/").await;
+    assert_eq!(resp.status(), http::StatusCode::FOUND);
+    assert_eq!(resp.headers()[http::header::LOCATION], "http://example.com/x");
+
+    let addr = testing::spawn(forward(false)).await;
+    let resp = testing::get(addr, "/").await;
+    assert_eq!(resp.headers()[http::header::LOCATION], "http://internal/x");
+}
+
+#[test]
+fn set_cookie_domain_and_path_are_rewritten() {
+    let cookie = "sid=abc; Domain=.internal.svc; Path=/app/admin; Secure; HttpOnly; SameSite=Lax";
+    assert_eq!(
+        rewrite_set_cookie(cookie, Some("example.com"), None),
+        "sid=abc; Domain=example.com; Path=/app/admin; Secure; HttpOnly; SameSite=Lax",
+    );
+    assert_eq!(
+        rewrite_set_cookie(cookie, Some("example.com"), Some("/app/")),
+        "sid=abc; Domain=example.com; Path=/admin; Secure; HttpOnly; SameSite=Lax",
+    );
+    assert_eq!(rewrite_set_cookie("a=1; path=/app", None, Some("/app")), "a=1; Path=/");
+    // only whole segments of the prefix are stripped
+    assert_eq!(rewrite_set_cookie("a=1; Path=/application", None, Some("/app")), "a=1; Path=/application");
+    assert_eq!(rewrite_set_cookie("a=b=c;Max-Age=60", Some("example.com"), None), "a=b=c; Max-Age=60");
+}
+
+#[tokio::test]
+async fn upstream_set_cookie_domain_is_rewritten() {
+    let upstream = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - respond:
+          status: 200
+          headers: { set-cookie: "sid=abc; Domain=internal.svc; Path=/app/; Secure; HttpOnly; SameSite=Strict" }
+"#)).await;
+    let forward = |rewrite: bool| testing::service(&format!(r#"
+handler: forward
+target: {{ scheme: http, host: 127.0.0.1, port: {}, path_prefix: /app }}
+rewrite_set_cookie: {rewrite}
+rewrite_set_cookie_path: true
+"#, upstream.port()));
+
+    let addr = testing::spawn(forward(true)).await;
+    let resp = testing::get(addr, "/").await;
+    assert_eq!(
+        resp.headers()[http::header::SET_COOKIE],
+        "sid=abc; Domain=example.com; Path=/; Secure; HttpOnly; SameSite=Strict",
+    );
+
+    let addr = testing::spawn(forward(false)).await;
+    let resp = testing::get(addr, "/").await;
+    assert_eq!(
+        resp.headers()[http::header::SET_COOKIE],
+        "sid=abc; Domain=internal.svc; Path=/app/; Secure; HttpOnly; SameSite=Strict",
+    );
+}
+
+async fn echo_auth_upstream() -> std::net::SocketAddr {
+    testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - respond: { status: 200, body: "${header.authorization}" }
+"#)).await
+}
+
+fn forward_with_auth(upstream: std::net::SocketAddr, auth: &str) -> crate::build::service::LoadedService {
+    testing::service(&format!(
+        "handler: forward\ntarget: {{ scheme: http, host: 127.0.0.1, port: {} }}\nauth: {auth}\n",
+        upstream.port(),
+    ))
+}
+
+fn get_with_auth(path: &str, auth: &str) -> http::Request<http_body_util::Full<bytes::Bytes>> {
+    http::Request::get(path)
+        .header(http::header::HOST, "example.com")
+        .header(http::header::AUTHORIZATION, auth)
+        .body(Default::default())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn bearer_auth_is_env_substituted() {
+    // cargo sets CARGO_PKG_NAME for test binaries, so the test never has to write the environment
+    let upstream = echo_auth_upstream().await;
+    let addr = testing::spawn(forward_with_auth(upstream, r#"{ bearer: "${env.CARGO_PKG_NAME}" }"#)).await;
+
+    let resp = testing::get(addr, "/").await;
+    assert_eq!(resp.body(), concat!("Bearer ", env!("CARGO_PKG_NAME")));
+
+    // client-supplied credentials win unless `override` is set
+    let resp = testing::send(addr, get_with_auth("/", "Bearer mine")).await;
+    assert_eq!(resp.body(), "Bearer mine");
+}
+
+#[tokio::test]
+async fn basic_auth_can_override_client_header() {
+    let upstream = echo_auth_upstream().await;
+    let addr = testing::spawn(forward_with_auth(
+        upstream,
+        "{ basic: { username: alice, password: open-sesame }, override: true }",
+    )).await;
+
+    let resp = testing::send(addr, get_with_auth("/", "Bearer mine")).await;
+    assert_eq!(resp.body(), "Basic YWxpY2U6b3Blbi1zZXNhbWU=");
+}
+
+#[tokio::test]
+async fn stripped_client_authorization_is_replaced_by_auth() {
+    let upstream = echo_auth_upstream().await;
+    let addr = testing::spawn(testing::service(&format!(
+        "handler: forward\ntarget: {{ scheme: http, host: 127.0.0.1, port: {} }}\nauth: {{ bearer: proxy-token }}\nstrip_request_headers: [authorization]\n",
+        upstream.port(),
+    ))).await;
+
+    let resp = testing::send(addr, get_with_auth("/", "Bearer mine")).await;
+    assert_eq!(resp.body(), "Bearer proxy-token");
+    let resp = testing::get(addr, "/").await;
+    assert_eq!(resp.body(), "Bearer proxy-token");
+}
+
+#[test]
+fn missing_env_var_is_a_config_error() {
+    let cfg: crate::config::service::Service = serde_yaml::from_str(
+        "handler: forward\ntarget: { scheme: http, host: a, port: 80 }\nauth: { bearer: \"${env.OXIDASE_TEST_UNSET_VAR}\" }\n",
+    ).unwrap();
+    let err = crate::build::service::build_service(&cfg, std::path::Path::new(".")).unwrap_err();
+    assert!(err.to_string().contains("OXIDASE_TEST_UNSET_VAR"));
+}
+
+/// Upstream that echoes the request body, prefixed with the `Expect` header it saw.
+async fn echo_body_upstream() -> std::net::SocketAddr {
+    use http_body_util::BodyExt;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let svc = hyper::service::service_fn(|req: http::Request<hyper::body::Incoming>| async move {
+                let expect = req.headers().get(http::header::EXPECT).cloned();
+                let body = req.into_body().collect().await?.to_bytes();
+                let echoed = format!("expect={expect:?};{}", String::from_utf8_lossy(&body));
+                Ok::<_, hyper::Error>(http::Response::new(http_body_util::Full::<bytes::Bytes>::from(echoed)))
+            });
+            tokio::spawn(hyper::server::conn::http1::Builder::new()
+                .serve_connection(hyper_util::rt::TokioIo::new(stream), svc));
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn expect_continue_is_answered_before_the_body_is_sent() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let upstream = echo_body_upstream().await;
+    let addr = testing::spawn(testing::service(&format!(
+        "handler: forward\ntarget: {{ scheme: http, host: 127.0.0.1, port: {} }}\n",
+        upstream.port(),
+    ))).await;
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream.write_all(
+        b"POST /upload HTTP/1.1\r\nHost: example.com\r\nContent-Length: 5\r\nExpect: 100-continue\r\nConnection: close\r\n\r\n",
+    ).await.unwrap();
+
+    // the client holds the body back until it sees the interim response
+    let mut buf = [0u8; 64];
+    let n = tokio::time::timeout(std::time::Duration::from_secs(5), stream.read(&mut buf))
+        .await
+        .expect("stalled waiting for 100 Continue")
+        .unwrap();
+    assert!(String::from_utf8_lossy(&buf[..n]).starts_with("HTTP/1.1 100 Continue"));
+
+    stream.write_all(b"hello").await.unwrap();
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).await.unwrap();
+    let rest = String::from_utf8_lossy(&rest);
+    assert!(rest.contains("200 OK"), "{rest}");
+    assert!(rest.ends_with("expect=None;hello"), "{rest}");
+}
+
+/// Upstream that replies with a body followed by a `grpc-status` trailer,
+/// and echoes the request's `x-checksum` trailer back as a header.
+async fn trailer_upstream() -> std::net::SocketAddr {
+    use http_body_util::{BodyExt, Full};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let svc = hyper::service::service_fn(|req: http::Request<hyper::body::Incoming>| async move {
+                let collected = req.into_body().collect().await?;
+                let checksum = collected
+                    .trailers()
+                    .and_then(|t| t.get("x-checksum").cloned())
+                    .unwrap_or(http::HeaderValue::from_static("none"));
+
+                let mut trailers = http::HeaderMap::new();
+                trailers.insert("grpc-status", http::HeaderValue::from_static("0"));
+                let body = Full::<bytes::Bytes>::from("payload")
+                    .with_trailers(async move { Some(Ok(trailers)) });
+                let resp = http::Response::builder()
+                    .header("x-request-checksum", checksum)
+                    .header(http::header::TRANSFER_ENCODING, "chunked")
+                    .header(http::header::TRAILER, "grpc-status")
+                    .body(body)
+                    .unwrap();
+                Ok::<_, hyper::Error>(resp)
+            });
+            tokio::spawn(hyper::server::conn::http1::Builder::new()
+                .serve_connection(hyper_util::rt::TokioIo::new(stream), svc));
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn trailers_are_relayed_both_ways() {
+    use http_body_util::{BodyExt, Full};
+
+    let upstream = trailer_upstream().await;
+    let addr = testing::spawn(testing::service(&format!(
+        "handler: forward\ntarget: {{ scheme: http, host: 127.0.0.1, port: {} }}\n",
+        upstream.port(),
+    ))).await;
+
+    let mut req_trailers = http::HeaderMap::new();
+    req_trailers.insert("x-checksum", http::HeaderValue::from_static("abc123"));
+    let req = http::Request::post("/rpc")
+        .header(http::header::HOST, "example.com")
+        .header(http::header::TE, "trailers")
+        .header(http::header::TRANSFER_ENCODING, "chunked")
+        .header(http::header::TRAILER, "x-checksum")
+        .body(Full::<bytes::Bytes>::from("request").with_trailers(async move { Some(Ok(req_trailers)) }))
+        .unwrap();
+
+    let resp = testing::send_streaming(addr, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["x-request-checksum"], "abc123");
+    let collected = resp.into_body().collect().await.unwrap();
+    assert_eq!(collected.trailers().unwrap()["grpc-status"], "0");
+    assert_eq!(collected.to_bytes(), "payload");
+}
+
+async fn status_upstream(status: u16) -> std::net::SocketAddr {
+    testing::spawn(testing::service(&format!(
+        "handler: router\nrules:\n  - ops:\n      - respond: {{ status: {status}, body: \"{status}\" }}\n"
+    ))).await
+}
+
+#[tokio::test]
+async fn retry_statuses_fail_over_to_the_next_target() {
+    let unavailable = status_upstream(503).await;
+    let healthy = status_upstream(200).await;
+    let addr = testing::spawn(testing::service(&format!(r#"
+handler: forward
+target: {{ scheme: http, host: 127.0.0.1, port: {} }}
+failover_targets:
+  - {{ scheme: http, host: 127.0.0.1, port: {} }}
+retry_statuses: [503]
+"#, unavailable.port(), healthy.port()))).await;
+
+    let resp = testing::get(addr, "/").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body(), "200");
+
+    // non-idempotent requests are never replayed
+    let req = http::Request::post("/")
+        .header(http::header::HOST, "example.com")
+        .body(http_body_util::Full::from("payload"))
+        .unwrap();
+    assert_eq!(testing::send(addr, req).await.status(), 503);
+}
+
+#[tokio::test]
+async fn last_target_response_is_returned_when_all_fail() {
+    let first = status_upstream(503).await;
+    let second = status_upstream(502).await;
+    let addr = testing::spawn(testing::service(&format!(r#"
+handler: forward
+target: {{ scheme: http, host: 127.0.0.1, port: {} }}
+failover_targets:
+  - {{ scheme: http, host: 127.0.0.1, port: {} }}
+retry_statuses: [502, 503]
+"#, first.port(), second.port()))).await;
+
+    assert_eq!(testing::get(addr, "/").await.status(), 502);
+}
+
+#[tokio::test]
+async fn idle_connections_are_reaped_after_pool_idle_timeout() {
+    let upstream = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - respond: { status: 200, body: ok }
+"#)).await;
+    let svc = testing::service(&format!(
+        "handler: forward\ntarget: {{ scheme: http, host: 127.0.0.1, port: {} }}\npool_idle_timeout_ms: 100\n",
+        upstream.port(),
+    ));
+    let crate::build::service::LoadedService::Forward(fw) = &svc else { unreachable!() };
+    let pool = fw.pool.clone();
+    let addr = testing::spawn(svc).await;
+
+    assert_eq!(testing::get(addr, "/").await.body().as_ref(), b"ok");
+    assert_eq!((pool.idle(), pool.active()), (1, 0));
+
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    assert_eq!((pool.open(), pool.idle()), (0, 0));
+}
+
+#[tokio::test]
+async fn listed_headers_are_stripped_in_both_directions() {
+    let upstream = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - respond:
+          status: 200
+          body: "internal=${header.x-internal-user|default(none)};debug=${header.x-debug|default(none)};keep=${header.x-keep}"
+          headers:
+            x-internal-trace: abc
+            X-Backend: node-3
+            x-public: yes
+"#)).await;
+    let addr = testing::spawn(testing::service(&format!(r#"
+handler: forward
+target: {{ scheme: http, host: 127.0.0.1, port: {} }}
+strip_request_headers: [ "X-Internal-*", x-debug ]
+strip_response_headers: [ "x-internal-*", x-backend ]
+"#, upstream.port()))).await;
+
+    let req = http::Request::get("/")
+        .header(http::header::HOST, "example.com")
+        .header("x-internal-user", "root")
+        .header("x-debug", "1")
+        .header("x-keep", "1")
+        .body(http_body_util::Full::new(bytes::Bytes::new()))
+        .unwrap();
+    let resp = testing::send(addr, req).await;
+    assert_eq!(resp.body().as_ref(), b"internal=none;debug=none;keep=1");
+    assert!(resp.headers().get("x-internal-trace").is_none());
+    assert!(resp.headers().get("x-backend").is_none());
+    assert_eq!(resp.headers()["x-public"], "yes");
+}
+
+/// Minimal HTTP CONNECT proxy that records each CONNECT request head.
+async fn connect_proxy() -> (std::net::SocketAddr, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let log = seen.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut client, _) = listener.accept().await.unwrap();
+            let log = log.clone();
+            tokio::spawn(async move {
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                    let mut byte = [0u8];
+                    if client.read_exact(&mut byte).await.is_err() { return; }
+                    head.push(byte[0]);
+                }
+                let head = String::from_utf8_lossy(&head).into_owned();
+                let target = head.split_whitespace().nth(1).unwrap_or_default().to_string();
+                log.lock().unwrap().push(head);
+                let mut upstream = tokio::net::TcpStream::connect(target).await.unwrap();
+                client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").await.unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+            });
+        }
+    });
+    (addr, seen)
+}
+
+/// Minimal SOCKSv5 proxy accepting only `user`/`secret`, for IPv4 destinations.
+async fn socks5_proxy() -> (std::net::SocketAddr, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let tunnels = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let count = tunnels.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut c, _) = listener.accept().await.unwrap();
+            let count = count.clone();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 2];
+                c.read_exact(&mut buf).await.unwrap();
+                let mut methods = vec![0u8; buf[1] as usize];
+                c.read_exact(&mut methods).await.unwrap();
+                assert!(methods.contains(&2), "client must offer user/pass auth");
+                c.write_all(&[5, 2]).await.unwrap();
+
+                let read_field = async |c: &mut tokio::net::TcpStream| {
+                    let mut len = [0u8];
+                    c.read_exact(&mut len).await.unwrap();
+                    let mut field = vec![0u8; len[0] as usize];
+                    c.read_exact(&mut field).await.unwrap();
+                    String::from_utf8(field).unwrap()
+                };
+                let mut ver = [0u8];
+                c.read_exact(&mut ver).await.unwrap();
+                let (user, pass) = (read_field(&mut c).await, read_field(&mut c).await);
+                let ok = user == "user" && pass == "secret";
+                c.write_all(&[1, if ok { 0 } else { 1 }]).await.unwrap();
+                if !ok { return; }
+
+                let mut req = [0u8; 4];
+                c.read_exact(&mut req).await.unwrap();
+                assert_eq!(req[3], 1, "expected an IPv4 destination");
+                let mut dst = [0u8; 6];
+                c.read_exact(&mut dst).await.unwrap();
+                let target = std::net::SocketAddr::from((
+                    [dst[0], dst[1], dst[2], dst[3]],
+                    u16::from_be_bytes([dst[4], dst[5]]),
+                ));
+                let mut upstream = tokio::net::TcpStream::connect(target).await.unwrap();
+                c.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await.unwrap();
+                count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let _ = tokio::io::copy_bidirectional(&mut c, &mut upstream).await;
+            });
+        }
+    });
+    (addr, tunnels)
+}
+
+#[tokio::test]
+async fn requests_traverse_http_connect_proxy_with_auth() {
+    let upstream = echo_auth_upstream().await;
+    let (proxy, seen) = connect_proxy().await;
+    let addr = testing::spawn(testing::service(&format!(r#"
+handler: forward
+target: {{ scheme: http, host: 127.0.0.1, port: {} }}
+upstream_proxy: "http://user:p%40ss@{proxy}"
+"#, upstream.port()))).await;
+
+    assert_eq!(testing::get(addr, "/").await.status(), 200);
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.len(), 1);
+    assert!(seen[0].starts_with(&format!("CONNECT 127.0.0.1:{} ", upstream.port())), "{}", seen[0]);
+    // base64("user:p@ss")
+    assert!(seen[0].to_ascii_lowercase().contains("proxy-authorization: basic dxnlcjpwqhnz"), "{}", seen[0]);
+}
+
+#[tokio::test]
+async fn requests_traverse_socks5_proxy_with_auth() {
+    let upstream = echo_auth_upstream().await;
+    let (proxy, tunnels) = socks5_proxy().await;
+    let addr = testing::spawn(testing::service(&format!(r#"
+handler: forward
+target: {{ scheme: http, host: 127.0.0.1, port: {} }}
+upstream_proxy: "socks5://user:secret@{proxy}"
+"#, upstream.port()))).await;
+
+    assert_eq!(testing::get(addr, "/").await.status(), 200);
+    assert_eq!(tunnels.load(std::sync::atomic::Ordering::SeqCst), 1);
+}
+
+/// Upstream that answers with a fixed gzip body when the client accepts gzip.
+async fn gzip_upstream() -> std::net::SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let svc = hyper::service::service_fn(|req: http::Request<hyper::body::Incoming>| async move {
+                let accepts_gzip = req.headers().get(http::header::ACCEPT_ENCODING).is_some_and(|v| v == "gzip");
+                let resp = if accepts_gzip {
+                    http::Response::builder()
+                        .header(http::header::CONTENT_ENCODING, "gzip")
+                        .body(http_body_util::Full::new(bytes::Bytes::from_static(GZIP_HELLO)))
+                } else {
+                    http::Response::builder().status(406).body(http_body_util::Full::default())
+                };
+                Ok::<_, hyper::Error>(resp.unwrap())
+            });
+            tokio::spawn(hyper::server::conn::http1::Builder::new()
+                .serve_connection(hyper_util::rt::TokioIo::new(stream), svc));
+        }
+    });
+    addr
+}
+
+/// `gzip("hello gzip")` with a zero mtime.
+const GZIP_HELLO: &[u8] = &[
+    31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 203, 72, 205, 201, 201, 87, 72, 175, 202, 44, 0, 0, 25, 106, 210, 223, 10, 0, 0, 0,
+];
+
+#[tokio::test]
+async fn compressed_responses_pass_through_untouched() {
+    let upstream = gzip_upstream().await;
+    let addr = testing::spawn(testing::service(&format!(r#"
+handler: forward
+target: {{ scheme: http, host: 127.0.0.1, port: {} }}
+"#, upstream.port()))).await;
+
+    let req = http::Request::get("/")
+        .header(http::header::HOST, "example.com")
+        .header(http::header::ACCEPT_ENCODING, "gzip")
+        .body(http_body_util::Full::default())
+        .unwrap();
+    let resp = testing::send(addr, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()[http::header::CONTENT_ENCODING], "gzip");
+    assert_eq!(resp.body().as_ref(), GZIP_HELLO);
+}
+
+#[tokio::test]
+async fn max_concurrent_rejects_or_queues_excess_requests() {
+    let upstream = testing::spawn(testing::service(r#"
+handler: router
+enable_testing_ops: true
+rules:
+  - ops:
+      - delay: { ms: 200 }
+      - respond: { status: 200 }
+"#)).await;
+    let forward = |queue: bool| testing::service(&format!(r#"
+handler: forward
+target: {{ scheme: http, host: 127.0.0.1, port: {} }}
+max_concurrent: 1
+queue_when_full: {queue}
+"#, upstream.port()));
+
+    let addr = testing::spawn(forward(false)).await;
+    let (a, b) = tokio::join!(testing::get(addr, "/"), async {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        testing::get(addr, "/").await
+    });
+    assert_eq!(a.status(), 200);
+    assert_eq!(b.status(), 503);
+
+    let addr = testing::spawn(forward(true)).await;
+    let started = std::time::Instant::now();
+    let (a, b) = tokio::join!(testing::get(addr, "/"), testing::get(addr, "/"));
+    assert_eq!((a.status(), b.status()), (http::StatusCode::OK, http::StatusCode::OK));
+    assert!(started.elapsed() >= std::time::Duration::from_millis(400), "{:?}", started.elapsed());
+}
+
+#[tokio::test]
+async fn max_response_bytes_turns_oversized_bodies_into_502() {
+    let upstream = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - when: { path: "/small" }
+    ops:
+      - respond: { status: 200, body: "0123456789" }
+  - ops:
+      - respond: { status: 200, body: "0123456789abcdef" }
+"#)).await;
+    // a chunked body has no Content-Length to reject up front
+    let chunked = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let chunked_port = chunked.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        while let Ok((mut stream, _)) = chunked.accept().await {
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).await;
+            let _ = stream.write_all(
+                b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n8\r\n01234567\r\n8\r\n89abcdef\r\n0\r\n\r\n",
+            ).await;
+        }
+    });
+    let forward = |port: u16| testing::service(&format!(r#"
+handler: forward
+target: {{ scheme: http, host: 127.0.0.1, port: {port} }}
+max_response_bytes: 12
+"#));
+
+    let addr = testing::spawn(forward(upstream.port())).await;
+    let resp = testing::get(addr, "/small").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body().as_ref(), b"0123456789");
+    assert_eq!(testing::get(addr, "/big").await.status(), 502);
+
+    let addr = testing::spawn(forward(chunked_port)).await;
+    assert_eq!(testing::get(addr, "/").await.status(), 502);
+}
+
+#[tokio::test]
+async fn wait_for_ready_answers_503_until_first_successful_probe() {
+    // reserve a port with nothing listening on it yet
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let addr = testing::spawn(testing::service(&format!(r#"
+handler: forward
+target: {{ scheme: http, host: 127.0.0.1, port: {port} }}
+wait_for_ready: {{ path: /healthz, interval_ms: 20 }}
+"#))).await;
+
+    let resp = testing::get(addr, "/").await;
+    assert_eq!(resp.status(), 503);
+    assert_eq!(resp.body().as_ref(), b"upstream not ready");
+
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+    tokio::spawn(crate::http_server::serve(listener, None, std::sync::Arc::new(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - respond: { status: 200, body: "${path}" }
+"#)), Default::default(), Default::default(), Vec::new()));
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    let resp = loop {
+        let resp = testing::get(addr, "/app").await;
+        if resp.status() != 503 || std::time::Instant::now() > deadline {
+            break resp;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    };
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body().as_ref(), b"/app");
+}
+
+#[tokio::test]
+async fn wait_for_ready_gives_up_on_a_probe_that_never_answers() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        // the first connection is accepted and never answered; later ones are served
+        let (_stalled, _) = listener.accept().await.unwrap();
+        crate::http_server::serve(listener, None, std::sync::Arc::new(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - respond: { status: 200 }
+"#)), Default::default(), Default::default(), Vec::new()).await;
+    });
+    let addr = testing::spawn(testing::service(&format!(r#"
+handler: forward
+target: {{ scheme: http, host: 127.0.0.1, port: {port} }}
+wait_for_ready: {{ path: /healthz, interval_ms: 50 }}
+"#))).await;
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    let resp = loop {
+        let resp = testing::get(addr, "/").await;
+        if resp.status() != 503 || std::time::Instant::now() > deadline {
+            break resp;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    };
+    assert_eq!(resp.status(), 200);
+}
+
+/// Resolver answering every name with the current address list, in order.
+#[derive(Clone, Default)]
+struct StubResolver(std::sync::Arc<std::sync::Mutex<Vec<std::net::SocketAddr>>>);
+
+impl StubResolver {
+    fn answer(&self, addrs: &[std::net::SocketAddr]) {
+        *self.0.lock().unwrap() = addrs.to_vec();
+    }
+}
+
+impl tower_service::Service<hyper_util::client::legacy::connect::dns::Name> for StubResolver {
+    type Response = std::vec::IntoIter<std::net::SocketAddr>;
+    type Error = std::io::Error;
+    type Future = std::future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _: hyper_util::client::legacy::connect::dns::Name) -> Self::Future {
+        std::future::ready(Ok(self.0.lock().unwrap().clone().into_iter()))
+    }
+}
+
+#[tokio::test]
+async fn dialer_falls_back_to_ipv4_when_ipv6_fails() {
+    use tower_service::Service;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    // 100::/64 is the IPv6 discard prefix: it either fails at once or never answers
+    let resolver = StubResolver::default();
+    resolver.answer(&[
+        format!("[100::1]:{port}").parse().unwrap(),
+        format!("127.0.0.1:{port}").parse().unwrap(),
+    ]);
+    let mut dialer = hyper_util::client::legacy::connect::HttpConnector::new_with_resolver(resolver);
+    let timeouts = crate::config::forward::Timeouts { happy_eyeballs_ms: Some(100), ..Default::default() };
+    super::pool::configure_dialer(&mut dialer, &timeouts);
+
+    let started = std::time::Instant::now();
+    let uri: hyper::Uri = format!("http://dual-stack.test:{port}/").parse().unwrap();
+    let conn = tokio::time::timeout(std::time::Duration::from_secs(2), dialer.call(uri)).await;
+    assert!(conn.expect("dial timed out").is_ok());
+    assert!(started.elapsed() < std::time::Duration::from_secs(1), "{:?}", started.elapsed());
+    assert!(listener.accept().await.is_ok());
+}
+
+#[tokio::test]
+async fn dns_answers_are_refreshed_after_the_interval() {
+    use tower_service::Service;
+
+    // the dialer takes the port from the URI, so the two "hosts" differ by IP only
+    let old = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = old.local_addr().unwrap().port();
+    let new = tokio::net::TcpListener::bind(("127.0.0.2", port)).await.unwrap();
+    let stub = StubResolver::default();
+    stub.answer(&[old.local_addr().unwrap()]);
+    let resolver = super::pool::RefreshingResolver::new(stub.clone(), Some(std::time::Duration::from_millis(100)));
+    let mut dialer = hyper_util::client::legacy::connect::HttpConnector::new_with_resolver(resolver);
+    super::pool::configure_dialer(&mut dialer, &Default::default());
+    let uri: hyper::Uri = format!("http://scaled.test:{port}/").parse().unwrap();
+
+    let peer = |conn: hyper_util::rt::TokioIo<tokio::net::TcpStream>| conn.inner().peer_addr().unwrap();
+    assert_eq!(peer(dialer.call(uri.clone()).await.unwrap()), old.local_addr().unwrap());
+    // the DNS record moves, but the cached answer is still fresh
+    stub.answer(&[new.local_addr().unwrap()]);
+    assert_eq!(peer(dialer.call(uri.clone()).await.unwrap()), old.local_addr().unwrap());
+
+    tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+    assert_eq!(peer(dialer.call(uri).await.unwrap()), new.local_addr().unwrap());
+}
+
+/// Upstream answering with the HTTP version of each request, over HTTP/2 only or HTTP/1.1 only.
+async fn version_upstream(h2: bool) -> std::net::SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let io = hyper_util::rt::TokioIo::new(stream);
+            let svc = hyper::service::service_fn(|req: http::Request<hyper::body::Incoming>| async move {
+                let version = format!("{:?}", req.version());
+                Ok::<_, hyper::Error>(http::Response::new(http_body_util::Full::<bytes::Bytes>::from(version)))
+            });
+            if h2 {
+                let builder = hyper::server::conn::http2::Builder::new(hyper_util::rt::TokioExecutor::new());
+                tokio::spawn(async move { builder.serve_connection(io, svc).await });
+            } else {
+                tokio::spawn(hyper::server::conn::http1::Builder::new().serve_connection(io, svc));
+            }
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn upstream_http_version_selects_the_client_protocol() {
+    let forward = |port: u16, version: &str| testing::service(&format!(
+        "handler: forward\ntarget: {{ scheme: http, host: 127.0.0.1, port: {port} }}\nupstream_http_version: {version}\n",
+    ));
+    let h1 = version_upstream(false).await;
+    let h2 = version_upstream(true).await;
+
+    for version in ["http1", "auto"] {
+        let addr = testing::spawn(forward(h1.port(), version)).await;
+        let resp = testing::get(addr, "/").await;
+        assert_eq!(resp.body().as_ref(), b"HTTP/1.1", "{version}");
+    }
+
+    let addr = testing::spawn(forward(h2.port(), "http2")).await;
+    assert_eq!(testing::get(addr, "/").await.body().as_ref(), b"HTTP/2.0");
+
+    // prior-knowledge HTTP/2 is what breaks h1-only backends
+    let addr = testing::spawn(forward(h1.port(), "http2")).await;
+    assert_eq!(testing::get(addr, "/").await.status(), 502);
+}
+
+#[tokio::test]
+async fn via_is_appended_in_both_directions() {
+    let upstream = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - when: { path: /chained }
+    ops:
+      - respond: { status: 200, body: "${header.via}", headers: { via: "1.0 backend" } }
+  - ops:
+      - respond: { status: 200, body: "${header.via}" }
+"#)).await;
+    let forward = |extra: &str| testing::service(&format!(
+        "handler: forward\ntarget: {{ scheme: http, host: 127.0.0.1, port: {} }}\n{extra}",
+        upstream.port(),
+    ));
+
+    let addr = testing::spawn(forward("")).await;
+    let resp = testing::get(addr, "/").await;
+    assert_eq!(resp.body().as_ref(), b"1.1 oxidase");
+    assert_eq!(resp.headers()[http::header::VIA], "1.1 oxidase");
+
+    // earlier hops stay in front of ours
+    let req = http::Request::get("/chained")
+        .header(http::header::HOST, "example.com")
+        .header(http::header::VIA, "1.1 edge")
+        .header(http::header::VIA, "1.0 cdn")
+        .body(http_body_util::Full::default())
+        .unwrap();
+    let resp = testing::send(addr, req).await;
+    assert_eq!(resp.body().as_ref(), b"1.1 edge, 1.0 cdn, 1.1 oxidase");
+    assert_eq!(resp.headers()[http::header::VIA], "1.0 backend, 1.1 oxidase");
+
+    let addr = testing::spawn(forward("via_pseudonym: gw-1\n")).await;
+    assert_eq!(testing::get(addr, "/").await.body().as_ref(), b"1.1 gw-1");
+
+    let addr = testing::spawn(forward("via: false\n")).await;
+    let resp = testing::get(addr, "/").await;
+    assert_eq!(resp.body().as_ref(), b"");
+    assert!(resp.headers().get(http::header::VIA).is_none());
+}
+
+/// Sends the head and half of a 10-byte body, then goes quiet without closing.
+async fn stalling_upstream() -> std::net::SocketAddr {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf).await;
+                stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nhello").await.unwrap();
+                tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+            });
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn idle_timeout_aborts_a_stalled_response_body() {
+    use http_body_util::{BodyExt, Full};
+
+    let upstream = stalling_upstream().await;
+    let forward = |extra: &str| testing::service(&format!(
+        "handler: forward\ntarget: {{ scheme: http, host: 127.0.0.1, port: {} }}\nidle_timeout_ms: 150\n{extra}",
+        upstream.port(),
+    ));
+
+    // streamed: the head is already out, so the connection is cut mid-body
+    let addr = testing::spawn(forward("")).await;
+    let req = http::Request::get("/").header(http::header::HOST, "example.com").body(Full::<bytes::Bytes>::default()).unwrap();
+    let resp = testing::send_streaming(addr, req).await;
+    assert_eq!(resp.status(), 200);
+    let started = std::time::Instant::now();
+    assert!(resp.into_body().collect().await.is_err());
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+    // buffered: nothing has been sent yet, so the client gets a 504
+    let addr = testing::spawn(forward("max_response_bytes: 1024\n")).await;
+    let resp = testing::get(addr, "/").await;
+    assert_eq!(resp.status(), 504);
+}
@@ -1,23 +1,92 @@
 pub mod r#static;
 pub mod forward;
 pub mod router;
+pub mod body;
 
-use hyper::{body, http};
-use http_body_util::Full;
+pub use body::RequestBody;
+
+use hyper::http;
+use http_body_util::{BodyExt, Full, combinators::BoxBody};
 use bytes::Bytes;
 use std::future::Future;
 use std::pin::Pin;
 
 use crate::build::service::LoadedService;
 
-pub type BoxResponseFuture<'a> = Pin<Box<dyn Future<Output = http::Response<Full<Bytes>>> + Send + 'a>>;
+/// Body of every handler response: buffered bodies go through [`full`], forwarded
+/// ones stream straight from the upstream (including trailers). Upstream failures
+/// mid-body surface as I/O errors, which makes the server drop the connection.
+pub type ResponseBody = BoxBody<Bytes, std::io::Error>;
+
+pub type BoxResponseFuture<'a> = Pin<Box<dyn Future<Output = http::Response<ResponseBody>> + Send + 'a>>;
+
+/// Wrap an in-memory body; serves as a request body too.
+pub fn full<E: 'static>(body: impl Into<Bytes>) -> BoxBody<Bytes, E> {
+    Full::new(body.into()).map_err(|never| match never {}).boxed()
+}
+
+/// Wrap an in-memory body that is handed out in frames of at most `chunk` bytes.
+pub fn chunked(body: Bytes, chunk: usize) -> ResponseBody {
+    Chunked { rest: body, chunk: chunk.max(1) }.map_err(|never| match never {}).boxed()
+}
+
+struct Chunked {
+    rest: Bytes,
+    chunk: usize,
+}
+
+impl hyper::body::Body for Chunked {
+    type Data = Bytes;
+    type Error = std::convert::Infallible;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<hyper::body::Frame<Bytes>, Self::Error>>> {
+        let this = self.get_mut();
+        if this.rest.is_empty() {
+            return std::task::Poll::Ready(None);
+        }
+        let n = this.chunk.min(this.rest.len());
+        std::task::Poll::Ready(Some(Ok(hyper::body::Frame::data(this.rest.split_to(n)))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.rest.is_empty()
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        hyper::body::SizeHint::with_exact(self.rest.len() as u64)
+    }
+}
+
+/// Wrap a body produced piece by piece; it ends once every sender is dropped.
+pub fn channel(rx: tokio::sync::mpsc::Receiver<Bytes>) -> ResponseBody {
+    Channel { rx }.map_err(|never| match never {}).boxed()
+}
+
+struct Channel {
+    rx: tokio::sync::mpsc::Receiver<Bytes>,
+}
+
+impl hyper::body::Body for Channel {
+    type Data = Bytes;
+    type Error = std::convert::Infallible;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<hyper::body::Frame<Bytes>, Self::Error>>> {
+        self.get_mut().rx.poll_recv(cx).map(|chunk| chunk.map(|b| Ok(hyper::body::Frame::data(b))))
+    }
+}
 
 pub trait ServiceHandler {
-    fn handle_request<'a>(&'a self, req: &'a mut http::Request<body::Incoming>) -> BoxResponseFuture<'a>;
+    fn handle_request<'a>(&'a self, req: &'a mut http::Request<RequestBody>) -> BoxResponseFuture<'a>;
 }
 
 impl ServiceHandler for LoadedService {
-    fn handle_request<'a>(&'a self, req: &'a mut http::Request<body::Incoming>) -> BoxResponseFuture<'a> {
+    fn handle_request<'a>(&'a self, req: &'a mut http::Request<RequestBody>) -> BoxResponseFuture<'a> {
         match self {
             LoadedService::Static(handler) => handler.handle_request(req),
             LoadedService::Router(handler) => handler.handle_request(req),
@@ -1,32 +1,94 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use hyper::{body, http};
-use percent_encoding::percent_decode_str;
+use bytes::Bytes;
+use hyper::http;
+
+use crate::handler::RequestBody;
+use crate::handler::body::{BufferedBody, peek_request_body};
+use indexmap::IndexMap;
+use percent_encoding::{AsciiSet, CONTROLS, percent_decode_str, utf8_percent_encode};
 
 use crate::config::http_method::HttpMethod;
+use crate::http_server::{ClientCert, ConnInfo};
 use crate::template::ValueProvider;
+use crate::util::http::{decode_path, multipart_boundary, multipart_field_names, parse_form, parse_host_header};
 
 #[derive(Debug, Clone)]
 pub struct RouterCtx {
     pub method: Option<HttpMethod>,
     pub scheme: Option<String>,
+    pub tls: bool,
+    pub sni: Option<String>,
+    pub client_cert: Option<Arc<ClientCert>>,
+    /// Client address from [`ConnInfo::remote_ip`].
+    pub remote_ip: Option<IpAddr>,
+    /// `origin`, `absolute`, `authority` or `asterisk`: how the request target was written.
+    pub target_form: &'static str,
     pub host: String,
     pub port: Option<u16>,
     pub path: String,
-    pub query: HashMap<String, Vec<String>>,
+    pub query: IndexMap<String, Vec<String>>,
     pub headers: HashMap<String, Vec<String>>,
-    pub cookies: HashMap<String, String>,
+    pub cookies: HashMap<String, Vec<String>>,
+    pub form: HashMap<String, String>,
+    /// Names of the `multipart/form-data` fields seen by [`scan_multipart`].
+    pub multipart: HashSet<String>,
     pub captures: HashMap<String, String>,
+    /// Head and body of the last `subrequest`, replayed as-is by `respond: { passthrough: true }`.
+    pub subresponse: Option<(http::response::Parts, Bytes)>,
+    /// When the request arrived; source of the `now.*` variables.
+    pub now: SystemTime,
+    /// Added to whatever response the router ends up returning.
+    pub response_headers: Vec<(http::HeaderName, http::HeaderValue)>,
 }
 
 impl ValueProvider for RouterCtx {
     fn get(&self, key: &str) -> Option<String> {
-        match key {
-            "method" => self.method.as_ref().map(|m| format!("{:?}", m).to_ascii_uppercase()),
+        self.builtin(key).unwrap_or_else(|| self.captures.get(key).cloned())
+    }
+}
+
+/// Whether `key` names a built-in variable, which shadows any capture of the same name.
+pub(crate) fn is_builtin_var(key: &str) -> bool {
+    RouterCtx::from_request(&http::Request::new(())).builtin(key).is_some()
+}
+
+impl RouterCtx {
+    /// Value of a built-in variable, or `None` when `key` is not one and is looked up
+    /// among the captures instead.
+    fn builtin(&self, key: &str) -> Option<Option<String>> {
+        let val = match key {
+            "method" => self.method.as_ref().map(|m| m.as_str().to_string()),
             "scheme" => self.scheme.clone(),
+            "tls" => Some(self.tls.to_string()),
+            "sni" => self.sni.clone(),
+            "remote.ip" => self.remote_ip.map(|ip| ip.to_string()),
+            // unset without a verified client certificate; SANs are comma-separated
+            "cert.cn" => self.client_cert.as_ref().and_then(|c| c.cn.clone()),
+            "cert.san" => self.client_cert.as_ref().map(|c| c.san.join(",")),
+            "cert.fingerprint" => self.client_cert.as_ref().map(|c| c.fingerprint.clone()),
+            "target_form" => Some(self.target_form.to_string()),
             "host" => Some(self.host.clone()),
             "port" => self.port.map(|p| p.to_string()),
             "path" => Some(self.path.clone()),
+            "path_decoded" => decode_path(&self.path),
+            // UTC; weekday runs from 1 (Monday) to 7 (Sunday)
+            "now.unix" => Some(self.unix_secs().to_string()),
+            "now.hour" => Some((self.unix_secs() / 3600 % 24).to_string()),
+            "now.weekday" => Some(((self.unix_secs() / 86_400 + 3) % 7 + 1).to_string()),
+            "query_string" => Some(query_string(self)),
+            // counts of every value, so repeated keys count once per occurrence
+            "query_count" => Some(self.query.values().map(Vec::len).sum::<usize>().to_string()),
+            "header_count" => Some(self.headers.values().map(Vec::len).sum::<usize>().to_string()),
+            "cookie_count" => Some(self.cookies.values().map(Vec::len).sum::<usize>().to_string()),
+            // debugging aid: every capture so far as a JSON object with sorted keys
+            "captures_json" => {
+                let sorted: BTreeMap<_, _> = self.captures.iter().collect();
+                serde_json::to_string(&sorted).ok()
+            }
             v if v.starts_with("header.") => {
                 let name = v.trim_start_matches("header.").to_ascii_lowercase();
                 self.headers.get(&name).and_then(|vals| vals.get(0)).cloned()
@@ -37,79 +99,151 @@ impl ValueProvider for RouterCtx {
             }
             v if v.starts_with("cookie.") => {
                 let k = v.trim_start_matches("cookie.");
-                self.cookies.get(k).cloned()
+                self.cookies.get(k).and_then(|vals| vals.first()).cloned()
             }
-            _ => self.captures.get(key).cloned(),
-        }
+            v if v.starts_with("multipart.has.") => {
+                Some(self.multipart.contains(v.trim_start_matches("multipart.has.")).to_string())
+            }
+            v if v.starts_with("form.") => {
+                let k = v.trim_start_matches("form.");
+                self.form.get(k).cloned()
+            }
+            _ => return None,
+        };
+        Some(val)
     }
-}
 
-impl RouterCtx {
-    pub fn from_request(req: &http::Request<body::Incoming>) -> Self {
-        let method = HttpMethod::try_from(req.method().as_str()).ok();
-        let scheme = req.uri().scheme_str().map(|s| s.to_ascii_lowercase());
+    pub fn from_request<B>(req: &http::Request<B>) -> Self {
+        let conn = req.extensions().get::<ConnInfo>();
+        let tls = conn.is_some_and(|ci| ci.is_tls);
+        let sni = conn.and_then(|ci| ci.sni.clone());
+        let remote_ip = conn.map(|ci| ci.remote_ip);
+        let client_cert = conn.and_then(|ci| ci.client_cert.clone());
+        let method = Some(HttpMethod::from(req.method().as_str()));
+        // origin-form requests carry no scheme; derive it from the connection
+        let scheme = match req.uri().scheme_str() {
+            Some(s) => Some(s.to_ascii_lowercase()),
+            None => conn.map(|_| if tls { "https" } else { "http" }.to_string()),
+        };
         let (host, port) = parse_host_and_port(req);
+        // without an explicit port, the request reached us on the listener's port
+        let port = port.or_else(|| conn.map(|ci| ci.local_addr.port()));
         let path = req.uri().path().to_string();
         let query = parse_query(req.uri().query());
         let headers = collect_headers(req);
-        let cookies = parse_cookies(headers.get("cookie"));
+        let cookies = parse_cookies(headers.get("cookie"), true);
+        let form = parse_form_body(req);
         RouterCtx {
             method,
             scheme,
+            tls,
+            sni,
+            client_cert,
+            remote_ip,
+            target_form: target_form(req.method(), req.uri()),
             host,
             port,
             path,
             query,
             headers,
             cookies,
+            form,
+            multipart: HashSet::new(),
+            subresponse: None,
             captures: HashMap::new(),
+            now: SystemTime::now(),
+            response_headers: Vec::new(),
+        }
+    }
+
+    pub fn unix_secs(&self) -> u64 {
+        self.now.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+    }
+
+    /// Re-read cookies from the `Cookie` header keeping values exactly as sent.
+    pub fn with_raw_cookies(mut self) -> Self {
+        self.cookies = parse_cookies(self.headers.get("cookie"), false);
+        self
+    }
+
+    /// Sort query pairs by key, and values within each key, for a canonical query string.
+    pub fn sort_query(&mut self) {
+        self.query.sort_keys();
+        for vals in self.query.values_mut() {
+            vals.sort();
         }
     }
 }
 
-pub fn apply_ctx_to_request(ctx: &RouterCtx, req: &mut http::Request<body::Incoming>) {
-    if !ctx.host.is_empty() {
+pub fn apply_ctx_to_request(ctx: &RouterCtx, req: &mut http::Request<RequestBody>, rewrite_host: bool) {
+    if rewrite_host && !ctx.host.is_empty() {
         if let Ok(val) = http::HeaderValue::from_str(&ctx.host) {
             req.headers_mut().insert(http::header::HOST, val);
         }
     }
 
+    if let Ok(new_uri) = request_target(ctx).parse() {
+        *req.uri_mut() = new_uri;
+    }
+}
+
+/// Rebuild the origin-form request target (path + query) from the context.
+/// Query pairs are emitted in their stored order.
+pub fn request_target(ctx: &RouterCtx) -> String {
     let mut uri = ctx.path.clone();
     if !ctx.query.is_empty() {
-        let mut parts = Vec::new();
-        for (k, vals) in &ctx.query {
-            for v in vals {
-                parts.push(format!("{k}={v}"));
-            }
-        }
         uri.push('?');
-        uri.push_str(&parts.join("&"));
+        uri.push_str(&query_string(ctx));
     }
-    if let Ok(new_uri) = uri.parse() {
-        *req.uri_mut() = new_uri;
+    uri
+}
+
+/// Bytes escaped in query values. `%` is kept as is: values read from the request
+/// are stored with their original encoding.
+const QUERY_VALUE: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'&').add(b'<').add(b'>').add(b'`');
+const QUERY_KEY: &AsciiSet = &QUERY_VALUE.add(b'=');
+
+/// Serialize the context query back into `k=v&k=v` form, without the leading `?`.
+/// Characters that cannot appear in a query component, such as spaces set by ops, are percent-encoded.
+pub fn query_string(ctx: &RouterCtx) -> String {
+    let mut parts = Vec::new();
+    for (k, vals) in &ctx.query {
+        for v in vals {
+            parts.push(format!("{}={}", utf8_percent_encode(k, QUERY_KEY), utf8_percent_encode(v, QUERY_VALUE)));
+        }
     }
+    parts.join("&")
 }
 
-fn parse_host_and_port(req: &http::Request<body::Incoming>) -> (String, Option<u16>) {
+/// The request-target form (RFC 9112, section 3.2) of `uri`.
+fn target_form(method: &http::Method, uri: &http::Uri) -> &'static str {
+    if uri.scheme().is_some() {
+        "absolute"
+    } else if method == http::Method::CONNECT && uri.authority().is_some() {
+        "authority"
+    } else if uri.path() == "*" {
+        "asterisk"
+    } else {
+        "origin"
+    }
+}
+
+/// Host and port of the request: absolute- and authority-form targets carry their
+/// own, which take precedence over the `Host` header.
+fn parse_host_and_port<B>(req: &http::Request<B>) -> (String, Option<u16>) {
     if let Some(host) = req.uri().host() {
         let port = req.uri().port_u16();
         return (host.to_string(), port);
     }
-    if let Some(host_header) = req.headers().get(http::header::HOST) {
-        if let Ok(hs) = host_header.to_str() {
-            if let Some((h, p)) = hs.split_once(':') {
-                if let Ok(port) = p.parse::<u16>() {
-                    return (h.to_string(), Some(port));
-                }
-            }
-            return (hs.to_string(), None);
-        }
-    }
-    ("".into(), None)
+    req.headers()
+        .get(http::header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_host_header)
+        .unwrap_or_default()
 }
 
-fn parse_query(q: Option<&str>) -> HashMap<String, Vec<String>> {
-    let mut out: HashMap<String, Vec<String>> = HashMap::new();
+fn parse_query(q: Option<&str>) -> IndexMap<String, Vec<String>> {
+    let mut out: IndexMap<String, Vec<String>> = IndexMap::new();
     if let Some(qs) = q {
         for pair in qs.split('&') {
             if pair.is_empty() { continue; }
@@ -122,7 +256,7 @@ fn parse_query(q: Option<&str>) -> HashMap<String, Vec<String>> {
     out
 }
 
-fn collect_headers(req: &http::Request<body::Incoming>) -> HashMap<String, Vec<String>> {
+fn collect_headers<B>(req: &http::Request<B>) -> HashMap<String, Vec<String>> {
     let mut map: HashMap<String, Vec<String>> = HashMap::new();
     for (name, value) in req.headers() {
         let key = name.as_str().to_ascii_lowercase();
@@ -133,8 +267,45 @@ fn collect_headers(req: &http::Request<body::Incoming>) -> HashMap<String, Vec<S
     map
 }
 
-fn parse_cookies(cookies: Option<&Vec<String>>) -> HashMap<String, String> {
+/// Fields of a urlencoded form body; empty unless the body was buffered.
+fn parse_form_body<B>(req: &http::Request<B>) -> HashMap<String, String> {
+    let is_form = req
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/x-www-form-urlencoded"));
     let mut out = HashMap::new();
+    if let (true, Some(BufferedBody(bytes))) = (is_form, req.extensions().get::<BufferedBody>()) {
+        for (k, v) in parse_form(&String::from_utf8_lossy(bytes)) {
+            out.entry(k).or_insert(v);
+        }
+    }
+    out
+}
+
+/// How much of a streamed multipart body is read looking for part headers.
+const MULTIPART_SCAN_BYTES: usize = 64 * 1024;
+
+/// Field names of a `multipart/form-data` request, read from part headers only.
+/// Streamed bodies are scanned up to [`MULTIPART_SCAN_BYTES`] and re-attached intact,
+/// so fields whose headers start past that point are not seen.
+pub async fn scan_multipart(req: &mut http::Request<RequestBody>) -> Result<HashSet<String>, hyper::Error> {
+    let boundary = req
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(multipart_boundary);
+    let Some(boundary) = boundary else { return Ok(HashSet::new()) };
+    let scanned = match req.extensions().get::<BufferedBody>() {
+        Some(BufferedBody(bytes)) => bytes.clone(),
+        None => peek_request_body(req, MULTIPART_SCAN_BYTES).await?,
+    };
+    Ok(multipart_field_names(&scanned, &boundary).into_iter().collect())
+}
+
+pub(super) fn parse_cookies(cookies: Option<&Vec<String>>, decode: bool) -> HashMap<String, Vec<String>> {
+    let mut out: HashMap<String, Vec<String>> = HashMap::new();
     if let Some(list) = cookies {
         for raw in list {
             for part in raw.split(';') {
@@ -142,28 +313,15 @@ fn parse_cookies(cookies: Option<&Vec<String>>) -> HashMap<String, String> {
                 if trimmed.is_empty() { continue; }
                 if let Some((k, v)) = trimmed.split_once('=') {
                     let key = k.trim();
-                    let val = percent_decode_str(v.trim()).decode_utf8_lossy().to_string();
-                    out.insert(key.to_string(), val);
+                    let val = if decode {
+                        percent_decode_str(v.trim()).decode_utf8_lossy().to_string()
+                    } else {
+                        v.trim().to_string()
+                    };
+                    out.entry(key.to_string()).or_default().push(val);
                 }
             }
         }
     }
     out
 }
-
-impl TryFrom<&str> for HttpMethod {
-    type Error = ();
-
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value {
-            "GET" => Ok(HttpMethod::Get),
-            "POST" => Ok(HttpMethod::Post),
-            "PUT" => Ok(HttpMethod::Put),
-            "PATCH" => Ok(HttpMethod::Patch),
-            "DELETE" => Ok(HttpMethod::Delete),
-            "HEAD" => Ok(HttpMethod::Head),
-            "OPTIONS" => Ok(HttpMethod::Options),
-            _ => Err(()),
-        }
-    }
-}
@@ -1,5 +1,6 @@
 use crate::build::router::CompiledRouterMatch;
 use crate::config::router::r#match::Scheme;
+use crate::util::http::decode_path;
 
 use super::ctx::RouterCtx;
 
@@ -9,26 +10,44 @@ pub enum MatchResult {
     NoMatch,
 }
 
+/// A capture whose filters grow it past `max_len` bytes fails the match.
 pub fn matches_rule(
     m: &CompiledRouterMatch,
     ctx: &mut RouterCtx,
+    max_len: usize,
 ) -> MatchResult {
     if let Some(host_pat) = &m.host {
         if !host_pat.is_match(&ctx.host) {
             return MatchResult::NoMatch;
         }
-        if let Some(caps) = host_pat.captures_map(&ctx.host) {
-            ctx.captures.extend(caps);
-        }
+        let Ok(caps) = host_pat.captures_map(&ctx.host, max_len) else {
+            return MatchResult::NoMatch;
+        };
+        ctx.captures.extend(caps.into_iter().flatten());
     }
 
     if let Some(path_pat) = &m.path {
         if !path_pat.is_match(&ctx.path) {
             return MatchResult::NoMatch;
         }
-        if let Some(caps) = path_pat.captures_map(&ctx.path) {
-            ctx.captures.extend(caps);
+        let Ok(caps) = path_pat.captures_map(&ctx.path, max_len) else {
+            return MatchResult::NoMatch;
+        };
+        ctx.captures.extend(caps.into_iter().flatten());
+    }
+
+    if let Some(pat) = &m.path_decoded {
+        // a path that does not decode to UTF-8 cannot match
+        let Some(decoded) = decode_path(&ctx.path) else {
+            return MatchResult::NoMatch;
+        };
+        if !pat.is_match(&decoded) {
+            return MatchResult::NoMatch;
         }
+        let Ok(caps) = pat.captures_map(&decoded, max_len) else {
+            return MatchResult::NoMatch;
+        };
+        ctx.captures.extend(caps.into_iter().flatten());
     }
 
     if let Some(scheme) = &m.scheme {
@@ -53,42 +72,65 @@ pub fn matches_rule(
     }
 
     for h in &m.headers {
+        let Some(pattern) = &h.pattern else {
+            if ctx.headers.contains_key(&h.name) != h.not {
+                return MatchResult::NoMatch;
+            }
+            continue;
+        };
         let vals = ctx.headers.get(&h.name).cloned().unwrap_or_default();
-        let matched = vals.iter().any(|v| h.pattern.is_match(v));
+        let matched = vals.iter().any(|v| pattern.is_match(v));
         let ok = if h.not { !matched } else { matched };
         if !ok {
             return MatchResult::NoMatch;
         }
         if let Some(v) = vals.first() {
-            if let Some(caps) = h.pattern.captures_map(v) {
-                ctx.captures.extend(caps);
-            }
+            let Ok(caps) = pattern.captures_map(v, max_len) else {
+                return MatchResult::NoMatch;
+            };
+            ctx.captures.extend(caps.into_iter().flatten());
         }
     }
 
     for q in &m.queries {
+        let Some(pattern) = &q.pattern else {
+            if ctx.query.contains_key(&q.key) != q.not {
+                return MatchResult::NoMatch;
+            }
+            continue;
+        };
         let vals = ctx.query.get(&q.key).cloned().unwrap_or_default();
-        let matched = vals.iter().any(|v| q.pattern.is_match(v));
+        let matched = vals.iter().any(|v| pattern.is_match(v));
         let ok = if q.not { !matched } else { matched };
         if !ok {
             return MatchResult::NoMatch;
         }
         if let Some(v) = vals.first() {
-            if let Some(caps) = q.pattern.captures_map(v) {
-                ctx.captures.extend(caps);
-            }
+            let Ok(caps) = pattern.captures_map(v, max_len) else {
+                return MatchResult::NoMatch;
+            };
+            ctx.captures.extend(caps.into_iter().flatten());
         }
     }
 
     for c in &m.cookies {
-        let val = ctx.cookies.get(&c.name).cloned().unwrap_or_default();
-        let matched = c.pattern.is_match(&val);
-        let ok = if c.not { !matched } else { matched };
-        if !ok {
+        let Some(pattern) = &c.pattern else {
+            if ctx.cookies.contains_key(&c.name) != c.not {
+                return MatchResult::NoMatch;
+            }
+            continue;
+        };
+        // a repeated cookie matches if any of its values does; captures come from that value
+        let vals = ctx.cookies.get(&c.name).cloned().unwrap_or_default();
+        let matched = vals.iter().find(|v| pattern.is_match(v));
+        if matched.is_some() == c.not {
             return MatchResult::NoMatch;
         }
-        if let Some(caps) = c.pattern.captures_map(&val) {
-            ctx.captures.extend(caps);
+        if let Some(v) = matched {
+            let Ok(caps) = pattern.captures_map(v, max_len) else {
+                return MatchResult::NoMatch;
+            };
+            ctx.captures.extend(caps.into_iter().flatten());
         }
     }
 
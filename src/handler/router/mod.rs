@@ -1,24 +1,27 @@
 mod ctx;
 mod matcher;
 mod ops;
+mod timing;
 
-use bytes::Bytes;
-use http_body_util::Full;
-use hyper::{body, http};
+use hyper::http;
+use std::time::Instant;
 
 use crate::build::service::LoadedRouter;
 use crate::config::router::OnMatch;
-use crate::handler::{BoxResponseFuture, ServiceHandler};
-use crate::util::http::make_error_resp;
+use crate::handler::{BoxResponseFuture, RequestBody, ResponseBody, ServiceHandler, full};
+use crate::http_server::ConnInfo;
+use crate::util::http::{make_error_resp, parse_host_header};
 
-use ctx::{apply_ctx_to_request, RouterCtx};
+use ctx::{apply_ctx_to_request, scan_multipart, RouterCtx};
+pub(crate) use ctx::is_builtin_var;
 use matcher::{matches_rule, MatchResult};
 use ops::{run_ops, OpOutcome};
+use timing::ServerTiming;
 
 impl ServiceHandler for LoadedRouter {
     fn handle_request<'a>(
         &'a self,
-        req: &'a mut http::Request<body::Incoming>,
+        req: &'a mut http::Request<RequestBody>,
     ) -> BoxResponseFuture<'a> {
         Box::pin(async move { route_request(self, req).await })
     }
@@ -26,9 +29,61 @@ impl ServiceHandler for LoadedRouter {
 
 async fn route_request(
     router: &LoadedRouter,
-    req: &mut http::Request<body::Incoming>,
-) -> http::Response<Full<Bytes>> {
+    req: &mut http::Request<RequestBody>,
+) -> http::Response<ResponseBody> {
+    let started = Instant::now();
+    let mut timing = ServerTiming::default();
     let mut ctx = RouterCtx::from_request(req);
+    ctx.now = router.clock.now();
+    if !router.decode_cookies {
+        ctx = ctx.with_raw_cookies();
+    }
+    if router.scan_multipart {
+        match scan_multipart(req).await {
+            Ok(fields) => ctx.multipart = fields,
+            Err(_) => return make_error_resp(http::StatusCode::BAD_REQUEST, "failed to read request body"),
+        }
+    }
+    let mut resp = run_rules(router, req, &mut ctx, &mut timing).await;
+    for (name, value) in ctx.response_headers {
+        resp.headers_mut().append(name, value);
+    }
+    timing.apply(started.elapsed(), resp.headers_mut());
+    resp
+}
+
+/// Whether `resp` redirects to the same scheme, host and path the request came in on.
+fn is_self_redirect(resp: &http::Response<ResponseBody>, req: &http::Request<RequestBody>) -> bool {
+    if !resp.status().is_redirection() {
+        return false;
+    }
+    let Some(Ok(location)) = resp.headers().get(http::header::LOCATION).map(|v| v.to_str()) else {
+        return false;
+    };
+    let Ok(target) = location.parse::<http::Uri>() else { return false };
+    if let Some(scheme) = target.scheme_str() {
+        let is_tls = req.extensions().get::<ConnInfo>().is_some_and(|ci| ci.is_tls);
+        if scheme.eq_ignore_ascii_case("https") != is_tls {
+            return false;
+        }
+    }
+    if let Some(host) = target.host() {
+        let req_host = req.headers().get(http::header::HOST)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_host_header);
+        if req_host.is_none_or(|(h, _)| !h.eq_ignore_ascii_case(host)) {
+            return false;
+        }
+    }
+    target.path() == req.uri().path()
+}
+
+async fn run_rules(
+    router: &LoadedRouter,
+    req: &mut http::Request<RequestBody>,
+    ctx: &mut RouterCtx,
+    timing: &mut ServerTiming,
+) -> http::Response<ResponseBody> {
     let mut step = 0u32;
     let mut idx = 0usize;
 
@@ -38,25 +93,20 @@ async fn route_request(
         }
 
         if idx >= router.rules.len() {
-            if let Some(nx) = &router.next {
-                apply_ctx_to_request(&ctx, req);
-                return nx.handle_request(req).await;
-            } else {
-                return make_error_resp(http::StatusCode::NOT_FOUND, "no route matched");
-            }
+            return dispatch_next(router, ctx, req, timing).await;
         }
 
         let rule = &router.rules[idx];
 
-        match matches_rule(&rule.when, &mut ctx) {
+        match matches_rule(&rule.when, ctx, router.template.max_len) {
             MatchResult::NoMatch => {
                 idx += 1;
                 continue;
             }
-            MatchResult::Match => {}
+            MatchResult::Match => router.metrics.record_match(idx),
         }
 
-        match run_ops(&rule.ops, &mut ctx, req).await {
+        match run_ops(router, &rule.ops, ctx, timing, req).await {
             OpOutcome::ContinueNextRule => {
                 idx += 1;
             }
@@ -65,18 +115,26 @@ async fn route_request(
                 step += 1;
                 idx = 0;
             }
-            OpOutcome::Respond(resp) => return resp,
+            OpOutcome::Respond(resp) => {
+                if router.redirect_loop_guard && is_self_redirect(&resp, req) {
+                    return make_error_resp(
+                        http::StatusCode::INTERNAL_SERVER_ERROR,
+                        "redirect loop: Location points back to the request path",
+                    );
+                }
+                return resp;
+            }
+            OpOutcome::Metrics => {
+                return http::Response::builder()
+                    .header(http::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+                    .body(full(router.metrics.render() + &crate::metrics::render_pools()))
+                    .unwrap_or_else(|_| make_error_resp(http::StatusCode::INTERNAL_SERVER_ERROR, "metrics build failed"));
+            }
+            OpOutcome::GotoNext => return dispatch_next(router, ctx, req, timing).await,
             OpOutcome::UseService(resp) => return resp,
             OpOutcome::Fallthrough => {
                 match rule.on_match {
-                    OnMatch::Stop => {
-                        if let Some(n) = &router.next {
-                            apply_ctx_to_request(&ctx, req);
-                            return n.handle_request(req).await;
-                        } else {
-                            return make_error_resp(http::StatusCode::NOT_FOUND, "no route matched");
-                        }
-                    }
+                    OnMatch::Stop => return dispatch_next(router, ctx, req, timing).await,
                     OnMatch::Continue => idx += 1,
                     OnMatch::Restart => {
                         ctx.captures.clear();
@@ -89,5 +147,21 @@ async fn route_request(
     }
 }
 
+/// Hand the request, rewritten from `ctx`, to `router.next`; 404 when there is none.
+async fn dispatch_next(
+    router: &LoadedRouter,
+    ctx: &RouterCtx,
+    req: &mut http::Request<RequestBody>,
+    timing: &mut ServerTiming,
+) -> http::Response<ResponseBody> {
+    match &router.next {
+        Some(next) => {
+            apply_ctx_to_request(ctx, req, router.rewrite_host);
+            timing.upstream(next.handle_request(req)).await
+        }
+        None => make_error_resp(http::StatusCode::NOT_FOUND, "no route matched"),
+    }
+}
+
 #[cfg(test)]
 mod tests;
@@ -1,40 +1,52 @@
+use base64::Engine;
 use bytes::Bytes;
-use http_body_util::Full;
-use hyper::{body, http};
-use std::collections::HashMap;
+use hyper::http;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use crate::build::router::{
     CompiledBasicCond,
     CompiledCondNode,
     CompiledTestCond,
     LoadedOp,
+    LoadedRespondStream,
 };
+use crate::build::service::LoadedRouter;
+use crate::config::router::op::LogLevel;
 use crate::config::url_scheme::Scheme;
-use crate::handler::ServiceHandler;
-use crate::template::expand_template;
-use crate::util::http::make_error_resp;
+use crate::handler::body::peek_request_body;
+use crate::handler::{RequestBody, ResponseBody, ServiceHandler, channel, full};
+use crate::template::{expand_template, CompiledTemplate, ValueProvider};
+use crate::util::http::{decode_path, make_error_resp, set_content_length};
+use crate::util::{cookie_sig, jwt};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
 
-use super::ctx::{apply_ctx_to_request, RouterCtx};
+use super::ctx::{apply_ctx_to_request, parse_cookies, query_string, RouterCtx};
+use super::timing::ServerTiming;
 
 #[derive(Debug)]
 pub enum OpOutcome {
     ContinueNextRule,
     Restart,
-    Respond(http::Response<Full<Bytes>>),
-    UseService(http::Response<Full<Bytes>>),
+    Respond(http::Response<ResponseBody>),
+    Metrics,
+    GotoNext,
+    UseService(http::Response<ResponseBody>),
     Fallthrough,
 }
 
 pub async fn run_ops(
+    router: &LoadedRouter,
     ops: &[LoadedOp],
     ctx: &mut RouterCtx,
-    req: &mut http::Request<body::Incoming>,
+    timing: &mut ServerTiming,
+    req: &mut http::Request<RequestBody>,
 ) -> OpOutcome {
     let mut stack: Vec<(&[LoadedOp], usize)> = vec![(ops, 0)];
 
     while let Some((ops_slice, mut idx)) = stack.pop() {
         while idx < ops_slice.len() {
             let op = &ops_slice[idx];
+            router.metrics.record_op(op.name());
             match op {
                 LoadedOp::SetScheme(s) => {
                     ctx.scheme = Some(match s {
@@ -43,14 +55,14 @@ pub async fn run_ops(
                     });
                 }
                 LoadedOp::SetHost(tpl) => {
-                    match expand_template(tpl, &ctx) {
+                    match expand_template(tpl, &ctx, &router.template) {
                         Ok(val) => ctx.host = val,
                         Err(_) => return OpOutcome::Respond(make_error_resp(http::StatusCode::BAD_REQUEST, "template error")),
                     }
                 }
                 LoadedOp::SetPort(p) => ctx.port = Some(*p),
                 LoadedOp::SetPath(tpl) => {
-                    let val = match expand_template(tpl, &ctx) {
+                    let val = match expand_template(tpl, &ctx, &router.template) {
                         Ok(v) => v,
                         Err(_) => return OpOutcome::Respond(make_error_resp(http::StatusCode::BAD_REQUEST, "template error")),
                     };
@@ -59,10 +71,28 @@ pub async fn run_ops(
                     }
                     ctx.path = val;
                 }
+                LoadedOp::RewritePath { from, to } => {
+                    // A path that does not match `from` is left untouched.
+                    let caps = match from.captures_map(&ctx.path, router.template.max_len) {
+                        Ok(caps) => caps,
+                        Err(_) => return OpOutcome::Respond(make_error_resp(http::StatusCode::BAD_REQUEST, "template error")),
+                    };
+                    if let Some(caps) = caps {
+                        ctx.captures.extend(caps);
+                        let val = match expand_template(to, &ctx, &router.template) {
+                            Ok(v) => v,
+                            Err(_) => return OpOutcome::Respond(make_error_resp(http::StatusCode::BAD_REQUEST, "template error")),
+                        };
+                        if !val.starts_with('/') {
+                            return OpOutcome::Respond(make_error_resp(http::StatusCode::BAD_REQUEST, "path must start with '/'"));
+                        }
+                        ctx.path = val;
+                    }
+                }
                 LoadedOp::HeaderSet(map) => {
                     let headers = req.headers_mut();
                     for (k, v) in map {
-                        let val = match expand_template(v, &ctx) {
+                        let val = match expand_template(v, &ctx, &router.template) {
                             Ok(v) => v,
                             Err(_) => return OpOutcome::Respond(make_error_resp(http::StatusCode::BAD_REQUEST, "template error")),
                         };
@@ -78,7 +108,7 @@ pub async fn run_ops(
                 LoadedOp::HeaderAdd(map) => {
                     let headers = req.headers_mut();
                     for (k, v) in map {
-                        let val = match expand_template(v, &ctx) {
+                        let val = match expand_template(v, &ctx, &router.template) {
                             Ok(v) => v,
                             Err(_) => return OpOutcome::Respond(make_error_resp(http::StatusCode::BAD_REQUEST, "template error")),
                         };
@@ -91,6 +121,23 @@ pub async fn run_ops(
                         }
                     }
                 }
+                LoadedOp::HeaderSetIfAbsent(map) => {
+                    let headers = req.headers_mut();
+                    for (k, v) in map {
+                        let Ok(name) = http::HeaderName::try_from(k.as_str()) else { continue };
+                        if headers.contains_key(&name) {
+                            continue;
+                        }
+                        let val = match expand_template(v, &ctx, &router.template) {
+                            Ok(v) => v,
+                            Err(_) => return OpOutcome::Respond(make_error_resp(http::StatusCode::BAD_REQUEST, "template error")),
+                        };
+                        if let Ok(hv) = http::HeaderValue::from_str(&val) {
+                            headers.insert(name.clone(), hv);
+                            ctx.headers.insert(name.as_str().to_ascii_lowercase(), vec![val]);
+                        }
+                    }
+                }
                 LoadedOp::HeaderDelete(keys) => {
                     let headers = req.headers_mut();
                     for k in keys {
@@ -100,13 +147,34 @@ pub async fn run_ops(
                         }
                     }
                 }
+                LoadedOp::HeaderDedup(keys) => {
+                    let headers = req.headers_mut();
+                    for k in keys {
+                        if let Ok(name) = http::HeaderName::try_from(k.as_str()) {
+                            let mut distinct: Vec<http::HeaderValue> = Vec::new();
+                            for v in headers.get_all(&name) {
+                                if !distinct.contains(v) {
+                                    distinct.push(v.clone());
+                                }
+                            }
+                            headers.remove(&name);
+                            for v in distinct {
+                                headers.append(name.clone(), v);
+                            }
+                            if let Some(values) = ctx.headers.get_mut(name.as_str()) {
+                                let mut seen = HashSet::new();
+                                values.retain(|v| seen.insert(v.clone()));
+                            }
+                        }
+                    }
+                }
                 LoadedOp::HeaderClear => {
                     req.headers_mut().clear();
                     ctx.headers.clear();
                 }
                 LoadedOp::QuerySet(map) => {
                     for (k, v) in map {
-                        let val = match expand_template(v, &ctx) {
+                        let val = match expand_template(v, &ctx, &router.template) {
                             Ok(v) => v,
                             Err(_) => return OpOutcome::Respond(make_error_resp(http::StatusCode::BAD_REQUEST, "template error")),
                         };
@@ -115,7 +183,7 @@ pub async fn run_ops(
                 }
                 LoadedOp::QueryAdd(map) => {
                     for (k, v) in map {
-                        let val = match expand_template(v, &ctx) {
+                        let val = match expand_template(v, &ctx, &router.template) {
                             Ok(v) => v,
                             Err(_) => return OpOutcome::Respond(make_error_resp(http::StatusCode::BAD_REQUEST, "template error")),
                         };
@@ -124,33 +192,87 @@ pub async fn run_ops(
                 }
                 LoadedOp::QueryDelete(keys) => {
                     for k in keys {
-                        ctx.query.remove(k);
+                        ctx.query.shift_remove(k);
                     }
                 }
                 LoadedOp::QueryClear => ctx.query.clear(),
+                LoadedOp::SortQuery => ctx.sort_query(),
+                LoadedOp::DecodePath => match decode_path(&ctx.path) {
+                    Some(p) => ctx.path = p,
+                    None => return OpOutcome::Respond(make_error_resp(http::StatusCode::BAD_REQUEST, "path is not valid UTF-8")),
+                },
+                LoadedOp::CopyValue { from, to } => {
+                    if let Some(val) = value_of(from, ctx) {
+                        copy_value_to(to, val, ctx, req, router.decode_cookies);
+                    }
+                }
+                LoadedOp::RequireUserAgent { pattern, deny } => {
+                    let ua = ctx.headers.get("user-agent").and_then(|vals| vals.first());
+                    let matched = pattern.is_match(ua.map_or("", String::as_str));
+                    if matched == *deny {
+                        return OpOutcome::Respond(make_error_resp(http::StatusCode::FORBIDDEN, "user agent not allowed"));
+                    }
+                }
+                LoadedOp::RequireContentType(types) => {
+                    let media_type = ctx.headers.get("content-type")
+                        .and_then(|vals| vals.first())
+                        .and_then(|v| v.split(';').next())
+                        .map(|v| v.trim().to_ascii_lowercase());
+                    if !media_type.is_some_and(|t| types.contains(&t)) {
+                        return OpOutcome::Respond(make_error_resp(http::StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported content type"));
+                    }
+                }
+                LoadedOp::VerifyJwt { key, alg, claims, leeway_secs } => {
+                    let token = ctx.headers.get("authorization")
+                        .and_then(|vals| vals.first())
+                        .and_then(|v| v.split_once(' '))
+                        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+                        .map(|(_, t)| t.trim());
+                    let now = ctx.unix_secs();
+                    let Some(Ok(verified)) = token.map(|t| jwt::verify(t, key, *alg, *leeway_secs, now)) else {
+                        let mut resp = make_error_resp(http::StatusCode::UNAUTHORIZED, "invalid or missing token");
+                        resp.headers_mut().insert(http::header::WWW_AUTHENTICATE, http::HeaderValue::from_static("Bearer"));
+                        return OpOutcome::Respond(resp);
+                    };
+                    for name in claims {
+                        if let Some(v) = verified.get(name) {
+                            ctx.captures.insert(format!("jwt.{name}"), jwt::claim_to_string(v));
+                        }
+                    }
+                }
+                LoadedOp::Delay(d) => tokio::time::sleep(*d).await,
+                LoadedOp::InjectError { status, probability } => {
+                    if fastrand::f64() < *probability {
+                        return OpOutcome::Respond(make_error_resp(*status, "injected error"));
+                    }
+                }
                 LoadedOp::InternalRewrite => return OpOutcome::Restart,
-                LoadedOp::Redirect { status, location } => {
+                LoadedOp::Redirect { status, location, preserve_query } => {
                     let status_code = match status {
                         crate::config::router::op::RedirectCode::_301 => http::StatusCode::MOVED_PERMANENTLY,
                         crate::config::router::op::RedirectCode::_302 => http::StatusCode::FOUND,
                         crate::config::router::op::RedirectCode::_307 => http::StatusCode::TEMPORARY_REDIRECT,
                         crate::config::router::op::RedirectCode::_308 => http::StatusCode::PERMANENT_REDIRECT,
                     };
-                    let loc = match expand_template(location, &ctx) {
+                    let mut loc = match expand_template(location, &ctx, &router.template) {
                         Ok(v) => v,
                         Err(_) => return OpOutcome::Respond(make_error_resp(http::StatusCode::BAD_REQUEST, "template error")),
                     };
+                    if *preserve_query && !ctx.query.is_empty() {
+                        loc = append_query(&loc, &query_string(ctx));
+                    }
                     let resp = http::Response::builder()
                         .status(status_code)
                         .header(http::header::LOCATION, loc.as_str())
-                        .body(Full::default())
+                        .header(http::header::CONTENT_LENGTH, 0)
+                        .body(full(Bytes::new()))
                         .unwrap_or_else(|_| make_error_resp(http::StatusCode::INTERNAL_SERVER_ERROR, "redirect build failed"));
                     return OpOutcome::Respond(resp);
                 }
-                LoadedOp::Respond { status, body, headers } => {
+                LoadedOp::Respond { status, body, embedded, headers, auto_content_type, stream } => {
                     let mut builder = http::Response::builder().status(*status);
                     for (k, v) in headers {
-                        let val = match expand_template(v, &ctx) {
+                        let val = match expand_template(v, &ctx, &router.template) {
                             Ok(v) => v,
                             Err(_) => return OpOutcome::Respond(make_error_resp(http::StatusCode::BAD_REQUEST, "template error")),
                         };
@@ -161,25 +283,119 @@ pub async fn run_ops(
                             builder = builder.header(name, val);
                         }
                     }
-                    let body_val = match body {
-                        Some(t) => match expand_template(t, &ctx) {
-                            Ok(v) => v,
+                    let (body_val, inferred_type) = match (embedded, body) {
+                        (Some(asset), _) => (Bytes::from_static(asset.bytes), asset.content_type.as_str()),
+                        (None, Some(t)) => match expand_template(t, &ctx, &router.template) {
+                            Ok(v) => {
+                                let inferred = infer_content_type(&v);
+                                (Bytes::from(v), inferred)
+                            }
                             Err(_) => return OpOutcome::Respond(make_error_resp(http::StatusCode::BAD_REQUEST, "template error")),
                         },
-                        None => String::new(),
+                        (None, None) => (Bytes::new(), infer_content_type("")),
                     };
+                    if *auto_content_type
+                        && !headers.keys().any(|k| k.eq_ignore_ascii_case("content-type"))
+                    {
+                        builder = builder.header(http::header::CONTENT_TYPE, inferred_type);
+                    }
+                    if let Some(stream) = stream {
+                        let resp = builder
+                            .body(stream_lines(router, ctx, stream, body_val))
+                            .unwrap_or_else(|_| make_error_resp(http::StatusCode::INTERNAL_SERVER_ERROR, "respond build failed"));
+                        return OpOutcome::Respond(resp);
+                    }
+                    let len = body_val.len();
                     let resp = builder
-                        .body(Full::from(body_val))
+                        .body(full(body_val))
+                        .map(|mut resp| {
+                            set_content_length(&mut resp, len);
+                            resp
+                        })
                         .unwrap_or_else(|_| make_error_resp(http::StatusCode::INTERNAL_SERVER_ERROR, "respond build failed"));
                     return OpOutcome::Respond(resp);
                 }
+                LoadedOp::RespondPassthrough { headers } => {
+                    return OpOutcome::Respond(relay_subresponse(router, ctx, headers));
+                }
+                LoadedOp::Metrics => return OpOutcome::Metrics,
+                LoadedOp::ServerTiming => timing.enabled = true,
+                LoadedOp::Preload(links) => {
+                    let mut value = String::new();
+                    for link in links {
+                        let href = match expand_template(&link.href, &ctx, &router.template) {
+                            Ok(v) => v,
+                            Err(_) => return OpOutcome::Respond(make_error_resp(http::StatusCode::BAD_REQUEST, "template error")),
+                        };
+                        if !value.is_empty() {
+                            value.push_str(", ");
+                        }
+                        value.push_str(&format!("<{href}>; rel=preload; as={}", link.r#as.as_str()));
+                        if link.crossorigin {
+                            value.push_str("; crossorigin");
+                        }
+                    }
+                    if let Ok(v) = http::HeaderValue::from_str(&value) {
+                        ctx.response_headers.push((http::header::LINK, v));
+                    }
+                }
+                LoadedOp::GotoNext => return OpOutcome::GotoNext,
+                LoadedOp::GenNonce { name } => {
+                    let mut bytes = [0u8; NONCE_BYTES];
+                    if let Err(e) = crate::util::random::fill(&mut bytes) {
+                        tracing::error!(error = %e, "no OS randomness for gen_nonce");
+                        return OpOutcome::Respond(make_error_resp(http::StatusCode::INTERNAL_SERVER_ERROR, "nonce generation failed"));
+                    }
+                    ctx.captures.insert(name.clone(), base64::engine::general_purpose::STANDARD.encode(bytes));
+                }
+                LoadedOp::SignCookie { name, value_template, key, attributes } => {
+                    let value = match expand_template(value_template, &ctx, &router.template) {
+                        Ok(v) => v,
+                        Err(_) => return OpOutcome::Respond(make_error_resp(http::StatusCode::BAD_REQUEST, "template error")),
+                    };
+                    let encoded = utf8_percent_encode(&value, COOKIE_VALUE).to_string();
+                    let mut cookie = format!("{name}={}", cookie_sig::sign(name, &encoded, key));
+                    if !attributes.is_empty() {
+                        cookie.push_str("; ");
+                        cookie.push_str(attributes);
+                    }
+                    if let Ok(v) = http::HeaderValue::from_str(&cookie) {
+                        ctx.response_headers.push((http::header::SET_COOKIE, v));
+                    }
+                }
+                LoadedOp::VerifyCookie { name, key } => {
+                    // the signature covers the value exactly as it was sent
+                    let raw = parse_cookies(ctx.headers.get("cookie"), false);
+                    let verified = raw.get(name).into_iter().flatten().find_map(|v| cookie_sig::verify(name, v, key));
+                    if let Some(v) = verified {
+                        ctx.captures.insert(format!("signed.{name}"), percent_decode_str(v).decode_utf8_lossy().into_owned());
+                    }
+                }
+                LoadedOp::DebugLog { level, include_body } => {
+                    let body = if *include_body {
+                        match peek_request_body(req, DEBUG_LOG_MAX_BODY + 1).await {
+                            Ok(bytes) => Some(bytes),
+                            Err(_) => return OpOutcome::Respond(make_error_resp(http::StatusCode::BAD_REQUEST, "failed to read request body")),
+                        }
+                    } else {
+                        None
+                    };
+                    log_request(*level, req, body.as_deref());
+                }
+                LoadedOp::Subrequest(svc) => {
+                    let mut sub = subrequest_for(ctx, req, router.rewrite_host);
+                    let resp = timing.upstream(svc.handle_request(&mut sub)).await;
+                    if let Err(resp) = bind_subresponse(resp, ctx).await {
+                        return OpOutcome::Respond(resp);
+                    }
+                }
                 LoadedOp::Use(svc) => {
-                    apply_ctx_to_request(ctx, req);
-                    let resp = svc.handle_request(req).await;
+                    apply_ctx_to_request(ctx, req, router.rewrite_host);
+                    let resp = timing.upstream(svc.handle_request(req)).await;
                     return OpOutcome::UseService(resp);
                 }
                 LoadedOp::Branch(cond, then_ops, else_ops) => {
-                    let (pass, captures) = eval_cond(cond, ctx);
+                    let (pass, captures) = eval_cond(cond, ctx, router.template.max_len);
                     if pass {
                         ctx.captures.extend(captures);
                     }
@@ -196,13 +412,223 @@ pub async fn run_ops(
     OpOutcome::Fallthrough
 }
 
-/// Evaluate a condition tree, returning (is_true, captures_from_true_path).
-pub(crate) fn eval_cond(node: &CompiledCondNode, ctx: &RouterCtx) -> (bool, HashMap<String, String>) {
+/// Append `query` to a location, merging with any query it already has and keeping its fragment last.
+pub(crate) fn append_query(location: &str, query: &str) -> String {
+    let (base, fragment) = match location.find('#') {
+        Some(i) => location.split_at(i),
+        None => (location, ""),
+    };
+    let sep = if !base.contains('?') {
+        "?"
+    } else if base.ends_with('?') || base.ends_with('&') {
+        ""
+    } else {
+        "&"
+    };
+    format!("{base}{sep}{query}{fragment}")
+}
+
+/// Write `val` to a `copy_value` target; `to` was validated at build time.
+fn copy_value_to(
+    to: &str,
+    val: String,
+    ctx: &mut RouterCtx,
+    req: &mut http::Request<RequestBody>,
+    encode_cookies: bool,
+) {
+    if let Some(name) = to.strip_prefix("header.") {
+        if let (Ok(name), Ok(hv)) = (http::HeaderName::try_from(name), http::HeaderValue::from_str(&val)) {
+            req.headers_mut().insert(name.clone(), hv);
+            ctx.headers.insert(name.as_str().to_ascii_lowercase(), vec![val]);
+        }
+    } else if let Some(key) = to.strip_prefix("query.") {
+        ctx.query.insert(key.to_string(), vec![val]);
+    } else if let Some(name) = to.strip_prefix("cookie.") {
+        ctx.cookies.insert(name.to_string(), vec![val]);
+        write_cookie_header(ctx, req, encode_cookies);
+    } else {
+        ctx.captures.insert(to.to_string(), val);
+    }
+}
+
+/// Body that starts with `initial`, then gains one expanded `stream.line` per interval.
+/// Each line sees the request context plus `stream.index`, counting from 0.
+fn stream_lines(router: &LoadedRouter, ctx: &RouterCtx, stream: &LoadedRespondStream, initial: Bytes) -> ResponseBody {
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    let mut ctx = ctx.clone();
+    let (line, interval, count) = (stream.line.clone(), stream.interval, stream.count);
+    let (options, clock) = (router.template.clone(), router.clock.clone());
+    tokio::spawn(async move {
+        if !initial.is_empty() && tx.send(initial).await.is_err() {
+            return;
+        }
+        let mut index = 0;
+        while count.is_none_or(|n| index < n) {
+            tokio::time::sleep(interval).await;
+            ctx.now = clock.now();
+            ctx.captures.insert("stream.index".into(), index.to_string());
+            let Ok(mut text) = expand_template(&line, &ctx, &options) else { return };
+            text.push('\n');
+            // a dropped receiver means the client went away
+            if tx.send(Bytes::from(text)).await.is_err() {
+                return;
+            }
+            index += 1;
+        }
+    });
+    channel(rx)
+}
+
+/// Random bytes in a `gen_nonce` value; 128 bits, as CSP recommends.
+const NONCE_BYTES: usize = 16;
+
+/// Most body bytes a `debug_log` op writes out; anything past this is elided.
+const DEBUG_LOG_MAX_BODY: usize = 4096;
+
+/// Headers carrying credentials, logged as `<redacted>` by `debug_log`.
+const REDACTED_HEADERS: [http::HeaderName; 3] =
+    [http::header::AUTHORIZATION, http::header::PROXY_AUTHORIZATION, http::header::COOKIE];
+
+fn log_request(level: LogLevel, req: &http::Request<RequestBody>, body: Option<&[u8]>) {
+    let mut headers = req.headers().clone();
+    for (name, value) in headers.iter_mut() {
+        if REDACTED_HEADERS.contains(name) {
+            *value = http::HeaderValue::from_static("<redacted>");
+        }
+    }
+    let body = body.map(|b| {
+        let shown = String::from_utf8_lossy(&b[..b.len().min(DEBUG_LOG_MAX_BODY)]).into_owned();
+        if b.len() > DEBUG_LOG_MAX_BODY { shown + "..." } else { shown }
+    });
+    macro_rules! emit {
+        ($lvl:expr) => {
+            tracing::event!($lvl, method = %req.method(), uri = %req.uri(), headers = ?headers, body = ?body, "debug_log")
+        };
+    }
+    match level {
+        LogLevel::Trace => emit!(tracing::Level::TRACE),
+        LogLevel::Debug => emit!(tracing::Level::DEBUG),
+        LogLevel::Info => emit!(tracing::Level::INFO),
+        LogLevel::Warn => emit!(tracing::Level::WARN),
+        LogLevel::Error => emit!(tracing::Level::ERROR),
+    }
+}
+
+/// Largest subrequest response body bound into `sub.body`.
+const SUBREQUEST_MAX_BODY: usize = 1024 * 1024;
+
+/// Bodyless `GET` for the current request target, carrying the client's headers.
+fn subrequest_for(ctx: &RouterCtx, req: &http::Request<RequestBody>, rewrite_host: bool) -> http::Request<RequestBody> {
+    let mut sub = http::Request::new(full(Bytes::new()));
+    *sub.uri_mut() = req.uri().clone();
+    *sub.headers_mut() = req.headers().clone();
+    for name in [http::header::CONTENT_LENGTH, http::header::TRANSFER_ENCODING, http::header::EXPECT] {
+        sub.headers_mut().remove(name);
+    }
+    if let Some(conn) = req.extensions().get::<crate::http_server::ConnInfo>() {
+        sub.extensions_mut().insert(conn.clone());
+    }
+    apply_ctx_to_request(ctx, &mut sub, rewrite_host);
+    sub
+}
+
+/// Read a subrequest response into `sub.*` captures, keeping the raw head and body
+/// for `respond: { passthrough: true }`; a body over the cap fails with 502.
+async fn bind_subresponse(
+    resp: http::Response<ResponseBody>,
+    ctx: &mut RouterCtx,
+) -> Result<(), http::Response<ResponseBody>> {
+    use http_body_util::BodyExt;
+
+    let (parts, body) = resp.into_parts();
+    let body = http_body_util::Limited::new(body, SUBREQUEST_MAX_BODY)
+        .collect()
+        .await
+        .map_err(|_| make_error_resp(http::StatusCode::BAD_GATEWAY, "subrequest body unreadable or too large"))?
+        .to_bytes();
+    ctx.captures.insert("sub.status".into(), parts.status.as_u16().to_string());
+    ctx.captures.insert("sub.body".into(), String::from_utf8_lossy(&body).into_owned());
+    for name in parts.headers.keys() {
+        if let Some(val) = parts.headers.get(name).and_then(|v| v.to_str().ok()) {
+            ctx.captures.insert(format!("sub.header.{name}"), val.to_string());
+        }
+    }
+    ctx.subresponse = Some((parts, body));
+    Ok(())
+}
+
+/// Send back the response bound by the last `subrequest` byte for byte, every header
+/// value included, with `headers` set on top.
+fn relay_subresponse(
+    router: &LoadedRouter,
+    ctx: &RouterCtx,
+    headers: &BTreeMap<String, CompiledTemplate>,
+) -> http::Response<ResponseBody> {
+    let Some((parts, body)) = ctx.subresponse.clone() else {
+        return make_error_resp(http::StatusCode::INTERNAL_SERVER_ERROR, "respond passthrough without a subrequest");
+    };
+    let len = body.len();
+    let mut resp = http::Response::from_parts(parts, full(body));
+    // the body was collected, so it is re-framed with a fresh Content-Length
+    resp.headers_mut().remove(http::header::TRANSFER_ENCODING);
+    resp.headers_mut().remove(http::header::CONTENT_LENGTH);
+    for (k, v) in headers {
+        let val = match expand_template(v, &ctx, &router.template) {
+            Ok(v) => v,
+            Err(_) => return make_error_resp(http::StatusCode::BAD_REQUEST, "template error"),
+        };
+        if let (Ok(name), Ok(val)) = (
+            http::HeaderName::try_from(k.as_str()),
+            http::HeaderValue::from_str(&val),
+        ) {
+            resp.headers_mut().insert(name, val);
+        }
+    }
+    set_content_length(&mut resp, len);
+    resp
+}
+
+/// Bytes a cookie value cannot carry unescaped; decoded again when cookies are parsed.
+const COOKIE_VALUE: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b',').add(b';').add(b'\\').add(b'%');
+
+/// Rebuild the `Cookie` header from `ctx.cookies`, sorted by name. Values are
+/// percent-encoded unless cookies are kept raw.
+fn write_cookie_header(ctx: &mut RouterCtx, req: &mut http::Request<RequestBody>, encode: bool) {
+    let sorted: BTreeMap<_, _> = ctx.cookies.iter().collect();
+    let joined = sorted
+        .into_iter()
+        .flat_map(|(k, vals)| vals.iter().map(move |v| (k, v)))
+        .map(|(k, v)| if encode { format!("{k}={}", utf8_percent_encode(v, COOKIE_VALUE)) } else { format!("{k}={v}") })
+        .collect::<Vec<_>>()
+        .join("; ");
+    if let Ok(hv) = http::HeaderValue::from_str(&joined) {
+        req.headers_mut().insert(http::header::COOKIE, hv);
+        ctx.headers.insert("cookie".into(), vec![joined]);
+    }
+}
+
+/// Guess a Content-Type from the shape of a respond body.
+pub(crate) fn infer_content_type(body: &str) -> &'static str {
+    let trimmed = body.trim();
+    let looks_json = (trimmed.starts_with('{') && trimmed.ends_with('}'))
+        || (trimmed.starts_with('[') && trimmed.ends_with(']'));
+    if looks_json {
+        "application/json"
+    } else if trimmed.starts_with('<') && trimmed.ends_with('>') {
+        "text/html; charset=utf-8"
+    } else {
+        "text/plain; charset=utf-8"
+    }
+}
+
+/// Evaluate a condition tree, returning (is_true, captures_from_true_path). A pattern
+/// whose filtered captures outgrow `max_len` bytes is false.
+pub(crate) fn eval_cond(node: &CompiledCondNode, ctx: &RouterCtx, max_len: usize) -> (bool, HashMap<String, String>) {
     match node {
         CompiledCondNode::All(children) => {
             let mut acc = HashMap::new();
             for child in children {
-                let (pass, caps) = eval_cond(child, ctx);
+                let (pass, caps) = eval_cond(child, ctx, max_len);
                 if !pass {
                     return (false, HashMap::new());
                 }
@@ -212,7 +638,7 @@ pub(crate) fn eval_cond(node: &CompiledCondNode, ctx: &RouterCtx) -> (bool, Hash
         }
         CompiledCondNode::Any(children) => {
             for child in children {
-                let (pass, caps) = eval_cond(child, ctx);
+                let (pass, caps) = eval_cond(child, ctx, max_len);
                 if pass {
                     return (true, caps);
                 }
@@ -220,14 +646,14 @@ pub(crate) fn eval_cond(node: &CompiledCondNode, ctx: &RouterCtx) -> (bool, Hash
             (false, HashMap::new())
         }
         CompiledCondNode::Not(child) => {
-            let (pass, _) = eval_cond(child, ctx);
+            let (pass, _) = eval_cond(child, ctx, max_len);
             (!pass, HashMap::new())
         }
-        CompiledCondNode::Test(t) => eval_test(t, ctx),
+        CompiledCondNode::Test(t) => eval_test(t, ctx, max_len),
     }
 }
 
-fn eval_test(t: &CompiledTestCond, ctx: &RouterCtx) -> (bool, HashMap<String, String>) {
+fn eval_test(t: &CompiledTestCond, ctx: &RouterCtx, max_len: usize) -> (bool, HashMap<String, String>) {
     match &t.cond {
         CompiledBasicCond::Equals(is) => {
             let pass = value_of(&t.var, ctx).map_or(false, |v| serde_yaml::Value::String(v) == *is);
@@ -241,37 +667,32 @@ fn eval_test(t: &CompiledTestCond, ctx: &RouterCtx) -> (bool, HashMap<String, St
             let has = value_of(&t.var, ctx).is_some();
             (has == *p, HashMap::new())
         }
+        CompiledBasicCond::Empty(e) => {
+            let empty = value_of(&t.var, ctx).is_none_or(|v| v.is_empty());
+            (empty == *e, HashMap::new())
+        }
         CompiledBasicCond::Pattern(pat) => {
             if let Some(v) = value_of(&t.var, ctx) {
-                if pat.is_match(&v) {
-                    let caps = pat.captures_map(&v).unwrap_or_default();
+                if let Ok(Some(caps)) = pat.captures_map(&v, max_len) {
                     return (true, caps);
                 }
             }
             (false, HashMap::new())
         }
+        CompiledBasicCond::Compare { gt, gte, lt, lte } => {
+            let pass = value_of(&t.var, ctx)
+                .and_then(|v| v.trim().parse::<f64>().ok())
+                .is_some_and(|n| {
+                    gt.is_none_or(|b| n > b)
+                        && gte.is_none_or(|b| n >= b)
+                        && lt.is_none_or(|b| n < b)
+                        && lte.is_none_or(|b| n <= b)
+                });
+            (pass, HashMap::new())
+        }
     }
 }
 
 fn value_of(var: &str, ctx: &RouterCtx) -> Option<String> {
-    match var {
-        "method" => ctx.method.as_ref().map(|m| format!("{:?}", m).to_ascii_uppercase()),
-        "scheme" => ctx.scheme.clone(),
-        "host" => Some(ctx.host.clone()),
-        "port" => ctx.port.map(|p| p.to_string()),
-        "path" => Some(ctx.path.clone()),
-        v if v.starts_with("header.") => {
-            let key = v.trim_start_matches("header.").to_ascii_lowercase();
-            ctx.headers.get(&key).and_then(|vals| vals.get(0)).cloned()
-        }
-        v if v.starts_with("query.") => {
-            let key = v.trim_start_matches("query.");
-            ctx.query.get(key).and_then(|vals| vals.get(0)).cloned()
-        }
-        v if v.starts_with("cookie.") => {
-            let key = v.trim_start_matches("cookie.");
-            ctx.cookies.get(key).cloned()
-        }
-        _ => ctx.captures.get(var).cloned(),
-    }
+    ctx.get(var)
 }
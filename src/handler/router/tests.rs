@@ -1,23 +1,39 @@
 use std::collections::HashMap;
 
+use http_body_util::BodyExt;
+use hyper::http;
+use indexmap::IndexMap;
+
 use crate::pattern::{compile, compile_host};
 use crate::pattern::context::PathCtx;
-use crate::template::{compile_template, expand_template, CompiledTemplate, ValueProvider};
+use crate::template::{compile_template, expand_template, CompiledTemplate, ExpandOptions, ValueProvider, DEFAULT_MAX_EXPANSION};
 
-use super::ctx::RouterCtx;
-use super::ops::eval_cond;
+use super::ctx::{request_target, RouterCtx};
+use crate::build::service::LoadedService;
+use crate::testing;
+use super::ops::{append_query, eval_cond, infer_content_type};
 use crate::build::router::{CompiledBasicCond, CompiledCondNode, CompiledTestCond};
 
 fn ctx_with_path(path: &str) -> RouterCtx {
     RouterCtx {
         method: None,
         scheme: None,
+        tls: false,
+        sni: None,
         host: String::new(),
         port: None,
         path: path.to_string(),
-        query: HashMap::new(),
+        query: IndexMap::new(),
         headers: HashMap::new(),
         cookies: HashMap::new(),
+        form: HashMap::new(),
+        multipart: Default::default(),
+        subresponse: None,
+        client_cert: None,
+        remote_ip: None,
+        target_form: "origin",
+        now: std::time::SystemTime::UNIX_EPOCH,
+        response_headers: Vec::new(),
         captures: HashMap::new(),
     }
 }
@@ -26,12 +42,22 @@ fn ctx_with_host(host: &str) -> RouterCtx {
     RouterCtx {
         method: None,
         scheme: None,
+        tls: false,
+        sni: None,
         host: host.to_string(),
         port: None,
         path: String::new(),
-        query: HashMap::new(),
+        query: IndexMap::new(),
         headers: HashMap::new(),
         cookies: HashMap::new(),
+        form: HashMap::new(),
+        multipart: Default::default(),
+        subresponse: None,
+        client_cert: None,
+        remote_ip: None,
+        target_form: "origin",
+        now: std::time::SystemTime::UNIX_EPOCH,
+        response_headers: Vec::new(),
         captures: HashMap::new(),
     }
 }
@@ -57,7 +83,7 @@ fn any_takes_first_true_captures() {
         test_node("path", "<other:path>", &PathCtx),
     ]);
     let ctx = ctx_with_path("123");
-    let (pass, caps) = eval_cond(&cond, &ctx);
+    let (pass, caps) = eval_cond(&cond, &ctx, DEFAULT_MAX_EXPANSION);
     assert!(pass);
     assert_eq!(caps.get("id").map(String::as_str), Some("123"));
     assert!(!caps.contains_key("other"));
@@ -70,7 +96,7 @@ fn all_merges_captures() {
         test_node("path", "<b:regex([0-9]{3})>", &PathCtx),
     ]);
     let ctx = ctx_with_path("123");
-    let (pass, caps) = eval_cond(&cond, &ctx);
+    let (pass, caps) = eval_cond(&cond, &ctx, DEFAULT_MAX_EXPANSION);
     assert!(pass);
     assert_eq!(caps.get("a").map(String::as_str), Some("123"));
     assert_eq!(caps.get("b").map(String::as_str), Some("123"));
@@ -80,7 +106,7 @@ fn all_merges_captures() {
 fn not_does_not_propagate_captures() {
     let cond = CompiledCondNode::Not(Box::new(test_node("path", "<p:*>", &PathCtx)));
     let ctx = ctx_with_path("/whatever");
-    let (pass, caps) = eval_cond(&cond, &ctx);
+    let (pass, caps) = eval_cond(&cond, &ctx, DEFAULT_MAX_EXPANSION);
     assert!(!pass);
     assert!(caps.is_empty());
 }
@@ -92,7 +118,7 @@ fn any_all_fail_no_captures() {
         test_node("path", "<slug:slug>", &PathCtx),
     ]);
     let ctx = ctx_with_path("bad.slug"); // contains '.' so neither uint nor slug matches
-    let (pass, caps) = eval_cond(&cond, &ctx);
+    let (pass, caps) = eval_cond(&cond, &ctx, DEFAULT_MAX_EXPANSION);
     assert!(!pass);
     assert!(caps.is_empty());
 }
@@ -104,7 +130,7 @@ fn all_stops_on_first_fail_and_drops_captures() {
         test_node("path", "<never:uint>", &PathCtx), // will fail because path already consumed non-digit?
     ]);
     let ctx = ctx_with_path("abc");
-    let (pass, caps) = eval_cond(&cond, &ctx);
+    let (pass, caps) = eval_cond(&cond, &ctx, DEFAULT_MAX_EXPANSION);
     assert!(!pass);
     assert!(caps.is_empty());
 }
@@ -119,7 +145,7 @@ fn any_inside_all_merges_chosen_branch_only() {
         ]),
     ]);
     let ctx = ctx_with_path("123");
-    let (pass, caps) = eval_cond(&cond, &ctx);
+    let (pass, caps) = eval_cond(&cond, &ctx, DEFAULT_MAX_EXPANSION);
     assert!(pass);
     assert_eq!(caps.get("p").map(String::as_str), Some("123"));
     assert_eq!(caps.get("x").map(String::as_str), Some("123"));
@@ -139,7 +165,7 @@ fn captures_with_same_key_follow_last_writer() {
     ]);
     let mut ctx = ctx_with_host("api.example.com");
     ctx.path = "123".to_string();
-    let (pass, caps) = eval_cond(&cond, &ctx);
+    let (pass, caps) = eval_cond(&cond, &ctx, DEFAULT_MAX_EXPANSION);
     assert!(pass);
     // path capture should overwrite host capture because it runs later
     assert_eq!(caps.get("id").map(String::as_str), Some("123"));
@@ -153,7 +179,7 @@ fn host_pattern_context_compiles_and_captures() {
         cond: CompiledBasicCond::Pattern(pattern),
     });
     let ctx = ctx_with_host("api.example.com");
-    let (pass, caps) = eval_cond(&cond, &ctx);
+    let (pass, caps) = eval_cond(&cond, &ctx, DEFAULT_MAX_EXPANSION);
     assert!(pass);
     assert_eq!(caps.get("sub").map(String::as_str), Some("api"));
 }
@@ -171,7 +197,7 @@ fn equals_and_present_do_not_capture() {
         }),
     ]);
     let ctx = ctx_with_path("/foo");
-    let (pass, caps) = eval_cond(&cond, &ctx);
+    let (pass, caps) = eval_cond(&cond, &ctx, DEFAULT_MAX_EXPANSION);
     assert!(pass);
     assert!(caps.is_empty());
 }
@@ -198,7 +224,7 @@ fn expand(tpl: &CompiledTemplate, map: &[(&str, &str)]) -> String {
     for (k, v) in map {
         prov.map.insert(k.to_string(), v.to_string());
     }
-    expand_template(tpl, &prov).unwrap()
+    expand_template(tpl, &prov, &ExpandOptions::default()).unwrap()
 }
 
 #[test]
@@ -241,18 +267,28 @@ fn template_header_and_query_case_insensitive() {
     let mut ctx = RouterCtx {
         method: None,
         scheme: None,
+        tls: false,
+        sni: None,
         host: String::new(),
         port: None,
         path: String::new(),
-        query: HashMap::new(),
+        query: IndexMap::new(),
         headers: HashMap::new(),
         cookies: HashMap::new(),
+        form: HashMap::new(),
+        multipart: Default::default(),
+        subresponse: None,
+        client_cert: None,
+        remote_ip: None,
+        target_form: "origin",
+        now: std::time::SystemTime::UNIX_EPOCH,
+        response_headers: Vec::new(),
         captures: HashMap::new(),
     };
     ctx.headers.insert("x-foo".into(), vec!["Bar".into()]);
     ctx.query.insert("q".into(), vec!["1".into()]);
     let t = tpl("h=${header.X-Foo},q=${query.q}");
-    let out = expand_template(&t, &ctx).unwrap();
+    let out = expand_template(&t, &ctx, &ExpandOptions::default()).unwrap();
     assert_eq!(out, "h=Bar,q=1");
 }
 
@@ -262,6 +298,1675 @@ fn template_capture_overwrites() {
     ctx.captures.insert("id".into(), "111".into());
     ctx.captures.insert("id".into(), "222".into());
     let t = tpl("${id}");
-    let out = expand_template(&t, &ctx).unwrap();
+    let out = expand_template(&t, &ctx, &ExpandOptions::default()).unwrap();
     assert_eq!(out, "222");
 }
+
+// --- query tests ---
+
+#[test]
+fn sort_query_emits_canonical_order() {
+    let mut ctx = ctx_with_path("/search");
+    ctx.query.insert("z".into(), vec!["1".into()]);
+    ctx.query.insert("b".into(), vec!["3".into(), "2".into()]);
+    ctx.query.insert("a".into(), vec!["x".into()]);
+    assert_eq!(request_target(&ctx), "/search?z=1&b=3&b=2&a=x");
+
+    ctx.sort_query();
+    assert_eq!(request_target(&ctx), "/search?a=x&b=2&b=3&z=1");
+}
+
+// --- metrics tests ---
+
+const METRICS_ROUTER: &str = r#"
+handler: router
+rules:
+  - when: { path: /never }
+    ops:
+      - respond: { status: 204 }
+  - name: hello
+    when: { path: /hello }
+    ops:
+      - respond: { status: 200, body: hi }
+  - when: { path: /metrics }
+    ops: [metrics]
+"#;
+
+#[tokio::test]
+async fn matching_rule_increments_only_its_counter() {
+    let svc = testing::service(METRICS_ROUTER);
+    let LoadedService::Router(router) = &svc else { panic!("expected router") };
+    let metrics = router.metrics.clone();
+    let addr = testing::spawn(svc).await;
+
+    assert_eq!(testing::get(addr, "/hello").await.status(), 200);
+    testing::get(addr, "/hello").await;
+
+    let rendered = metrics.render();
+    assert!(rendered.contains("oxidase_router_rule_matches_total{rule=\"0\"} 0"));
+    assert!(rendered.contains("oxidase_router_rule_matches_total{rule=\"hello\"} 2"));
+}
+
+#[tokio::test]
+async fn metrics_op_renders_rule_counters() {
+    let addr = testing::spawn(testing::service(METRICS_ROUTER)).await;
+    testing::get(addr, "/hello").await;
+
+    let resp = testing::get(addr, "/metrics").await;
+    assert_eq!(resp.status(), 200);
+    let body = String::from_utf8(resp.body().to_vec()).unwrap();
+    assert!(body.contains("oxidase_router_rule_matches_total{rule=\"0\"} 0"));
+    assert!(body.contains("oxidase_router_rule_matches_total{rule=\"hello\"} 1"));
+    assert!(body.contains("oxidase_router_rule_matches_total{rule=\"2\"} 1"));
+}
+
+#[tokio::test]
+async fn op_counters_track_runs_per_op_kind() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - when: { path: /metrics }
+    ops: [metrics]
+  - when: { path: /never }
+    ops: [decode_path, goto_next]
+  - ops:
+      - header_set: { x-seen: "1" }
+      - branch:
+          if: { var: path, pattern: "/deny" }
+          then:
+            - respond: { status: 403 }
+      - sort_query
+      - respond: { status: 200 }
+"#)).await;
+    testing::get(addr, "/a").await;
+    testing::get(addr, "/deny").await;
+
+    let body = String::from_utf8(testing::get(addr, "/metrics").await.body().to_vec()).unwrap();
+    assert!(body.contains("oxidase_router_op_runs_total{op=\"header_set\"} 2"));
+    assert!(body.contains("oxidase_router_op_runs_total{op=\"branch\"} 2"));
+    assert!(body.contains("oxidase_router_op_runs_total{op=\"sort_query\"} 1"));
+    assert!(body.contains("oxidase_router_op_runs_total{op=\"respond\"} 2"));
+    // ops that never ran still show up, at zero
+    assert!(body.contains("oxidase_router_op_runs_total{op=\"decode_path\"} 0"));
+}
+
+// --- respond tests ---
+
+#[test]
+fn infer_content_type_from_body_shape() {
+    assert_eq!(infer_content_type(r#" {"ok": true} "#), "application/json");
+    assert_eq!(infer_content_type("[1, 2]"), "application/json");
+    assert_eq!(infer_content_type("<!doctype html><p>hi</p>"), "text/html; charset=utf-8");
+    assert_eq!(infer_content_type("just text"), "text/plain; charset=utf-8");
+}
+
+#[tokio::test]
+async fn respond_auto_content_type_keeps_explicit_header() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - when: { path: /json }
+    ops:
+      - respond: { status: 200, body: '{"a":1}', auto_content_type: true }
+  - when: { path: /explicit }
+    ops:
+      - respond:
+          status: 200
+          body: '{"a":1}'
+          auto_content_type: true
+          headers: { content-type: text/x-custom }
+  - when: { path: /off }
+    ops:
+      - respond: { status: 200, body: '<p>hi</p>' }
+"#)).await;
+
+    let resp = testing::get(addr, "/json").await;
+    assert_eq!(resp.headers()[http::header::CONTENT_TYPE], "application/json");
+
+    let resp = testing::get(addr, "/explicit").await;
+    assert_eq!(resp.headers()[http::header::CONTENT_TYPE], "text/x-custom");
+
+    let resp = testing::get(addr, "/off").await;
+    assert!(resp.headers().get(http::header::CONTENT_TYPE).is_none());
+}
+
+#[tokio::test]
+async fn respond_stream_emits_lines_at_the_interval() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - respond:
+          status: 200
+          body: "path=${path}\n"
+          headers: { content-type: text/event-stream }
+          stream: { interval_ms: 40, line_template: "data: tick ${stream.index}", count: 3 }
+"#)).await;
+
+    let req = http::Request::get("/events")
+        .header(http::header::HOST, "example.com")
+        .body(http_body_util::Full::<bytes::Bytes>::default())
+        .unwrap();
+    let started = std::time::Instant::now();
+    let resp = testing::send_streaming(addr, req).await;
+    assert!(resp.headers().get(http::header::CONTENT_LENGTH).is_none());
+    let mut body = resp.into_body();
+    let mut frames = Vec::new();
+    while let Some(frame) = body.frame().await {
+        if let Ok(data) = frame.unwrap().into_data() {
+            frames.push((started.elapsed(), String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    let text: Vec<&str> = frames.iter().map(|(_, t)| t.as_str()).collect();
+    assert_eq!(text, ["path=/events\n", "data: tick 0\n", "data: tick 1\n", "data: tick 2\n"]);
+    for pair in frames.windows(2) {
+        assert!(pair[1].0 - pair[0].0 >= std::time::Duration::from_millis(30), "{frames:?}");
+    }
+}
+
+#[tokio::test]
+async fn gen_nonce_is_shared_within_a_request() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - gen_nonce: { name: csp }
+      - respond:
+          status: 200
+          body: "<script nonce=\"${csp}\"></script>"
+          headers:
+            content-security-policy: "script-src 'nonce-${csp}'"
+            x-nonce: "${csp}"
+"#)).await;
+
+    let nonce_of = |resp: &http::Response<bytes::Bytes>| {
+        let nonce = resp.headers()["x-nonce"].to_str().unwrap().to_string();
+        assert_eq!(resp.headers()["content-security-policy"], format!("script-src 'nonce-{nonce}'").as_str());
+        assert_eq!(resp.body(), format!("<script nonce=\"{nonce}\"></script>").as_str());
+        nonce
+    };
+    let first = nonce_of(&testing::get(addr, "/").await);
+    let second = nonce_of(&testing::get(addr, "/").await);
+    use base64::Engine;
+    assert_eq!(base64::engine::general_purpose::STANDARD.decode(&first).unwrap().len(), 16);
+    assert_ne!(first, second);
+}
+
+// --- user agent tests ---
+
+async fn get_with_ua(addr: std::net::SocketAddr, path: &str, ua: &str) -> http::StatusCode {
+    let req = http::Request::get(path)
+        .header(http::header::USER_AGENT, ua)
+        .body(http_body_util::Full::default())
+        .unwrap();
+    testing::send(addr, req).await.status()
+}
+
+#[tokio::test]
+async fn require_user_agent_allow_and_deny() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - when: { path: /allow }
+    ops:
+      - require_user_agent: { pattern: "^Mozilla/" }
+      - respond: { status: 200 }
+  - when: { path: /deny }
+    ops:
+      - require_user_agent: { pattern: "(?i)bot", deny: true }
+      - respond: { status: 200 }
+"#)).await;
+
+    assert_eq!(get_with_ua(addr, "/allow", "Mozilla/5.0").await, 200);
+    assert_eq!(get_with_ua(addr, "/allow", "curl/8.0").await, 403);
+    assert_eq!(get_with_ua(addr, "/deny", "Mozilla/5.0").await, 200);
+    assert_eq!(get_with_ua(addr, "/deny", "GoogleBot/2.1").await, 403);
+}
+
+#[tokio::test]
+async fn require_content_type_ignores_parameters() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - require_content_type: [application/json, text/plain]
+      - respond: { status: 200 }
+"#)).await;
+
+    let post = |content_type: Option<&str>| {
+        let mut req = http::Request::post("/api").header(http::header::HOST, "example.com");
+        if let Some(ct) = content_type {
+            req = req.header(http::header::CONTENT_TYPE, ct);
+        }
+        req.body(http_body_util::Full::from("{}")).unwrap()
+    };
+
+    assert_eq!(testing::send(addr, post(Some("application/json; charset=utf-8"))).await.status(), 200);
+    assert_eq!(testing::send(addr, post(Some("Text/Plain"))).await.status(), 200);
+    assert_eq!(testing::send(addr, post(Some("application/xml"))).await.status(), 415);
+    assert_eq!(testing::send(addr, post(None)).await.status(), 415);
+}
+
+#[tokio::test]
+async fn capture_filters_normalize_before_ops_see_the_value() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - when: { path: "/users/<name:slug|lower>" }
+    ops:
+      - set_path: "/u/${name}"
+      - respond: { status: 200, body: "${path}" }
+"#)).await;
+
+    let resp = testing::get(addr, "/users/Alice").await;
+    assert_eq!(resp.body().as_ref(), b"/u/alice");
+}
+
+#[tokio::test]
+async fn oversized_filtered_capture_does_not_match() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+max_template_len: 1024
+rules:
+  - when: { path: "/p/<x:slug|repeat(9999)>" }
+    ops:
+      - respond: { status: 200, body: "matched" }
+  - when: { path: "/q" }
+    ops:
+      - branch:
+          if: { var: query.v, pattern: "<x:slug|repeat(9999)>" }
+          then:
+            - respond: { status: 200, body: "matched" }
+      - respond: { status: 200, body: "no" }
+  - ops:
+      - respond: { status: 404, body: "fallthrough" }
+"#)).await;
+
+    // 9999 bytes is over the router's max_template_len, though under the default
+    assert_eq!(testing::get(addr, "/p/a").await.body().as_ref(), b"fallthrough");
+    assert_eq!(testing::get(addr, "/q?v=a").await.body().as_ref(), b"no");
+}
+
+// --- header op tests ---
+
+#[tokio::test]
+async fn header_set_if_absent_only_fills_missing() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - header_set_if_absent: { accept: "application/json", x-tenant: "${path}" }
+      - respond: { status: 200, body: "${header.accept}|${header.x-tenant}" }
+"#)).await;
+
+    let resp = testing::get(addr, "/t").await;
+    assert_eq!(resp.body().as_ref(), b"application/json|/t");
+
+    let req = http::Request::get("/t")
+        .header(http::header::ACCEPT, "text/html")
+        .body(http_body_util::Full::default())
+        .unwrap();
+    let resp = testing::send(addr, req).await;
+    assert_eq!(resp.body().as_ref(), b"text/html|/t");
+}
+
+#[tokio::test]
+async fn header_dedup_collapses_repeated_values() {
+    // echoes every `x-tag` / `x-other` value it receives, in order
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let svc = hyper::service::service_fn(|req: http::Request<hyper::body::Incoming>| async move {
+                let joined = |name: &str| req.headers().get_all(name).iter()
+                    .map(|v| v.to_str().unwrap().to_string())
+                    .collect::<Vec<_>>()
+                    .join(",");
+                let echoed = format!("{}|{}", joined("x-tag"), joined("x-other"));
+                Ok::<_, hyper::Error>(http::Response::new(http_body_util::Full::<bytes::Bytes>::from(echoed)))
+            });
+            tokio::spawn(hyper::server::conn::http1::Builder::new()
+                .serve_connection(hyper_util::rt::TokioIo::new(stream), svc));
+        }
+    });
+    let addr = testing::spawn(testing::service(&format!(r#"
+handler: router
+rules:
+  - when: {{ path: /raw }}
+    ops:
+      - respond: {{ status: 200, body: "${{header_count}}" }}
+  - when: {{ path: /count }}
+    ops:
+      - header_dedup: [x-tag]
+      - respond: {{ status: 200, body: "${{header_count}}" }}
+  - ops:
+      - header_dedup: [X-Tag]
+      - use:
+          handler: forward
+          target: {{ scheme: http, host: 127.0.0.1, port: {} }}
+"#, upstream.port()))).await;
+
+    let req = |path: &str| {
+        let mut req = http::Request::get(path).header(http::header::HOST, "example.com");
+        for v in ["a", "b", "a", "c", "b"] {
+            req = req.header("x-tag", v);
+        }
+        req.header("x-other", "z").header("x-other", "z")
+            .body(http_body_util::Full::default())
+            .unwrap()
+    };
+    // distinct values keep their first-seen order; unlisted headers are left alone
+    let resp = testing::send(addr, req("/fwd")).await;
+    assert_eq!(resp.body().as_ref(), b"a,b,c|z,z");
+
+    // the router's own view is deduplicated too
+    let raw: usize = std::str::from_utf8(testing::send(addr, req("/raw")).await.body()).unwrap().parse().unwrap();
+    let deduped: usize = std::str::from_utf8(testing::send(addr, req("/count")).await.body()).unwrap().parse().unwrap();
+    assert_eq!(raw - deduped, 2);
+}
+
+// --- testing op tests ---
+
+#[tokio::test]
+async fn delay_holds_response_for_configured_duration() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+enable_testing_ops: true
+rules:
+  - ops:
+      - delay: { ms: 150 }
+      - respond: { status: 200 }
+"#)).await;
+
+    let started = std::time::Instant::now();
+    let resp = testing::get(addr, "/slow").await;
+    assert_eq!(resp.status(), 200);
+    assert!(started.elapsed() >= std::time::Duration::from_millis(150));
+}
+
+#[tokio::test]
+async fn inject_error_rate_roughly_matches_probability() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+enable_testing_ops: true
+rules:
+  - ops:
+      - inject_error: { status: 503, probability: 0.3 }
+      - respond: { status: 200 }
+"#)).await;
+
+    let total = 500;
+    let mut errors = 0;
+    for _ in 0..total {
+        match testing::get(addr, "/").await.status().as_u16() {
+            503 => errors += 1,
+            200 => {}
+            other => panic!("unexpected status {other}"),
+        }
+    }
+    let rate = errors as f64 / total as f64;
+    assert!((0.2..0.4).contains(&rate), "error rate {rate} too far from 0.3");
+}
+
+// --- request context tests ---
+
+fn conn_info(local: &str) -> crate::http_server::ConnInfo {
+    crate::http_server::ConnInfo {
+        local_addr: local.parse().unwrap(),
+        is_tls: false,
+        sni: None,
+        client_cert: None,
+        remote_ip: "192.0.2.7".parse().unwrap(),
+    }
+}
+
+#[test]
+fn port_falls_back_to_listener_port() {
+    let mut req = http::Request::get("/").header(http::header::HOST, "example.com").body(()).unwrap();
+    req.extensions_mut().insert(conn_info("0.0.0.0:8443"));
+    let ctx = RouterCtx::from_request(&req);
+    assert_eq!(ctx.host, "example.com");
+    assert_eq!(ctx.port, Some(8443));
+
+    let mut req = http::Request::get("/").header(http::header::HOST, "example.com:9000").body(()).unwrap();
+    req.extensions_mut().insert(conn_info("0.0.0.0:8443"));
+    assert_eq!(RouterCtx::from_request(&req).port, Some(9000));
+}
+
+#[tokio::test]
+async fn port_variable_reports_listener_port() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - respond: { status: 200, body: "${port}" }
+"#)).await;
+
+    let resp = testing::get(addr, "/").await;
+    assert_eq!(resp.body().as_ref(), addr.port().to_string().as_bytes());
+}
+
+#[test]
+fn tls_connection_reports_https_for_origin_form() {
+    let mut req = http::Request::get("/login").body(()).unwrap();
+    req.extensions_mut().insert(crate::http_server::ConnInfo { is_tls: true, ..conn_info("0.0.0.0:443") });
+    let ctx = RouterCtx::from_request(&req);
+    assert_eq!(ctx.get("scheme").as_deref(), Some("https"));
+    assert_eq!(ctx.get("tls").as_deref(), Some("true"));
+
+    let cond = CompiledCondNode::Test(CompiledTestCond {
+        var: "scheme".to_string(),
+        cond: CompiledBasicCond::Equals(serde_yaml::Value::String("https".into())),
+    });
+    assert!(eval_cond(&cond, &ctx, DEFAULT_MAX_EXPANSION).0);
+
+    let mut req = http::Request::get("/login").body(()).unwrap();
+    req.extensions_mut().insert(conn_info("0.0.0.0:80"));
+    let ctx = RouterCtx::from_request(&req);
+    assert_eq!(ctx.get("scheme").as_deref(), Some("http"));
+    assert_eq!(ctx.get("tls").as_deref(), Some("false"));
+}
+
+// --- redirect tests ---
+
+#[test]
+fn append_query_merges_with_existing() {
+    assert_eq!(append_query("/new", "a=1"), "/new?a=1");
+    assert_eq!(append_query("/new?x=1", "a=1"), "/new?x=1&a=1");
+    assert_eq!(append_query("/new?", "a=1"), "/new?a=1");
+    assert_eq!(append_query("https://h/new#top", "a=1"), "https://h/new?a=1#top");
+}
+
+#[tokio::test]
+async fn redirect_preserve_query_on_and_off() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - when: { path: /keep }
+    ops:
+      - redirect: { status: _302, location: "/new?x=1", preserve_query: true }
+  - when: { path: /drop }
+    ops:
+      - redirect: { status: _302, location: "/new?x=1" }
+"#)).await;
+
+    let resp = testing::get(addr, "/keep?a=1&b=2").await;
+    assert_eq!(resp.status(), 302);
+    assert_eq!(resp.headers()[http::header::LOCATION], "/new?x=1&a=1&b=2");
+
+    let resp = testing::get(addr, "/drop?a=1&b=2").await;
+    assert_eq!(resp.headers()[http::header::LOCATION], "/new?x=1");
+
+    let resp = testing::get(addr, "/keep").await;
+    assert_eq!(resp.headers()[http::header::LOCATION], "/new?x=1");
+}
+
+// --- server timing tests ---
+
+#[tokio::test]
+async fn server_timing_reports_route_and_upstream_durations() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+enable_testing_ops: true
+rules:
+  - when: { path: "/timed" }
+    ops:
+      - server_timing
+      - use:
+          handler: router
+          enable_testing_ops: true
+          rules:
+            - ops:
+                - delay: { ms: 20 }
+                - respond: { status: 200 }
+  - ops:
+      - respond: { status: 200 }
+"#)).await;
+
+    let resp = testing::get(addr, "/timed").await;
+    let value = resp.headers()["server-timing"].to_str().unwrap();
+    let durations: HashMap<&str, f64> = value
+        .split(", ")
+        .map(|metric| {
+            let (name, dur) = metric.split_once(";dur=").expect("malformed metric");
+            (name, dur.parse().expect("non-numeric duration"))
+        })
+        .collect();
+    assert!(durations["route"] >= 0.0);
+    assert!(durations["upstream"] >= 20.0, "{value}");
+
+    let resp = testing::get(addr, "/other").await;
+    assert!(resp.headers().get("server-timing").is_none());
+}
+
+// --- jwt tests ---
+
+fn sign_hs256(claims: &str, key: &[u8]) -> String {
+    use base64::Engine;
+    use hmac::Mac;
+    let b64 = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    let signed = format!("{}.{}", b64.encode(r#"{"alg":"HS256","typ":"JWT"}"#), b64.encode(claims));
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(key).unwrap();
+    mac.update(signed.as_bytes());
+    format!("{signed}.{}", b64.encode(mac.finalize().into_bytes()))
+}
+
+async fn get_with_bearer(addr: std::net::SocketAddr, token: &str) -> http::Response<bytes::Bytes> {
+    let req = http::Request::get("/")
+        .header(http::header::AUTHORIZATION, format!("Bearer {token}"))
+        .body(http_body_util::Full::default())
+        .unwrap();
+    testing::send(addr, req).await
+}
+
+#[tokio::test]
+async fn verify_jwt_exposes_claims_and_rejects_bad_tokens() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - verify_jwt: { key: "secret", claims: [sub, admin] }
+      - respond: { status: 200, body: "${jwt.sub}|${jwt.admin}" }
+"#)).await;
+
+    let valid = sign_hs256(r#"{"sub":"alice","admin":true,"exp":32503680000}"#, b"secret");
+    let resp = get_with_bearer(addr, &valid).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body().as_ref(), b"alice|true");
+
+    let expired = sign_hs256(r#"{"sub":"alice","exp":1000}"#, b"secret");
+    let resp = get_with_bearer(addr, &expired).await;
+    assert_eq!(resp.status(), 401);
+    assert_eq!(resp.headers()[http::header::WWW_AUTHENTICATE], "Bearer");
+
+    let fractional = sign_hs256(r#"{"sub":"alice","exp":1000.5}"#, b"secret");
+    assert_eq!(get_with_bearer(addr, &fractional).await.status(), 401);
+
+    let non_numeric = sign_hs256(r#"{"sub":"alice","exp":"32503680000"}"#, b"secret");
+    assert_eq!(get_with_bearer(addr, &non_numeric).await.status(), 401);
+
+    let forged = sign_hs256(r#"{"sub":"alice"}"#, b"not-the-secret");
+    assert_eq!(get_with_bearer(addr, &forged).await.status(), 401);
+
+    assert_eq!(testing::get(addr, "/").await.status(), 401);
+}
+
+#[tokio::test]
+async fn signed_cookie_round_trips_and_rejects_tampering() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - when: { path: /login }
+    ops:
+      - sign_cookie: { name: session, value_template: "${query.user} ${query.role}", key: "secret" }
+      - respond: { status: 200 }
+  - ops:
+      - verify_cookie: { name: session, key: "secret" }
+      - respond: { status: 200, body: "${signed.session ?? \"anonymous\"}|${cookie.session}" }
+"#)).await;
+
+    let resp = testing::get(addr, "/login?user=alice&role=admin").await;
+    let set_cookie = resp.headers()[http::header::SET_COOKIE].to_str().unwrap().to_string();
+    assert!(set_cookie.ends_with("; Path=/; HttpOnly"));
+    let cookie = set_cookie.split(';').next().unwrap().to_string();
+    assert!(cookie.starts_with("session=alice%20admin."));
+
+    let me = |cookie: &str| http::Request::get("/me")
+        .header(http::header::COOKIE, cookie)
+        .body(http_body_util::Full::default())
+        .unwrap();
+    let resp = testing::send(addr, me(&cookie)).await;
+    assert!(resp.body().starts_with(b"alice admin|alice admin."));
+
+    let tampered = cookie.replacen("alice%20admin", "mallory%20admin", 1);
+    let resp = testing::send(addr, me(&tampered)).await;
+    assert!(resp.body().starts_with(b"anonymous|mallory admin."));
+
+    // a value signed for one cookie is not accepted under another name
+    let renamed = cookie.replacen("session=", "other=", 1) + "; session=x";
+    assert!(testing::send(addr, me(&renamed)).await.body().starts_with(b"anonymous|"));
+    assert!(testing::get(addr, "/me").await.body().starts_with(b"anonymous|"));
+}
+
+#[tokio::test]
+async fn mock_clock_drives_time_windows() {
+    use crate::util::clock::MockClock;
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let mut svc = testing::service(r#"
+handler: router
+rules:
+  - when: { path: "/token" }
+    ops:
+      - verify_jwt: { key: "secret" }
+      - respond: { status: 200 }
+  - ops:
+      - respond: { status: 200, body: "${now.hour}" }
+"#);
+    // 1970-01-01T23:59:00Z
+    let clock = Arc::new(MockClock::new(UNIX_EPOCH + Duration::from_secs(86_340)));
+    let LoadedService::Router(router) = &mut svc else { unreachable!() };
+    router.clock = clock.clone();
+    let addr = testing::spawn(svc).await;
+
+    let token = sign_hs256(r#"{"exp":86400}"#, b"secret");
+    let req = |t: &str| http::Request::get("/token")
+        .header(http::header::AUTHORIZATION, format!("Bearer {t}"))
+        .body(http_body_util::Full::default())
+        .unwrap();
+
+    assert_eq!(testing::get(addr, "/").await.body().as_ref(), b"23");
+    assert_eq!(testing::send(addr, req(&token)).await.status(), 200);
+
+    clock.advance(Duration::from_secs(61));
+    assert_eq!(testing::get(addr, "/").await.body().as_ref(), b"0");
+    assert_eq!(testing::send(addr, req(&token)).await.status(), 401);
+}
+
+#[test]
+fn host_header_with_ipv6_literal() {
+    let req = http::Request::get("/").header(http::header::HOST, "[::1]:8080").body(()).unwrap();
+    let ctx = RouterCtx::from_request(&req);
+    assert_eq!((ctx.host.as_str(), ctx.port), ("[::1]", Some(8080)));
+
+    let req = http::Request::get("/").header(http::header::HOST, "[::1]").body(()).unwrap();
+    let ctx = RouterCtx::from_request(&req);
+    assert_eq!((ctx.host.as_str(), ctx.port), ("[::1]", None));
+
+    // an unbracketed IPv6 address is not a valid Host
+    let req = http::Request::get("/").header(http::header::HOST, "::1").body(()).unwrap();
+    let ctx = RouterCtx::from_request(&req);
+    assert_eq!((ctx.host.as_str(), ctx.port), ("", None));
+}
+
+#[tokio::test]
+async fn malformed_host_is_rejected() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - respond: { status: 200, body: "${host}" }
+"#)).await;
+
+    for (host, status) in [("[::1]:8080", 200), ("::1", 400), ("user@example.com", 400), ("exa mple.com", 400)] {
+        let req = http::Request::get("/")
+            .header(http::header::HOST, host)
+            .body(http_body_util::Full::default())
+            .unwrap();
+        assert_eq!(testing::send(addr, req).await.status(), status, "{host}");
+    }
+}
+
+// --- presence condition tests ---
+
+#[tokio::test]
+async fn absent_header_condition_checks_presence_only() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - when:
+      headers: [{ name: x-debug, absent: true }]
+      queries: [{ key: trace, absent: true, not: true }]
+    ops:
+      - respond: { status: 200 }
+  - ops:
+      - respond: { status: 403 }
+"#)).await;
+
+    let status = |path: &'static str, debug: Option<&'static str>| async move {
+        let mut req = http::Request::get(path);
+        if let Some(v) = debug {
+            req = req.header("x-debug", v);
+        }
+        testing::send(addr, req.body(http_body_util::Full::default()).unwrap()).await.status()
+    };
+
+    assert_eq!(status("/?trace", None).await, 200);
+    assert_eq!(status("/?trace", Some("")).await, 403);
+    assert_eq!(status("/?trace", Some("1")).await, 403);
+    assert_eq!(status("/", None).await, 403);
+}
+
+#[tokio::test]
+async fn captures_json_echoes_pattern_captures() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - when: { path: "/<category:slug>/<id:uint>" }
+    ops:
+      - respond: { status: 200, body: "${captures_json}" }
+"#)).await;
+
+    let resp = testing::get(addr, "/rust/42").await;
+    assert_eq!(resp.body().as_ref(), br#"{"category":"rust","id":"42"}"#);
+}
+
+#[test]
+fn target_form_reflects_how_the_target_was_written() {
+    let ctx = |method: http::Method, target: &str| {
+        let req = http::Request::builder()
+            .method(method)
+            .uri(target)
+            .header(http::header::HOST, "example.com")
+            .body(())
+            .unwrap();
+        RouterCtx::from_request(&req)
+    };
+
+    let origin = ctx(http::Method::GET, "/a?b=1");
+    assert_eq!(origin.get("target_form").as_deref(), Some("origin"));
+    assert_eq!(origin.host, "example.com");
+
+    let absolute = ctx(http::Method::GET, "http://Other.test:8080/a?b=1");
+    assert_eq!(absolute.get("target_form").as_deref(), Some("absolute"));
+    assert_eq!((absolute.host.as_str(), absolute.port), ("Other.test", Some(8080)));
+    assert_eq!(absolute.scheme.as_deref(), Some("http"));
+    assert_eq!(request_target(&absolute), "/a?b=1");
+
+    let authority = ctx(http::Method::CONNECT, "tunnel.test:443");
+    assert_eq!(authority.get("target_form").as_deref(), Some("authority"));
+    assert_eq!((authority.host.as_str(), authority.port), ("tunnel.test", Some(443)));
+
+    let asterisk = ctx(http::Method::OPTIONS, "*");
+    assert_eq!(asterisk.get("target_form").as_deref(), Some("asterisk"));
+    assert_eq!(asterisk.host, "example.com");
+}
+
+#[tokio::test]
+async fn absolute_form_requests_route_on_their_own_host() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - when: { host: other.test, path: /x }
+    ops:
+      - respond: { status: 200, body: "${target_form} ${host} ${port} ${path} ${query.y}" }
+"#)).await;
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream.write_all(
+        b"GET http://other.test:8080/x?y=1 HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n",
+    ).await.unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).await.unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200"), "{resp}");
+    assert!(resp.ends_with("absolute other.test 8080 /x 1"), "{resp}");
+}
+
+#[tokio::test]
+async fn rules_match_on_client_cert_fields() {
+    use crate::handler::ServiceHandler;
+
+    let svc = testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - branch:
+          if: { var: cert.cn, pattern: "<:regex(svc-[a-z]+)>" }
+          then:
+            - respond: { status: 200, body: "${cert.cn}|${cert.san}|${cert.fingerprint}" }
+          else:
+            - respond: { status: 403, body: "[${cert.cn}]" }
+"#);
+    let request = |cert: Option<crate::http_server::ClientCert>| {
+        let mut req = http::Request::get("/")
+            .header(http::header::HOST, "example.com")
+            .body(crate::handler::full(bytes::Bytes::new()))
+            .unwrap();
+        req.extensions_mut().insert(crate::http_server::ConnInfo {
+            is_tls: true,
+            client_cert: cert.map(std::sync::Arc::new),
+            ..conn_info("0.0.0.0:443")
+        });
+        req
+    };
+    let cert = |cn: &str| crate::http_server::ClientCert {
+        cn: Some(cn.into()),
+        san: vec!["billing.internal".into(), "10.0.0.5".into()],
+        fingerprint: "ab12".into(),
+    };
+
+    let resp = svc.handle_request(&mut request(Some(cert("svc-billing")))).await;
+    assert_eq!(resp.status(), 200);
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body, "svc-billing|billing.internal,10.0.0.5|ab12");
+
+    assert_eq!(svc.handle_request(&mut request(Some(cert("alice")))).await.status(), 403);
+    let resp = svc.handle_request(&mut request(None)).await;
+    assert_eq!(resp.status(), 403);
+    assert_eq!(resp.into_body().collect().await.unwrap().to_bytes(), "[]");
+}
+
+#[test]
+fn sni_is_exposed_for_routing() {
+    let mut req = http::Request::get("/").header(http::header::HOST, "example.com").body(()).unwrap();
+    req.extensions_mut().insert(crate::http_server::ConnInfo {
+        is_tls: true,
+        sni: Some("api.example.net".into()),
+        ..conn_info("0.0.0.0:443")
+    });
+    let ctx = RouterCtx::from_request(&req);
+    assert_eq!(ctx.get("sni").as_deref(), Some("api.example.net"));
+    assert_eq!(ctx.host, "example.com");
+
+    let cond = CompiledCondNode::Test(CompiledTestCond {
+        var: "sni".to_string(),
+        cond: CompiledBasicCond::Equals(serde_yaml::Value::String("api.example.net".into())),
+    });
+    assert!(eval_cond(&cond, &ctx, DEFAULT_MAX_EXPANSION).0);
+
+    let mut req = http::Request::get("/").body(()).unwrap();
+    req.extensions_mut().insert(conn_info("0.0.0.0:80"));
+    assert_eq!(RouterCtx::from_request(&req).get("sni"), None);
+}
+
+// --- host rewrite tests ---
+
+#[tokio::test]
+async fn rewrite_host_off_keeps_original_host_header() {
+    let yaml = |rewrite: bool| format!(r#"
+handler: router
+rewrite_host: {rewrite}
+rules:
+  - on_match: continue
+    ops:
+      - set_host: internal.local
+  - ops:
+      - use:
+          handler: router
+          rules:
+            - ops:
+                - respond: {{ status: 200, body: "${{header.host}}" }}
+"#);
+
+    let addr = testing::spawn(testing::service(&yaml(true))).await;
+    assert_eq!(testing::get(addr, "/").await.body().as_ref(), b"internal.local");
+
+    let addr = testing::spawn(testing::service(&yaml(false))).await;
+    assert_eq!(testing::get(addr, "/").await.body().as_ref(), b"example.com");
+}
+
+// --- form body tests ---
+
+async fn echo_body_upstream() -> std::net::SocketAddr {
+    use http_body_util::BodyExt;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let svc = hyper::service::service_fn(|req: http::Request<hyper::body::Incoming>| async move {
+                let body = req.into_body().collect().await?.to_bytes();
+                Ok::<_, hyper::Error>(http::Response::new(http_body_util::Full::new(body)))
+            });
+            tokio::spawn(hyper::server::conn::http1::Builder::new()
+                .serve_connection(hyper_util::rt::TokioIo::new(stream), svc));
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn form_field_routes_and_body_forwards_unchanged() {
+    let upstream = echo_body_upstream().await;
+    let addr = testing::spawn(testing::service(&format!(r#"
+handler: router
+rules:
+  - ops:
+      - branch:
+          if: {{ var: form.action, is: delete }}
+          then:
+            - respond: {{ status: 403, body: "refused: ${{form.note}}" }}
+          else:
+            - use:
+                handler: forward
+                target: {{ scheme: http, host: 127.0.0.1, port: {} }}
+"#, upstream.port()))).await;
+
+    let post = |content_type: &str, body: &'static str| {
+        http::Request::post("/submit")
+            .header(http::header::HOST, "example.com")
+            .header(http::header::CONTENT_TYPE, content_type)
+            .body(http_body_util::Full::new(bytes::Bytes::from_static(body.as_bytes())))
+            .unwrap()
+    };
+    let form = "application/x-www-form-urlencoded; charset=utf-8";
+
+    let resp = testing::send(addr, post(form, "action=delete&note=a+b%2Fc")).await;
+    assert_eq!(resp.status(), 403);
+    assert_eq!(resp.body().as_ref(), b"refused: a b/c");
+
+    let resp = testing::send(addr, post(form, "action=update&note=a+b%2Fc")).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body().as_ref(), b"action=update&note=a+b%2Fc");
+
+    // other content types are not parsed as forms
+    let resp = testing::send(addr, post("text/plain", "action=delete")).await;
+    assert_eq!(resp.body().as_ref(), b"action=delete");
+}
+
+#[tokio::test]
+async fn multipart_field_presence_routes_and_upload_streams_through() {
+    use crate::config::http_server::{OverLimit, RequestBodyPolicy};
+
+    let upstream = echo_body_upstream().await;
+    let svc = testing::service(&format!(r#"
+handler: router
+rules:
+  - ops:
+      - branch:
+          if: {{ var: multipart.has.file, is: "true" }}
+          then:
+            - use:
+                handler: forward
+                target: {{ scheme: http, host: 127.0.0.1, port: {} }}
+          else:
+            - respond: {{ status: 400, body: "no file" }}
+"#, upstream.port()));
+    // a tiny buffering cap so uploads stream instead of being buffered
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let policy = RequestBodyPolicy { max_buffer_bytes: 16, over_limit: OverLimit::Stream };
+    tokio::spawn(crate::http_server::serve(listener, None, std::sync::Arc::new(svc), policy, Default::default(), Vec::new()));
+
+    let upload = |parts: &[(&str, String)]| {
+        let mut body = String::new();
+        for (disposition, content) in parts {
+            body.push_str(&format!("--XyZ\r\nContent-Disposition: form-data; {disposition}\r\n\r\n{content}\r\n"));
+        }
+        body.push_str("--XyZ--\r\n");
+        http::Request::post("/upload")
+            .header(http::header::HOST, "example.com")
+            .header(http::header::CONTENT_TYPE, "multipart/form-data; boundary=XyZ")
+            .body(http_body_util::Full::new(bytes::Bytes::from(body)))
+            .unwrap()
+    };
+
+    let data = "0123456789".repeat(20_000);
+    let req = upload(&[
+        ("name=\"note\"", "hi".into()),
+        ("name=\"file\"; filename=\"a.bin\"", data.clone()),
+    ]);
+    let sent = req.body().clone().collect().await.unwrap().to_bytes();
+    let resp = testing::send(addr, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body(), &sent);
+
+    let resp = testing::send(addr, upload(&[("name=\"note\"", data)])).await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[tokio::test]
+async fn debug_log_records_request_and_forwards_body() {
+    let upstream = echo_body_upstream().await;
+    let (logs, _guard) = testing::CapturedLogs::install(tracing::Level::INFO);
+    let addr = testing::spawn(testing::service(&format!(r#"
+handler: router
+rules:
+  - ops:
+      - debug_log: {{ level: info, include_body: true }}
+      - use:
+          handler: forward
+          target: {{ scheme: http, host: 127.0.0.1, port: {} }}
+"#, upstream.port()))).await;
+
+    let post = |body: String| {
+        http::Request::post("/submit?x=1")
+            .header(http::header::HOST, "example.com")
+            .header("x-trace", "abc")
+            .body(http_body_util::Full::new(bytes::Bytes::from(body)))
+            .unwrap()
+    };
+
+    let resp = testing::send(addr, post("hello=world".into())).await;
+    assert_eq!(resp.body().as_ref(), b"hello=world");
+    let out = logs.contents();
+    assert!(out.contains("debug_log"), "{out}");
+    assert!(out.contains("method=POST"), "{out}");
+    assert!(out.contains("uri=/submit?x=1"), "{out}");
+    assert!(out.contains(r#""x-trace": "abc""#), "{out}");
+    assert!(out.contains(r#"body=Some("hello=world")"#), "{out}");
+
+    // the logged body is capped, the forwarded one is not
+    let big = "a".repeat(10_000);
+    let resp = testing::send(addr, post(big.clone())).await;
+    assert_eq!(resp.body().as_ref(), big.as_bytes());
+    assert!(logs.contents().contains(&format!("{}...", "a".repeat(4096))));
+    assert!(!logs.contents().contains(&"a".repeat(4097)));
+}
+
+#[tokio::test]
+async fn debug_log_redacts_credentials() {
+    let (logs, _guard) = testing::CapturedLogs::install(tracing::Level::INFO);
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - debug_log: { level: info }
+      - respond: { status: 200 }
+"#)).await;
+
+    let req = http::Request::get("/")
+        .header(http::header::AUTHORIZATION, "Bearer tok-123")
+        .header(http::header::PROXY_AUTHORIZATION, "Basic cHJveHk=")
+        .header(http::header::COOKIE, "session=abc")
+        .header(http::header::COOKIE, "theme=dark")
+        .header("x-trace", "kept")
+        .body(http_body_util::Full::default())
+        .unwrap();
+    assert_eq!(testing::send(addr, req).await.status(), 200);
+    let out = logs.contents();
+    for secret in ["tok-123", "cHJveHk=", "session=abc", "theme=dark"] {
+        assert!(!out.contains(secret), "{out}");
+    }
+    assert!(out.contains(r#""authorization": "<redacted>""#), "{out}");
+    assert!(out.contains(r#""x-trace": "kept""#), "{out}");
+}
+
+// --- path decoding tests ---
+
+#[tokio::test]
+async fn path_decoded_matches_percent_encoded_letters() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - when: { path: "/A" }
+    ops:
+      - respond: { status: 200, body: "raw" }
+  - when: { path_decoded: "/A/<rest:segment>" }
+    ops:
+      - respond: { status: 200, body: "decoded ${rest} ${path} ${path_decoded}" }
+  - ops:
+      - respond: { status: 404 }
+"#)).await;
+
+    assert_eq!(testing::get(addr, "/A").await.body().as_ref(), b"raw");
+    let resp = testing::get(addr, "/%41/b%2Fc").await;
+    assert_eq!(resp.body().as_ref(), b"decoded b%2Fc /%41/b%2Fc /A/b%2Fc");
+    assert_eq!(testing::get(addr, "/%41").await.status(), 404);
+}
+
+// --- case-insensitive path tests ---
+
+#[tokio::test]
+async fn path_case_insensitive_matches_mixed_case_and_keeps_capture_case() {
+    let yaml = |nocase: bool| format!(r#"
+handler: router
+rules:
+  - when: {{ path: "/<prefix:segment>/users", path_case_insensitive: {nocase} }}
+    ops:
+      - respond: {{ status: 200, body: "${{prefix}}" }}
+"#);
+
+    let addr = testing::spawn(testing::service(&yaml(true))).await;
+    let resp = testing::get(addr, "/API/Users").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body().as_ref(), b"API");
+
+    let addr = testing::spawn(testing::service(&yaml(false))).await;
+    assert_eq!(testing::get(addr, "/API/Users").await.status(), 404);
+    assert_eq!(testing::get(addr, "/API/users").await.status(), 200);
+}
+
+// --- embedded asset tests ---
+
+#[tokio::test]
+async fn respond_serves_registered_embedded_asset() {
+    crate::embedded::register("test-favicon.ico", b"\x00\x00\x01\x00icon");
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - respond: { status: 200, embedded: test-favicon.ico, auto_content_type: true }
+"#)).await;
+
+    let resp = testing::get(addr, "/favicon.ico").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()[http::header::CONTENT_TYPE], "image/x-icon");
+    assert_eq!(resp.body().as_ref(), b"\x00\x00\x01\x00icon");
+}
+
+#[tokio::test]
+async fn build_script_embeds_files_by_relative_path() {
+    // what `register_builtin` registers for a build with OXIDASE_EMBED_DIR=tests/fixtures/embedded
+    crate::embedded::register_all(crate::embedded::FIXTURE);
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - respond: { status: 404, embedded: errors/fixture-404.html, auto_content_type: true }
+"#)).await;
+
+    let resp = testing::get(addr, "/missing").await;
+    assert_eq!(resp.status(), 404);
+    assert_eq!(resp.headers()[http::header::CONTENT_TYPE], "text/html");
+    assert_eq!(resp.body().as_ref(), include_bytes!("../../../tests/fixtures/embedded/errors/fixture-404.html"));
+}
+
+// --- copy_value tests ---
+
+#[tokio::test]
+async fn copy_value_moves_header_to_query_and_capture_to_header() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - when: { path: "/users/<id:uint>" }
+    ops:
+      - copy_value: { from: header.x-user, to: query.user }
+      - copy_value: { from: id, to: header.x-user-id }
+      - copy_value: { from: header.x-missing, to: query.missing }
+      - use:
+          handler: router
+          rules:
+            - ops:
+                - respond: { status: 200, body: "${path}?${query.user}|${header.x-user-id}|${query.missing|default(none)}" }
+"#)).await;
+
+    let req = http::Request::get("/users/42")
+        .header(http::header::HOST, "example.com")
+        .header("x-user", "alice")
+        .body(http_body_util::Full::new(bytes::Bytes::new()))
+        .unwrap();
+    let resp = testing::send(addr, req).await;
+    assert_eq!(resp.body().as_ref(), b"/users/42?alice|42|none");
+}
+
+// --- redirect loop guard tests ---
+
+#[tokio::test]
+async fn redirect_loop_guard_catches_self_redirect() {
+    let yaml = |guard: bool| format!(r#"
+handler: router
+redirect_loop_guard: {guard}
+rules:
+  - when: {{ path: /self }}
+    ops:
+      - redirect: {{ status: _302, location: "/self", preserve_query: true }}
+  - when: {{ path: /header }}
+    ops:
+      - respond: {{ status: 301, headers: {{ location: "http://example.com/header" }} }}
+  - when: {{ path: /upgrade }}
+    ops:
+      - redirect: {{ status: _301, location: "https://example.com/upgrade" }}
+  - ops:
+      - redirect: {{ status: _302, location: "/elsewhere" }}
+"#);
+
+    let addr = testing::spawn(testing::service(&yaml(true))).await;
+    let resp = testing::get(addr, "/self?a=1").await;
+    assert_eq!(resp.status(), 500);
+    assert!(String::from_utf8_lossy(resp.body()).contains("redirect loop"));
+    assert_eq!(testing::get(addr, "/header").await.status(), 500);
+    assert_eq!(testing::get(addr, "/upgrade").await.status(), 301);
+    assert_eq!(testing::get(addr, "/other").await.status(), 302);
+
+    let addr = testing::spawn(testing::service(&yaml(false))).await;
+    assert_eq!(testing::get(addr, "/self").await.status(), 302);
+}
+
+// --- cookie decoding tests ---
+
+#[tokio::test]
+async fn cookie_percent_decoding_can_be_disabled() {
+    let yaml = |decode: bool| format!(r#"
+handler: router
+decode_cookies: {decode}
+rules:
+  - when:
+      cookies:
+        - {{ name: sig, pattern: "ab%2Bcd==" }}
+    ops:
+      - respond: {{ status: 200, body: "raw:${{cookie.sig}}" }}
+  - ops:
+      - respond: {{ status: 200, body: "decoded:${{cookie.sig}}" }}
+"#);
+    let req = || http::Request::get("/")
+        .header(http::header::HOST, "example.com")
+        .header(http::header::COOKIE, "sig=ab%2Bcd==")
+        .body(http_body_util::Full::new(bytes::Bytes::new()))
+        .unwrap();
+
+    let addr = testing::spawn(testing::service(&yaml(true))).await;
+    assert_eq!(testing::send(addr, req()).await.body().as_ref(), b"decoded:ab+cd==");
+
+    let addr = testing::spawn(testing::service(&yaml(false))).await;
+    assert_eq!(testing::send(addr, req()).await.body().as_ref(), b"raw:ab%2Bcd==");
+}
+
+#[tokio::test]
+async fn repeated_cookie_matches_on_any_value() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - when:
+      cookies:
+        - { name: session, pattern: "admin-<id:uint>" }
+    ops:
+      - respond: { status: 200, body: "admin ${id}, first ${cookie.session}" }
+  - ops:
+      - respond: { status: 403 }
+"#)).await;
+    let req = |cookie: &str| http::Request::get("/")
+        .header(http::header::HOST, "example.com")
+        .header(http::header::COOKIE, cookie)
+        .body(http_body_util::Full::new(bytes::Bytes::new()))
+        .unwrap();
+
+    let resp = testing::send(addr, req("session=admin-7; session=guest")).await;
+    assert_eq!(resp.body().as_ref(), b"admin 7, first admin-7");
+
+    let resp = testing::send(addr, req("session=guest; session=admin-9")).await;
+    assert_eq!(resp.body().as_ref(), b"admin 9, first guest");
+
+    assert_eq!(testing::send(addr, req("session=guest")).await.status(), 403);
+}
+
+// --- count variable tests ---
+
+#[test]
+fn count_variables_reflect_request() {
+    let req = http::Request::get("/?a=1&a=2&b=3")
+        .header(http::header::HOST, "example.com")
+        .header(http::header::COOKIE, "x=1; y=2")
+        .body(())
+        .unwrap();
+    let ctx = RouterCtx::from_request(&req);
+    assert_eq!(ctx.get("query_count").as_deref(), Some("3"));
+    assert_eq!(ctx.get("header_count").as_deref(), Some("2"));
+    assert_eq!(ctx.get("cookie_count").as_deref(), Some("2"));
+
+    let test = |cond: CompiledBasicCond| eval_cond(&CompiledCondNode::Test(CompiledTestCond {
+        var: "query_count".to_string(),
+        cond,
+    }), &ctx, DEFAULT_MAX_EXPANSION).0;
+    assert!(test(CompiledBasicCond::Compare { gt: None, gte: Some(3.0), lt: None, lte: None }));
+    assert!(!test(CompiledBasicCond::Compare { gt: Some(3.0), gte: None, lt: None, lte: None }));
+    assert!(test(CompiledBasicCond::Compare { gt: Some(1.0), gte: None, lt: Some(4.0), lte: None }));
+}
+
+#[tokio::test]
+async fn too_many_query_params_are_rejected() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - branch:
+          if: { var: query_count, gte: 3 }
+          then:
+            - respond: { status: 400, body: "too many params" }
+          else:
+            - respond: { status: 200, body: "${query_count}" }
+"#)).await;
+
+    let resp = testing::get(addr, "/?a=1&b=2").await;
+    assert_eq!(resp.body().as_ref(), b"2");
+    assert_eq!(testing::get(addr, "/?a=1&b=2&c=3").await.status(), 400);
+}
+
+#[tokio::test]
+async fn rewrite_path_uses_its_own_captures() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - rewrite_path: { from: "/users/<id:uint>", to: "/internal/users/${id}" }
+      - respond: { status: 200, body: "${path}" }
+"#)).await;
+
+    let resp = testing::get(addr, "/users/42").await;
+    assert_eq!(resp.body().as_ref(), b"/internal/users/42");
+    // Non-matching paths pass through unchanged.
+    let resp = testing::get(addr, "/users/abc").await;
+    assert_eq!(resp.body().as_ref(), b"/users/abc");
+}
+
+#[test]
+fn empty_condition_separates_absent_blank_and_set() {
+    let ctx_with = |note: Option<&str>| {
+        let mut req = http::Request::get("/").header(http::header::HOST, "example.com");
+        if let Some(v) = note {
+            req = req.header("x-note", v);
+        }
+        RouterCtx::from_request(&req.body(()).unwrap())
+    };
+    let test = |cond: CompiledBasicCond, ctx: &RouterCtx| eval_cond(&CompiledCondNode::Test(CompiledTestCond {
+        var: "header.x-note".to_string(),
+        cond,
+    }), ctx, DEFAULT_MAX_EXPANSION).0;
+
+    let absent = ctx_with(None);
+    let blank = ctx_with(Some(""));
+    let set = ctx_with(Some("hi"));
+    assert!(test(CompiledBasicCond::Empty(true), &absent));
+    assert!(test(CompiledBasicCond::Empty(true), &blank));
+    assert!(!test(CompiledBasicCond::Empty(true), &set));
+    assert!(test(CompiledBasicCond::Empty(false), &set));
+    // `present` plus `empty` singles out a header that was sent blank.
+    assert!(!test(CompiledBasicCond::Present(true), &absent));
+    assert!(test(CompiledBasicCond::Present(true), &blank));
+}
+
+#[tokio::test]
+async fn decode_path_keeps_encoded_slashes() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - decode_path
+      - branch:
+          if: { var: path, pattern: "/files/<name:segment>" }
+          then:
+            - respond: { status: 200, body: "${name}" }
+          else:
+            - respond: { status: 404, body: "${path}" }
+"#)).await;
+
+    let resp = testing::get(addr, "/files/%41b%2Fc").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body().as_ref(), b"Ab%2Fc");
+    assert_eq!(testing::get(addr, "/files/%ff").await.status(), 400);
+}
+
+#[tokio::test]
+async fn higher_priority_rule_runs_before_earlier_rules() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - name: default
+    priority: -1
+    ops:
+      - respond: { status: 200, body: default }
+  - name: plain-urgent
+    when: { path: "/urgent" }
+    ops:
+      - respond: { status: 200, body: plain-urgent }
+  - name: first-urgent
+    priority: 10
+    when: { path: "/urgent" }
+    ops:
+      - respond: { status: 200, body: first-urgent }
+  - name: second-urgent
+    priority: 10
+    when: { path: "/urgent" }
+    ops:
+      - respond: { status: 200, body: second-urgent }
+"#)).await;
+
+    assert_eq!(testing::get(addr, "/urgent").await.body().as_ref(), b"first-urgent");
+    assert_eq!(testing::get(addr, "/other").await.body().as_ref(), b"default");
+}
+
+#[tokio::test]
+async fn query_string_variable_reflects_query_ops() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - when: { path: /raw }
+    ops:
+      - respond: { status: 200, body: "${query_string}" }
+  - ops:
+      - query_delete: [drop]
+      - query_set: { note: "a b&c" }
+      - redirect: { status: _302, location: "/next?${query_string}" }
+"#)).await;
+
+    let resp = testing::get(addr, "/raw?a=1&b=x%20y&a=2").await;
+    assert_eq!(resp.body().as_ref(), b"a=1&a=2&b=x%20y");
+
+    let resp = testing::get(addr, "/go?keep=1&drop=2").await;
+    assert_eq!(resp.headers()[http::header::LOCATION], "/next?keep=1&note=a%20b%26c");
+}
+
+#[tokio::test]
+async fn trace_and_extension_methods_can_be_matched_and_forwarded() {
+    let upstream = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - respond: { status: 200, body: "upstream ${method}" }
+"#)).await;
+    let addr = testing::spawn(testing::service(&format!(r#"
+handler: router
+rules:
+  - when: {{ methods: [TRACE] }}
+    ops:
+      - respond: {{ status: 200, body: "trace" }}
+  - when: {{ methods: [GET] }}
+    ops:
+      - respond: {{ status: 200, body: "get" }}
+next:
+  handler: forward
+  target: {{ scheme: http, host: 127.0.0.1, port: {} }}
+"#, upstream.port()))).await;
+
+    let send = |method: &'static str| async move {
+        let req = http::Request::builder()
+            .method(method)
+            .uri("/")
+            .header(http::header::HOST, "example.com")
+            .body(http_body_util::Full::default())
+            .unwrap();
+        testing::send(addr, req).await
+    };
+    assert_eq!(send("TRACE").await.body().as_ref(), b"trace");
+    assert_eq!(send("PROPFIND").await.body().as_ref(), b"upstream PROPFIND");
+}
+
+#[tokio::test]
+async fn goto_next_in_branch_skips_remaining_rules() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - on_match: continue
+    ops:
+      - set_path: "/legacy${path}"
+      - branch:
+          if: { var: header.x-legacy, present: true }
+          then: [goto_next]
+      - header_set: { x-after: "unreachable" }
+  - ops:
+      - respond: { status: 200, body: "rules ${path}" }
+next:
+  handler: router
+  rules:
+    - ops:
+        - respond: { status: 200, body: 'next ${path} ${header.x-after ?? "-"}' }
+"#)).await;
+
+    let req = http::Request::get("/a")
+        .header(http::header::HOST, "example.com")
+        .header("x-legacy", "1")
+        .body(http_body_util::Full::default())
+        .unwrap();
+    assert_eq!(testing::send(addr, req).await.body().as_ref(), b"next /legacy/a -");
+    assert_eq!(testing::get(addr, "/a").await.body().as_ref(), b"rules /legacy/a");
+}
+
+#[tokio::test]
+async fn preload_emits_one_link_header_for_all_resources() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - preload:
+          - { href: /app.js, as: script }
+          - { href: "/themes/${query.theme ?? \"light\"}.css", as: style }
+          - { href: /fonts/inter.woff2, as: font, crossorigin: true }
+      - respond: { status: 200 }
+"#)).await;
+
+    let resp = testing::get(addr, "/?theme=dark").await;
+    assert_eq!(
+        resp.headers()[http::header::LINK],
+        "</app.js>; rel=preload; as=script, </themes/dark.css>; rel=preload; as=style, \
+         </fonts/inter.woff2>; rel=preload; as=font; crossorigin",
+    );
+
+    let bad = serde_yaml::from_str::<crate::config::service::Service>(r#"
+handler: router
+rules:
+  - ops:
+      - preload: [{ href: /app.js, as: javascript }]
+      - respond: { status: 200 }
+"#);
+    assert!(bad.is_err());
+}
+
+#[tokio::test]
+async fn template_missing_default_applies_router_wide() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+template_missing_default: "n/a"
+rules:
+  - ops:
+      - respond: { status: 200, body: '${header.x-user}/${query.lang | default("en")}' }
+"#)).await;
+    assert_eq!(testing::get(addr, "/").await.body().as_ref(), b"n/a/en");
+}
+
+#[tokio::test]
+async fn subrequest_body_can_be_wrapped() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - subrequest:
+          handler: router
+          rules:
+            - ops:
+                - respond:
+                    status: 201
+                    body: '{"path":"${path}"}'
+                    headers: { x-origin: inner }
+      - respond:
+          status: 200
+          body: '{"status":${sub.status},"from":"${sub.header.x-origin}","data":${sub.body}}'
+"#)).await;
+
+    let resp = testing::get(addr, "/item").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body().as_ref(), br#"{"status":201,"from":"inner","data":{"path":"/item"}}"#);
+}
+
+#[tokio::test]
+async fn respond_passthrough_relays_subrequest_errors() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - subrequest:
+          handler: router
+          rules:
+            - when: { path: /teapot }
+              ops:
+                - respond: { status: 418, body: "short and stout", headers: { x-origin: kettle } }
+            - ops:
+                - respond: { status: 200, body: fine }
+      - branch:
+          if: { var: sub.status, gte: 400 }
+          then:
+            - respond: { passthrough: true, headers: { x-relayed: "${sub.status}" } }
+          else:
+            - respond: { status: 200, body: "ok: ${sub.body}" }
+"#)).await;
+
+    let resp = testing::get(addr, "/teapot").await;
+    assert_eq!(resp.status(), 418);
+    assert_eq!(resp.body().as_ref(), b"short and stout");
+    assert_eq!(resp.headers()["x-origin"], "kettle");
+    assert_eq!(resp.headers()["x-relayed"], "418");
+    assert_eq!(resp.headers()[http::header::CONTENT_LENGTH], "15");
+
+    assert_eq!(testing::get(addr, "/").await.body().as_ref(), b"ok: fine");
+}
+
+#[tokio::test]
+async fn respond_passthrough_keeps_raw_body_and_repeated_headers() {
+    let dir = testing::temp_dir(&[]);
+    std::fs::write(dir.join("blob.bin"), [0xff, 0x00, 0xfe]).unwrap();
+    let addr = testing::spawn(testing::service(&format!(r#"
+handler: router
+rules:
+  - when: {{ path: /cookies }}
+    ops:
+      - subrequest:
+          handler: router
+          rules:
+            - ops:
+                - sign_cookie: {{ name: a, value_template: "1", key: k }}
+                - sign_cookie: {{ name: b, value_template: "2", key: k }}
+                - respond: {{ status: 403 }}
+      - respond: {{ passthrough: true }}
+  - ops:
+      - subrequest:
+          handler: static
+          source_dir: "{}"
+      - respond: {{ passthrough: true }}
+"#, dir.display()))).await;
+
+    let resp = testing::get(addr, "/cookies").await;
+    assert_eq!(resp.status(), 403);
+    assert_eq!(resp.headers().get_all(http::header::SET_COOKIE).iter().count(), 2);
+
+    let resp = testing::get(addr, "/blob.bin").await;
+    assert_eq!(resp.body().as_ref(), [0xff, 0x00, 0xfe]);
+    assert_eq!(resp.headers()[http::header::CONTENT_LENGTH], "3");
+}
+
+#[tokio::test]
+async fn respond_and_redirect_send_content_length() {
+    let addr = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - when: { path: /moved }
+    ops:
+      - redirect: { status: _302, location: /new }
+  - when: { path: /hello }
+    ops:
+      - respond: { status: 200, body: "héllo" }
+"#)).await;
+
+    let resp = testing::get(addr, "/hello").await;
+    assert_eq!(resp.headers()[http::header::CONTENT_LENGTH], "6");
+    assert_eq!(resp.body().len(), 6);
+    let resp = testing::get(addr, "/moved").await;
+    assert_eq!(resp.headers()[http::header::CONTENT_LENGTH], resp.body().len().to_string().as_str());
+    let resp = testing::get(addr, "/missing").await;
+    assert_eq!(resp.status(), 404);
+    assert_eq!(resp.headers()[http::header::CONTENT_LENGTH], resp.body().len().to_string().as_str());
+}
+
+#[test]
+fn error_responses_carry_content_length() {
+    let resp = crate::util::http::make_error_resp(http::StatusCode::NOT_FOUND, "no route matched");
+    assert_eq!(resp.headers()[http::header::CONTENT_LENGTH], "16");
+}
+
+#[test]
+fn now_variables_gate_a_maintenance_window() {
+    let at = |secs: u64| {
+        let mut ctx = RouterCtx::from_request(&http::Request::get("/").header(http::header::HOST, "example.com").body(()).unwrap());
+        ctx.now = std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        ctx
+    };
+    let in_window = |ctx: &RouterCtx| eval_cond(&CompiledCondNode::Test(CompiledTestCond {
+        var: "now.hour".to_string(),
+        cond: CompiledBasicCond::Compare { gt: None, gte: Some(2.0), lt: None, lte: Some(4.0) },
+    }), ctx, DEFAULT_MAX_EXPANSION).0;
+
+    // 2024-01-01 was a Monday
+    let monday = 1_704_067_200;
+    let ctx = at(monday + 3 * 3600 + 59);
+    assert!(in_window(&ctx));
+    assert_eq!(ctx.get("now.hour").as_deref(), Some("3"));
+    assert_eq!(ctx.get("now.weekday").as_deref(), Some("1"));
+    assert_eq!(ctx.get("now.unix").as_deref(), Some("1704078059"));
+    assert!(!in_window(&at(monday + 5 * 3600)));
+    assert!(!in_window(&at(monday + 6 * 86_400 + 3600)));
+    assert_eq!(at(monday + 6 * 86_400).get("now.weekday").as_deref(), Some("7"));
+}
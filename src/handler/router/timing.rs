@@ -0,0 +1,38 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use hyper::http;
+
+/// Per-request `Server-Timing` bookkeeping, switched on by the `server_timing` op.
+#[derive(Debug, Default)]
+pub struct ServerTiming {
+    pub enabled: bool,
+    upstream: Duration,
+}
+
+impl ServerTiming {
+    /// Run `fut` (a `use`/`next` dispatch) and count its time as upstream time.
+    pub async fn upstream<F: Future>(&mut self, fut: F) -> F::Output {
+        let started = Instant::now();
+        let out = fut.await;
+        self.upstream += started.elapsed();
+        out
+    }
+
+    /// Append `route;dur=..., upstream;dur=...` (milliseconds) if enabled.
+    /// An upstream's own `Server-Timing` header is kept alongside ours.
+    pub fn apply(&self, total: Duration, headers: &mut http::HeaderMap) {
+        if !self.enabled {
+            return;
+        }
+        let route = total.saturating_sub(self.upstream);
+        let value = format!(
+            "route;dur={:.3}, upstream;dur={:.3}",
+            route.as_secs_f64() * 1000.0,
+            self.upstream.as_secs_f64() * 1000.0,
+        );
+        if let Ok(v) = http::HeaderValue::from_str(&value) {
+            headers.append(http::HeaderName::from_static("server-timing"), v);
+        }
+    }
+}
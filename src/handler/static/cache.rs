@@ -0,0 +1,94 @@
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::config::r#static::StaticCacheConfig;
+
+/// In-memory LRU of small files, bounded by entry count and total bytes.
+/// Entries are revalidated against the file's mtime and size on every lookup.
+#[derive(Debug)]
+pub struct FileCache {
+    limits: StaticCacheConfig,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<PathBuf, Entry>,
+    total_bytes: usize,
+    tick: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    body: Bytes,
+    modified: SystemTime,
+    last_used: u64,
+}
+
+impl FileCache {
+    pub fn new(limits: StaticCacheConfig) -> Self {
+        FileCache { limits, inner: Mutex::new(Inner::default()) }
+    }
+
+    /// Read `path`, serving it from memory when the cached copy is still current.
+    pub fn read(&self, path: &Path) -> io::Result<Bytes> {
+        let md = fs::metadata(path)?;
+        if !md.is_file() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a file"));
+        }
+        let modified = md.modified()?;
+
+        {
+            let mut inner = self.inner.lock().unwrap();
+            inner.tick += 1;
+            let tick = inner.tick;
+            if let Some(e) = inner.entries.get_mut(path)
+                && e.modified == modified
+                && e.body.len() as u64 == md.len()
+            {
+                e.last_used = tick;
+                return Ok(e.body.clone());
+            }
+        }
+
+        let body = Bytes::from(fs::read(path)?);
+        if body.len() <= self.limits.max_file_size && body.len() <= self.limits.max_bytes {
+            self.insert(path, body.clone(), modified);
+        }
+        Ok(body)
+    }
+
+    fn insert(&self, path: &Path, body: Bytes, modified: SystemTime) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(old) = inner.entries.remove(path) {
+            inner.total_bytes -= old.body.len();
+        }
+        while !inner.entries.is_empty()
+            && (inner.entries.len() >= self.limits.max_entries
+                || inner.total_bytes + body.len() > self.limits.max_bytes)
+        {
+            inner.evict_lru();
+        }
+        if self.limits.max_entries == 0 {
+            return;
+        }
+        inner.tick += 1;
+        inner.total_bytes += body.len();
+        let last_used = inner.tick;
+        inner.entries.insert(path.to_path_buf(), Entry { body, modified, last_used });
+    }
+}
+
+impl Inner {
+    fn evict_lru(&mut self) {
+        let oldest = self.entries.iter().min_by_key(|(_, e)| e.last_used).map(|(k, _)| k.clone());
+        if let Some(e) = oldest.and_then(|key| self.entries.remove(&key)) {
+            self.total_bytes -= e.body.len();
+        }
+    }
+}
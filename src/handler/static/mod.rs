@@ -0,0 +1,380 @@
+use bytes::Bytes;
+use hyper::http;
+use mime_guess::from_path;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use tracing::debug;
+
+mod cache;
+
+pub use cache::FileCache;
+
+use crate::build::service::LoadedStatic;
+use crate::config::r#static::{
+    EvilDirStrategyIndexExists,
+    EvilDirStrategyIndexMissing,
+    IndexStrategy,
+};
+use crate::config::http_server::default_body_chunk_bytes;
+use crate::handler::{BoxResponseFuture, RequestBody, ResponseBody, ServiceHandler, chunked, full};
+use crate::http_server::BodyChunkSize;
+use crate::util::glob::glob_match;
+use crate::util::http::make_error_resp;
+
+impl ServiceHandler for LoadedStatic {
+    fn handle_request<'a>(
+        &'a self,
+        req: &'a mut http::Request<RequestBody>,
+    ) -> BoxResponseFuture<'a> {
+        Box::pin(async move {
+            let mut resp = self.serve(req);
+            if resp.status() == http::StatusCode::NOT_FOUND
+                && let Some(fallback) = &self.fallback
+            {
+                return fallback.handle_request(req).await;
+            }
+            if resp.status().is_success() {
+                self.apply_rules(req.uri().path(), resp.headers_mut());
+            }
+            resp
+        })
+    }
+}
+
+impl LoadedStatic {
+    fn serve(&self, req: &http::Request<RequestBody>) -> http::Response<ResponseBody> {
+        match *req.method() {
+            http::Method::GET | http::Method::HEAD => {}
+            http::Method::OPTIONS => return allow_response(http::StatusCode::NO_CONTENT),
+            _ => return allow_response(http::StatusCode::METHOD_NOT_ALLOWED),
+        }
+        let head_only = req.method() == &http::Method::HEAD;
+        let chunk = req.extensions().get::<BodyChunkSize>()
+            .map_or_else(default_body_chunk_bytes, |c| c.0);
+
+        let url_path_raw = req.uri().path();
+        let is_url_path_dir = url_path_raw.ends_with('/');
+        debug!(path = url_path_raw, is_dir = is_url_path_dir, "static request");
+
+        let rel = match url_path_to_relative(url_path_raw) {
+            Ok(p) => p,
+            Err(msg) => return make_error_resp(http::StatusCode::BAD_REQUEST, msg),
+        };
+
+        let base_dir_path = Path::new(&self.config.source_dir);
+        let target_path = base_dir_path.join(&rel);
+        let is_target_dir = is_existing_dir(&target_path);
+        let is_target_index =
+            !is_url_path_dir
+            && target_path.file_name().map_or(false, |f| f == self.config.file_index.as_str());
+
+        debug!(target = ?target_path, is_dir = is_target_dir, is_index = is_target_index, "mapped to path");
+
+        if is_target_index {
+            match &self.config.index_strategy {
+                IndexStrategy::Redirect { code } =>
+                    return redirect_to(&location_cur_dir(req), *code),
+                IndexStrategy::NotFound =>
+                    return self.nearest_404(&target_path, head_only, chunk),
+                IndexStrategy::ServeIndex => {},
+            }
+        }
+
+        let target_file_path = if is_url_path_dir {
+            target_path.join(&self.config.file_index)
+        } else {
+            target_path.clone()
+        };
+
+        debug!(file = ?target_file_path, "mapped to file");
+
+        if let Ok(body) = self.read_file(&target_file_path) {
+            debug!(file = ?target_file_path, "serving file");
+            return with_ct(hyper::http::StatusCode::OK, &target_file_path, body, head_only, chunk, self.default_type());
+        }
+
+        if is_target_dir && !is_url_path_dir {
+            let index_file_path = target_path.join(&self.config.file_index);
+            let has_index_file = index_file_path.is_file();
+
+            return if has_index_file {
+                match &self.config.evil_dir_strategy.if_index_exists {
+                    EvilDirStrategyIndexExists::ServeIndex =>
+                        self.serve_file_or_404(&index_file_path, head_only, chunk),
+                    EvilDirStrategyIndexExists::Redirect { code } =>
+                        redirect_to(&location_with_slash(req), *code),
+                    EvilDirStrategyIndexExists::NotFound =>
+                        self.nearest_404(&target_path, head_only, chunk),
+                }
+            } else {
+                match &self.config.evil_dir_strategy.if_index_missing {
+                    EvilDirStrategyIndexMissing::Redirect { code } =>
+                        redirect_to(&location_with_slash(req), *code),
+                    EvilDirStrategyIndexMissing::NotFound =>
+                        self.nearest_404(&target_path, head_only, chunk),
+                }
+            }
+        }
+
+        self.nearest_404(&target_file_path, head_only, chunk)
+    }
+
+    fn read_file(&self, path: &Path) -> std::io::Result<Bytes> {
+        if let Some(root) = &self.root
+            && !is_within(root, path)?
+        {
+            debug!(file = ?path, "symlink escapes source_dir");
+            return Err(std::io::ErrorKind::NotFound.into());
+        }
+        match &self.cache {
+            Some(cache) => cache.read(path),
+            None => fs::read(path).map(Bytes::from),
+        }
+    }
+
+    /// `Content-Type` for files whose extension has no known MIME type.
+    fn default_type(&self) -> Option<&http::HeaderValue> {
+        self.default_type.as_ref()
+    }
+
+    fn serve_file_or_404(
+        &self,
+        path: &Path,
+        head_only: bool,
+        chunk: usize,
+    ) -> http::Response<ResponseBody> {
+        match self.read_file(path) {
+            Ok(body) => with_ct(hyper::http::StatusCode::OK, path, body, head_only, chunk, self.default_type()),
+            Err(_) => self.nearest_404(path, head_only, chunk),
+        }
+    }
+
+    /// The closest `file_404` at or above `start`, else the one in `source_dir`, else a plain 404.
+    fn nearest_404(&self, start: &Path, head_only: bool, chunk: usize) -> http::Response<ResponseBody> {
+        let base = Path::new(&self.config.source_dir);
+        let page = cascade_404_path(base, start, &self.config.file_404)
+            .or_else(|| Some(base.join(&self.config.file_404)).filter(|p| p.is_file()));
+        if let Some(p) = page
+            && let Ok(body) = self.read_file(&p)
+        {
+            return with_ct(http::StatusCode::NOT_FOUND, &p, body, head_only, chunk, None);
+        }
+        make_response(http::StatusCode::NOT_FOUND, b"404 Not Found", head_only)
+    }
+
+    /// Apply the headers of the first static rule whose glob matches the request path.
+    fn apply_rules(&self, url_path: &str, headers: &mut http::HeaderMap) {
+        if let Some(immutable) = &self.immutable {
+            let cc = if immutable.is_match(&download_name(url_path, &self.config.file_index)) {
+                IMMUTABLE_CACHE_CONTROL
+            } else {
+                "no-cache"
+            };
+            headers.insert(http::header::CACHE_CONTROL, http::HeaderValue::from_static(cc));
+        }
+        let rule = self.config.rules.iter().find(|r| glob_match(&r.glob, url_path));
+        if self.config.force_download || rule.is_some_and(|r| r.force_download) {
+            let name = download_name(url_path, &self.config.file_index);
+            if let Ok(val) = http::HeaderValue::from_str(&content_disposition(&name)) {
+                headers.insert(http::header::CONTENT_DISPOSITION, val);
+            }
+        }
+        let Some(rule) = rule else {
+            return;
+        };
+        for (k, v) in &rule.headers {
+            if let (Ok(name), Ok(val)) = (
+                http::HeaderName::try_from(k.as_str()),
+                http::HeaderValue::from_str(v),
+            ) {
+                headers.insert(name, val);
+            }
+        }
+        if let Some(cc) = &rule.cache_control
+            && let Ok(val) = http::HeaderValue::from_str(cc)
+        {
+            headers.insert(http::header::CACHE_CONTROL, val);
+        }
+    }
+}
+
+/// Whether `path` resolves, symlinks included, to a location inside the canonical `root`.
+fn is_within(root: &Path, path: &Path) -> std::io::Result<bool> {
+    Ok(fs::canonicalize(path)?.starts_with(root))
+}
+
+/// Cache policy for files matching `immutable_pattern`: a year, never revalidated.
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// File name the client sees: the last path segment, or the index file for a directory.
+fn download_name(url_path: &str, file_index: &str) -> String {
+    let last = url_path.rsplit('/').next().unwrap_or("");
+    if last.is_empty() {
+        return file_index.to_string();
+    }
+    percent_decode_str(last).decode_utf8_lossy().into_owned()
+}
+
+/// Characters `filename*` may carry unescaped (RFC 5987 `attr-char`).
+const ATTR_CHAR_ESCAPES: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!').remove(b'#').remove(b'$').remove(b'&').remove(b'+').remove(b'-')
+    .remove(b'.').remove(b'^').remove(b'_').remove(b'`').remove(b'|').remove(b'~');
+
+/// `attachment` disposition with a quoted ASCII fallback name plus the exact UTF-8 name.
+fn content_disposition(name: &str) -> String {
+    let fallback: String = name
+        .chars()
+        .map(|c| if (c.is_ascii_graphic() || c == ' ') && !matches!(c, '"' | '\\' | '/' | ';') { c } else { '_' })
+        .collect();
+    format!(
+        "attachment; filename=\"{fallback}\"; filename*=UTF-8''{}",
+        utf8_percent_encode(name, ATTR_CHAR_ESCAPES),
+    )
+}
+
+const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS";
+
+/// Bodyless response advertising the methods the static handler serves.
+fn allow_response(status: http::StatusCode) -> http::Response<ResponseBody> {
+    http::Response::builder()
+        .status(status)
+        .header(http::header::ALLOW, ALLOWED_METHODS)
+        .body(full(Bytes::new()))
+        .unwrap()
+}
+
+/// Convert URL path (starts with '/') to a relative path, disallowing ".." escapes and performing % decoding.
+fn url_path_to_relative(url_path: &str) -> Result<PathBuf, &'static str> {
+    if !url_path.starts_with('/') {
+        return Err("path must start with '/'");
+    }
+    
+    let decoded = percent_decode_str(url_path).decode_utf8_lossy();
+
+    let mut result = PathBuf::new();
+    for comp in Path::new(decoded.trim_start_matches('/')).components() {
+        match comp {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !result.pop() {
+                    return Err("Path traversal not allowed");
+                }
+            }
+            Component::Normal(seg) => {
+                result.push(seg);
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                return Err("Absolute path not allowed");
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+fn is_existing_dir(p: &Path) -> bool {
+    fs::metadata(p).map(|md| md.is_dir()).unwrap_or(false)
+}
+
+fn cascade_404_path(base: &Path, start: &Path, file_404: &str) -> Option<PathBuf> {
+    let mut dir = start.parent().unwrap_or(base);
+
+    loop {
+        if !dir.starts_with(base) { break; }
+
+        let candidate = dir.join(file_404);
+        if candidate.is_file() { return Some(candidate); }
+        match dir.parent() {
+            Some(parent) => dir = parent,
+            None => break,
+        }
+    }
+
+    None
+}
+
+fn make_response(status: http::StatusCode, body: &[u8], head_only: bool) -> http::Response<ResponseBody> {
+    let content = if head_only { Bytes::new() } else { Bytes::copy_from_slice(body) };
+    http::Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_LENGTH, body.len().to_string())
+        .body(full(content))
+        .unwrap()
+}
+
+fn with_ct(
+    status: http::StatusCode,
+    path: &Path,
+    content: Bytes,
+    head_only: bool,
+    chunk: usize,
+    default_type: Option<&http::HeaderValue>,
+) -> http::Response<ResponseBody> {
+    let content_type = match (from_path(path).first(), default_type) {
+        (Some(mime), _) => http::HeaderValue::from_str(mime.as_ref())
+            .unwrap_or(http::HeaderValue::from_static("application/octet-stream")),
+        (None, Some(default)) => default.clone(),
+        (None, None) => http::HeaderValue::from_static("application/octet-stream"),
+    };
+    let mut builder = http::Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_TYPE, content_type)
+        .header(http::header::CONTENT_LENGTH, content.len().to_string());
+    if status == http::StatusCode::OK
+        && let Some(etag) = etag_for(path)
+    {
+        builder = builder.header(http::header::ETAG, etag);
+    }
+    // HEAD gets exactly the GET headers, only the body is dropped
+    let body = if head_only { Bytes::new() } else { content };
+    builder.body(chunked(body, chunk)).unwrap()
+}
+
+/// Weak validator derived from modification time and size, in the common `"mtime-len"` hex form.
+fn etag_for(path: &Path) -> Option<String> {
+    let md = fs::metadata(path).ok()?;
+    let mtime = md.modified().ok()?.duration_since(std::time::UNIX_EPOCH).ok()?;
+    Some(format!("W/\"{:x}-{:x}\"", mtime.as_secs(), md.len()))
+}
+
+fn redirect_to(
+    location: &str,
+    code: u16,
+) -> http::Response<ResponseBody> {
+    let status = http::StatusCode::from_u16(code)
+        .unwrap_or(http::StatusCode::PERMANENT_REDIRECT);
+
+    http::Response::builder()
+        .status(status)
+        .header(
+            http::header::LOCATION,
+            http::HeaderValue::from_str(&location)
+                .unwrap_or_else(|_| http::HeaderValue::from_static("/")),
+        )
+        .body(full(Bytes::new()))
+        .unwrap()
+}
+
+fn location_with_slash(req: &http::Request<RequestBody>) -> String {
+    let mut location = req.uri().path().to_string();
+    if !location.ends_with('/') { location.push('/'); }
+    if let Some(query) = req.uri().query() {
+        location.push('?');
+        location.push_str(query);
+    }
+    location
+}
+
+fn location_cur_dir(req: &http::Request<RequestBody>) -> String {
+    let mut location = req.uri().path().to_string();
+    location = location.trim_end_matches(|c| c != '/').to_string();
+    if let Some(query) = req.uri().query() {
+        location.push('?');
+        location.push_str(query);
+    }
+    location
+}
+
+#[cfg(test)]
+mod tests;
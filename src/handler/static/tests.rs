@@ -0,0 +1,315 @@
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::http;
+
+use crate::testing;
+use crate::util::glob::glob_match;
+
+#[test]
+fn glob_star_and_question_mark() {
+    assert!(glob_match("*.js", "/assets/app.js"));
+    assert!(!glob_match("*.js", "/assets/app.json"));
+    assert!(glob_match("app.?s", "/app.js"));
+    assert!(glob_match("/assets/*.css", "/assets/site.css"));
+    assert!(!glob_match("/assets/*.css", "/assets/sub/site.css"));
+    assert!(glob_match("*", "/anything"));
+}
+
+#[tokio::test]
+async fn first_matching_rule_sets_headers() {
+    let dir = testing::temp_dir(&[("app.js", "js"), ("index.html", "html"), ("page.html", "html")]);
+    let addr = testing::spawn(testing::service(&format!(r#"
+handler: static
+source_dir: "{}"
+rules:
+  - glob: "*.js"
+    cache_control: "public, max-age=3600"
+    headers: {{ x-asset: script }}
+  - glob: "*.html"
+    cache_control: no-cache
+  - glob: "*"
+    headers: {{ x-asset: other }}
+"#, dir.display()))).await;
+
+    let resp = testing::get(addr, "/app.js").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()[http::header::CACHE_CONTROL], "public, max-age=3600");
+    assert_eq!(resp.headers()["x-asset"], "script");
+
+    let resp = testing::get(addr, "/page.html").await;
+    assert_eq!(resp.headers()[http::header::CACHE_CONTROL], "no-cache");
+    assert!(resp.headers().get("x-asset").is_none());
+
+    let resp = testing::get(addr, "/missing.js").await;
+    assert_eq!(resp.status(), 404);
+    assert!(resp.headers().get(http::header::CACHE_CONTROL).is_none());
+}
+
+#[tokio::test]
+async fn hashed_file_names_are_cached_as_immutable() {
+    let dir = testing::temp_dir(&[("app.3f9a1c2b.js", "js"), ("app.js", "js"), ("page.html", "html")]);
+    let addr = testing::spawn(testing::service(&format!(r#"
+handler: static
+source_dir: "{}"
+immutable_pattern: '\.[0-9a-f]{{8,}}\.'
+rules:
+  - glob: "*.html"
+    cache_control: "public, max-age=60"
+"#, dir.display()))).await;
+
+    let resp = testing::get(addr, "/app.3f9a1c2b.js").await;
+    assert_eq!(resp.headers()[http::header::CACHE_CONTROL], "public, max-age=31536000, immutable");
+
+    let resp = testing::get(addr, "/app.js").await;
+    assert_eq!(resp.headers()[http::header::CACHE_CONTROL], "no-cache");
+
+    // an explicit rule still wins
+    let resp = testing::get(addr, "/page.html").await;
+    assert_eq!(resp.headers()[http::header::CACHE_CONTROL], "public, max-age=60");
+}
+
+#[tokio::test]
+async fn file_body_is_split_into_configured_chunks() {
+    use crate::handler::ServiceHandler;
+    use http_body_util::BodyExt;
+
+    let dir = testing::temp_dir(&[("data.txt", "0123456789"), ("404.html", "missing")]);
+    let svc = testing::service(&format!("handler: static\nsource_dir: \"{}\"\n", dir.display()));
+    let frames = |path: &'static str| {
+        let svc = &svc;
+        async move {
+            let mut req = http::Request::get(path)
+                .extension(crate::http_server::BodyChunkSize(4))
+                .body(crate::handler::full(Bytes::new()))
+                .unwrap();
+            let mut body = svc.handle_request(&mut req).await.into_body();
+            let mut frames = Vec::new();
+            while let Some(frame) = body.frame().await {
+                frames.push(frame.unwrap().into_data().unwrap());
+            }
+            frames
+        }
+    };
+
+    assert_eq!(frames("/data.txt").await, ["0123", "4567", "89"]);
+    // the 404 page honours the same chunk size
+    assert_eq!(frames("/nope.txt").await, ["miss", "ing"]);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn symlink_escaping_source_dir_is_not_followed_when_disabled() {
+    let outside = testing::temp_dir(&[("secret.txt", "secret")]);
+    let root = testing::temp_dir(&[("public.txt", "public")]);
+    std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("leak.txt")).unwrap();
+    std::os::unix::fs::symlink(root.join("public.txt"), root.join("alias.txt")).unwrap();
+    std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("404.html")).unwrap();
+    let serve = |follow: bool| testing::service(&format!(
+        "handler: static\nsource_dir: \"{}\"\nfollow_symlinks: {follow}\n", root.display(),
+    ));
+
+    let addr = testing::spawn(serve(false)).await;
+    assert_eq!(testing::get(addr, "/leak.txt").await.status(), 404);
+    assert_eq!(testing::get(addr, "/alias.txt").await.body().as_ref(), b"public");
+    // the 404 page goes through the same check
+    let resp = testing::get(addr, "/missing.txt").await;
+    assert_eq!(resp.status(), 404);
+    assert_eq!(resp.body().as_ref(), b"404 Not Found");
+
+    let addr = testing::spawn(serve(true)).await;
+    assert_eq!(testing::get(addr, "/leak.txt").await.body().as_ref(), b"secret");
+    assert_eq!(testing::get(addr, "/missing.txt").await.body().as_ref(), b"secret");
+}
+
+fn head(path: &str) -> http::Request<Full<Bytes>> {
+    http::Request::head(path).body(Full::default()).unwrap()
+}
+
+#[tokio::test]
+async fn head_returns_get_headers_without_body() {
+    let dir = testing::temp_dir(&[("style.css", "body { color: red; }")]);
+    let addr = testing::spawn(testing::service(&format!(
+        "handler: static\nsource_dir: \"{}\"\n", dir.display()
+    ))).await;
+
+    let get = testing::get(addr, "/style.css").await;
+    assert_eq!(get.status(), 200);
+    assert_eq!(get.body().len(), 20);
+
+    let resp = testing::send(addr, head("/style.css")).await;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.body().is_empty());
+    for name in [http::header::CONTENT_LENGTH, http::header::CONTENT_TYPE, http::header::ETAG] {
+        assert_eq!(resp.headers().get(&name), get.headers().get(&name), "{name} differs");
+    }
+    assert_eq!(resp.headers()[http::header::CONTENT_LENGTH], "20");
+
+    let resp = testing::send(addr, head("/missing.css")).await;
+    assert_eq!(resp.status(), 404);
+    assert!(resp.body().is_empty());
+    assert_eq!(resp.headers()[http::header::CONTENT_LENGTH], "13");
+}
+
+#[tokio::test]
+async fn non_read_methods_are_rejected_with_allow() {
+    let dir = testing::temp_dir(&[("index.html", "home")]);
+    let addr = testing::spawn(testing::service(&format!(
+        "handler: static\nsource_dir: \"{}\"\n", dir.display()
+    ))).await;
+
+    let req = http::Request::post("/index.html").body(Full::from("x")).unwrap();
+    let resp = testing::send(addr, req).await;
+    assert_eq!(resp.status(), 405);
+    assert_eq!(resp.headers()[http::header::ALLOW], "GET, HEAD, OPTIONS");
+
+    let req = http::Request::options("*").body(Full::default()).unwrap();
+    let resp = testing::send(addr, req).await;
+    assert_eq!(resp.status(), 204);
+    assert_eq!(resp.headers()[http::header::ALLOW], "GET, HEAD, OPTIONS");
+}
+
+/// Overwrite `path` with `content` and pin its mtime, so only the bytes change.
+fn rewrite_with_mtime(path: &std::path::Path, content: &str, mtime: std::time::SystemTime) {
+    std::fs::write(path, content).unwrap();
+    std::fs::File::options().write(true).open(path).unwrap().set_modified(mtime).unwrap();
+}
+
+#[tokio::test]
+async fn cache_serves_repeat_hits_and_busts_on_mtime_change() {
+    let dir = testing::temp_dir(&[("a.txt", "aaaa")]);
+    let file = dir.join("a.txt");
+    let mtime = std::fs::metadata(&file).unwrap().modified().unwrap();
+    let addr = testing::spawn(testing::service(&format!(
+        "handler: static\nsource_dir: \"{}\"\ncache: {{}}\n", dir.display()
+    ))).await;
+
+    assert_eq!(testing::get(addr, "/a.txt").await.body().as_ref(), b"aaaa");
+
+    // same size and mtime: the stale cached copy is still considered current
+    rewrite_with_mtime(&file, "bbbb", mtime);
+    assert_eq!(testing::get(addr, "/a.txt").await.body().as_ref(), b"aaaa");
+
+    rewrite_with_mtime(&file, "bbbb", mtime + std::time::Duration::from_secs(5));
+    assert_eq!(testing::get(addr, "/a.txt").await.body().as_ref(), b"bbbb");
+}
+
+#[test]
+fn cache_evicts_least_recently_used() {
+    use super::FileCache;
+    use crate::config::r#static::StaticCacheConfig;
+
+    let dir = testing::temp_dir(&[("a", "1"), ("b", "2"), ("big", "0123456789")]);
+    let mtime = std::fs::metadata(dir.join("a")).unwrap().modified().unwrap();
+    let cache = FileCache::new(StaticCacheConfig { max_entries: 1, max_bytes: 64, max_file_size: 4 });
+
+    assert_eq!(cache.read(&dir.join("a")).unwrap().as_ref(), b"1");
+    assert_eq!(cache.read(&dir.join("b")).unwrap().as_ref(), b"2");
+    rewrite_with_mtime(&dir.join("a"), "9", mtime);
+    // `a` was evicted when `b` came in, so the new bytes are read from disk
+    assert_eq!(cache.read(&dir.join("a")).unwrap().as_ref(), b"9");
+
+    // over `max_file_size`: always read through
+    assert_eq!(cache.read(&dir.join("big")).unwrap().as_ref(), b"0123456789");
+    rewrite_with_mtime(&dir.join("a"), "8", mtime);
+    assert_eq!(cache.read(&dir.join("a")).unwrap().as_ref(), b"9");
+}
+
+#[tokio::test]
+async fn request_paths_are_logged_at_debug_only() {
+    let dir = testing::temp_dir(&[("a.txt", "a")]);
+    let svc = || testing::service(&format!("handler: static\nsource_dir: \"{}\"\n", dir.display()));
+
+    let (logs, guard) = testing::CapturedLogs::install(tracing::Level::INFO);
+    let addr = testing::spawn(svc()).await;
+    testing::get(addr, "/a.txt").await;
+    drop(guard);
+    assert!(logs.contents().is_empty(), "unexpected logs: {}", logs.contents());
+
+    let (logs, _guard) = testing::CapturedLogs::install(tracing::Level::DEBUG);
+    let addr = testing::spawn(svc()).await;
+    testing::get(addr, "/a.txt").await;
+    let out = logs.contents();
+    assert!(out.contains("static request"));
+    assert!(out.contains("path=\"/a.txt\""));
+}
+
+#[tokio::test]
+async fn force_download_sets_attachment_disposition() {
+    let dir = testing::temp_dir(&[("report.pdf", "pdf"), ("my \"notes\".txt", "txt"), ("page.html", "html")]);
+    let addr = testing::spawn(testing::service(&format!(r#"
+handler: static
+source_dir: "{}"
+rules:
+  - glob: "*.pdf"
+    force_download: true
+  - glob: "*.txt"
+    force_download: true
+"#, dir.display()))).await;
+
+    let resp = testing::get(addr, "/report.pdf").await;
+    assert_eq!(
+        resp.headers()[http::header::CONTENT_DISPOSITION],
+        "attachment; filename=\"report.pdf\"; filename*=UTF-8''report.pdf",
+    );
+
+    let resp = testing::get(addr, "/my%20%22notes%22.txt").await;
+    assert_eq!(
+        resp.headers()[http::header::CONTENT_DISPOSITION],
+        "attachment; filename=\"my _notes_.txt\"; filename*=UTF-8''my%20%22notes%22.txt",
+    );
+
+    let resp = testing::get(addr, "/page.html").await;
+    assert!(resp.headers().get(http::header::CONTENT_DISPOSITION).is_none());
+
+    let addr = testing::spawn(testing::service(&format!(
+        "handler: static\nsource_dir: \"{}\"\nforce_download: true\n", dir.display()
+    ))).await;
+    let resp = testing::get(addr, "/page.html").await;
+    assert_eq!(
+        resp.headers()[http::header::CONTENT_DISPOSITION],
+        "attachment; filename=\"page.html\"; filename*=UTF-8''page.html",
+    );
+    assert!(testing::get(addr, "/missing").await.headers().get(http::header::CONTENT_DISPOSITION).is_none());
+}
+
+#[tokio::test]
+async fn missing_files_are_fetched_from_fallback() {
+    let dir = testing::temp_dir(&[("local.txt", "local")]);
+    let origin = testing::spawn(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - respond: { status: 200, body: "origin ${path}" }
+"#)).await;
+    let addr = testing::spawn(testing::service(&format!(r#"
+handler: static
+source_dir: "{}"
+fallback:
+  handler: forward
+  target: {{ scheme: http, host: 127.0.0.1, port: {} }}
+"#, dir.display(), origin.port()))).await;
+
+    let resp = testing::get(addr, "/local.txt").await;
+    assert_eq!(resp.body().as_ref(), b"local");
+    let resp = testing::get(addr, "/remote.txt").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body().as_ref(), b"origin /remote.txt");
+}
+
+#[tokio::test]
+async fn default_content_type_applies_to_unknown_extensions() {
+    let dir = testing::temp_dir(&[("LICENSE", "MIT"), ("page.zzunknown", "x"), ("site.css", "a{}")]);
+    let svc = |extra: &str| testing::service(&format!(
+        "handler: static\nsource_dir: \"{}\"\n{extra}",
+        dir.display(),
+    ));
+
+    let addr = testing::spawn(svc("default_content_type: \"text/plain; charset=utf-8\"\n")).await;
+    let ct = |resp: http::Response<Bytes>| resp.headers()[http::header::CONTENT_TYPE].to_str().unwrap().to_string();
+    assert_eq!(ct(testing::get(addr, "/LICENSE").await), "text/plain; charset=utf-8");
+    assert_eq!(ct(testing::get(addr, "/page.zzunknown").await), "text/plain; charset=utf-8");
+    assert_eq!(ct(testing::get(addr, "/site.css").await), "text/css");
+
+    let addr = testing::spawn(svc("")).await;
+    assert_eq!(ct(testing::get(addr, "/LICENSE").await), "application/octet-stream");
+}
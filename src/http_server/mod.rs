@@ -0,0 +1,294 @@
+use hyper::{
+    server::conn::http1,
+    service::service_fn,
+    Request,
+    Response,
+    body,
+    http,
+    Version
+};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tracing::debug;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use crate::build::BuiltHttpServer;
+use crate::build::http_server::BuiltTls;
+use crate::build::service::LoadedService;
+use crate::config::http_server::{BufferSettings, RequestBodyPolicy, parse_bind_addr};
+use crate::handler::{ServiceHandler, full};
+use crate::handler::body::buffer_request;
+use crate::util::cidr::Cidr;
+use crate::util::http::{make_error_resp, parse_host_header};
+use hyper_util::rt::TokioIo;
+
+use std::sync::Arc;
+
+/// Per-connection facts attached to every request as an extension.
+#[derive(Debug, Clone)]
+pub struct ConnInfo {
+    pub local_addr: SocketAddr,
+    /// Whether the connection was accepted through a TLS layer.
+    pub is_tls: bool,
+    /// Server name the client asked for during the TLS handshake.
+    pub sni: Option<String>,
+    /// Verified client certificate, for TLS connections that presented one.
+    pub client_cert: Option<Arc<ClientCert>>,
+    /// The client as far as can be told: the peer, or an `X-Forwarded-For` hop when
+    /// the peer is a trusted proxy. See [`remote_ip`].
+    pub remote_ip: IpAddr,
+}
+
+/// Identity fields of a verified client certificate, filled in by the TLS layer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCert {
+    /// Subject common name.
+    pub cn: Option<String>,
+    /// DNS, URI and IP subject alternative names, in certificate order.
+    pub san: Vec<String>,
+    /// Lowercase hex SHA-256 of the DER encoding.
+    pub fingerprint: String,
+}
+
+impl ClientCert {
+    /// Describe a DER certificate; `None` if it does not parse.
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        use sha2::{Digest, Sha256};
+        use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+        let (_, cert) = X509Certificate::from_der(der).ok()?;
+        let cn = cert.subject().iter_common_name().next()
+            .and_then(|attr| attr.as_str().ok())
+            .map(str::to_string);
+        let san = match cert.subject_alternative_name() {
+            Ok(Some(ext)) => ext.value.general_names.iter().filter_map(|name| match name {
+                GeneralName::DNSName(s) | GeneralName::URI(s) => Some(s.to_string()),
+                GeneralName::IPAddress(b) => ip_from_bytes(b).map(|ip| ip.to_string()),
+                _ => None,
+            }).collect(),
+            _ => Vec::new(),
+        };
+        let fingerprint = Sha256::digest(der).iter().map(|b| format!("{b:02x}")).collect();
+        Some(ClientCert { cn, san, fingerprint })
+    }
+}
+
+/// An iPAddress SAN: 4 bytes for IPv4, 16 for IPv6.
+fn ip_from_bytes(b: &[u8]) -> Option<IpAddr> {
+    match b.len() {
+        4 => Some(IpAddr::from(<[u8; 4]>::try_from(b).ok()?)),
+        16 => Some(IpAddr::from(<[u8; 16]>::try_from(b).ok()?)),
+        _ => None,
+    }
+}
+
+/// `buffers.body_chunk_bytes` of the listener, attached to every request as an extension.
+#[derive(Debug, Clone, Copy)]
+pub struct BodyChunkSize(pub usize);
+
+/// A listener started by [`start_server`].
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl ServerHandle {
+    /// The address actually bound; with port 0 in `bind` this holds the port the OS picked.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop accepting connections. Requests already in flight are left to finish.
+    pub fn shutdown(self) {
+        self.task.abort();
+    }
+
+    /// Run until the accept loop ends.
+    pub async fn wait(self) {
+        let _ = self.task.await;
+    }
+}
+
+/// Bind `hs.bind` and serve it on a background task.
+pub fn start_server(hs: BuiltHttpServer) -> io::Result<ServerHandle> {
+    let addr = parse_bind_addr(&hs.bind)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    let listener = bind_listener(addr)?;
+    let local_addr = listener.local_addr()?;
+    let task = tokio::spawn(serve(listener, hs.tls, Arc::new(hs.service), hs.request_body, hs.buffers, hs.trusted_proxies));
+    Ok(ServerHandle { local_addr, task })
+}
+
+/// Bind a TCP listener on `addr`. The IPv6 wildcard `[::]` is made dual-stack so it
+/// also accepts IPv4 clients, where the OS allows it.
+pub fn bind_listener(addr: SocketAddr) -> io::Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        // best effort: some platforms only support v6-only sockets
+        let _ = socket.set_only_v6(false);
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Best effort: the OS may round or cap the sizes, and a failure keeps its defaults.
+fn apply_socket_buffers(stream: &tokio::net::TcpStream, buffers: &BufferSettings) {
+    let sock = socket2::SockRef::from(stream);
+    if let Some(n) = buffers.send_buffer_bytes {
+        let _ = sock.set_send_buffer_size(n);
+    }
+    if let Some(n) = buffers.recv_buffer_bytes {
+        let _ = sock.set_recv_buffer_size(n);
+    }
+}
+
+/// The client address for a request from `peer`. `X-Forwarded-For` is walked from
+/// the right only while each hop (starting with the peer) is in `trusted`; the first
+/// untrusted hop is the client. With nothing trusted the header is ignored.
+pub(crate) fn remote_ip(peer: IpAddr, headers: &http::HeaderMap, trusted: &[Cidr]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|c| c.contains(ip));
+    let mut client = peer;
+    let hops: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+    for hop in hops.iter().rev() {
+        if !is_trusted(client) {
+            break;
+        }
+        match hop.trim().parse::<IpAddr>() {
+            Ok(ip) => client = ip.to_canonical(),
+            // a garbled hop cannot be vouched for; stop at the last good one
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+/// A missing Host is left to the handlers; a present one must be `host[:port]`.
+fn has_valid_host<B>(req: &Request<B>) -> bool {
+    match req.headers().get(http::header::HOST) {
+        None => true,
+        Some(v) => v.to_str().ok().and_then(parse_host_header).is_some(),
+    }
+}
+
+/// Accept connections on `listener` forever, dispatching each request to `ox_svc_root`.
+/// With `tls` set every connection is a TLS handshake first, dropped when it does
+/// not finish within the handshake timeout.
+pub async fn serve(
+    listener: TcpListener,
+    tls: Option<BuiltTls>,
+    ox_svc_root: Arc<LoadedService>,
+    body_policy: RequestBodyPolicy,
+    buffers: BufferSettings,
+    trusted_proxies: Vec<Cidr>,
+) {
+    let body_policy = Arc::new(body_policy);
+    let trusted_proxies: Arc<[Cidr]> = trusted_proxies.into();
+    let tls = tls.map(|t| (TlsAcceptor::from(t.config), t.handshake_timeout));
+    loop {
+        let (stream, peer)
+            = listener
+                .accept().await
+                .expect("Failed to accept connection");
+        apply_socket_buffers(&stream, &buffers);
+
+        let conn_info = ConnInfo {
+            local_addr: stream.local_addr().expect("Failed to read local address"),
+            is_tls: tls.is_some(),
+            sni: None,
+            client_cert: None,
+            remote_ip: peer.ip().to_canonical(),
+        };
+        let conn = Connection {
+            svc: ox_svc_root.clone(),
+            body_policy: body_policy.clone(),
+            buffers,
+            trusted_proxies: trusted_proxies.clone(),
+        };
+        let tls = tls.clone();
+
+        tokio::spawn(async move {
+            let Some((acceptor, handshake_timeout)) = tls else {
+                return conn.serve(TokioIo::new(stream), conn_info).await;
+            };
+            let Ok(accepted) = tokio::time::timeout(handshake_timeout, acceptor.accept(stream)).await else {
+                return debug!(peer = %peer, "TLS handshake timed out");
+            };
+            match accepted {
+                Ok(stream) => {
+                    let session = stream.get_ref().1;
+                    let sni = session.server_name().map(str::to_string);
+                    // the verifier has already checked the chain; only the leaf is described
+                    let client_cert = session
+                        .peer_certificates()
+                        .and_then(|chain| chain.first())
+                        .and_then(|leaf| ClientCert::from_der(leaf))
+                        .map(Arc::new);
+                    conn.serve(TokioIo::new(stream), ConnInfo { sni, client_cert, ..conn_info }).await
+                }
+                Err(e) => debug!(peer = %peer, error = %e, "TLS handshake failed"),
+            }
+        });
+    }
+}
+
+/// What one accepted connection needs from its listener.
+struct Connection {
+    svc: Arc<LoadedService>,
+    body_policy: Arc<RequestBodyPolicy>,
+    buffers: BufferSettings,
+    trusted_proxies: Arc<[Cidr]>,
+}
+
+impl Connection {
+    /// Serve HTTP/1.1 requests on `io` until the client goes away.
+    async fn serve<I>(self, io: TokioIo<I>, conn_info: ConnInfo)
+    where
+        I: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+    {
+        let Connection { svc: ox_svc_conn, body_policy, buffers, trusted_proxies } = self;
+        let svc_fn
+            = service_fn(
+                move |mut req: Request<body::Incoming>| {
+                    let ox_svc = ox_svc_conn.clone();
+                    let body_policy = body_policy.clone();
+                    let remote_ip = remote_ip(conn_info.remote_ip, req.headers(), &trusted_proxies);
+                    req.extensions_mut().insert(ConnInfo { remote_ip, ..conn_info.clone() });
+                    req.extensions_mut().insert(BodyChunkSize(buffers.body_chunk_bytes));
+                    async move {
+                        if !has_valid_host(&req) {
+                            Ok(make_error_resp(http::StatusCode::BAD_REQUEST, "invalid Host header"))
+                        } else if req.version() == Version::HTTP_11 {
+                            let resp = match buffer_request(req, &body_policy).await {
+                                Ok(mut req) => ox_svc.handle_request(&mut req).await,
+                                Err(resp) => resp,
+                            };
+                            Ok::<_, hyper::Error>(resp)
+                        } else {
+                            Ok(Response::builder()
+                                .status(400)
+                                .body(full("not HTTP/1.1, abort connection"))
+                                .expect("Failed to construct response"))
+                        }
+                    }
+                }
+            );
+
+        if let Err(e) = http1::Builder::new().serve_connection(io, svc_fn).await {
+            eprintln!("Serve error: {e:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
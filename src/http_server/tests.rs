@@ -0,0 +1,300 @@
+use std::sync::Arc;
+
+use hyper::http;
+
+use super::{bind_listener, remote_ip, serve, start_server};
+use crate::build::BuiltHttpServer;
+use crate::config::http_server::parse_bind_addr;
+use crate::util::cidr::Cidr;
+use crate::testing;
+
+#[test]
+fn bind_addr_accepts_ipv4_and_bracketed_ipv6() {
+    assert_eq!(parse_bind_addr("0.0.0.0:80").unwrap().port(), 80);
+    assert!(parse_bind_addr("[::1]:8080").unwrap().is_ipv6());
+    assert!(parse_bind_addr("[::]:8080").unwrap().ip().is_unspecified());
+
+    let err = parse_bind_addr("::1:8080").unwrap_err();
+    assert!(err.to_string().contains("brackets"));
+    assert!(parse_bind_addr("localhost:8080").is_err());
+}
+
+#[tokio::test]
+async fn serves_over_ipv6_loopback() {
+    let listener = bind_listener(parse_bind_addr("[::1]:0").unwrap()).expect("bind [::1]");
+    let addr = listener.local_addr().unwrap();
+    assert!(addr.is_ipv6());
+    let svc = testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - respond: { status: 200, body: "${port}" }
+"#);
+    tokio::spawn(serve(listener, None, Arc::new(svc), Default::default(), Default::default(), Vec::new()));
+
+    let resp = testing::get(addr, "/").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body().as_ref(), addr.port().to_string().as_bytes());
+}
+
+#[tokio::test]
+async fn start_server_reports_the_port_picked_for_port_zero() {
+    let handle = start_server(BuiltHttpServer {
+        bind: "127.0.0.1:0".into(),
+        tls: None,
+        service: testing::service("handler: router\nrules:\n  - ops:\n      - respond: { status: 200, body: \"${port}\" }\n"),
+        request_body: Default::default(),
+        buffers: Default::default(),
+        trusted_proxies: Vec::new(),
+    }).expect("bind port 0");
+    let addr = handle.local_addr();
+    assert_ne!(addr.port(), 0);
+
+    let resp = testing::get(addr, "/").await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body().as_ref(), addr.port().to_string().as_bytes());
+
+    handle.shutdown();
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn ipv6_wildcard_also_accepts_ipv4() {
+    let listener = bind_listener(parse_bind_addr("[::]:0").unwrap()).expect("bind [::]");
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(serve(listener, None, Arc::new(testing::service(r#"
+handler: router
+rules:
+  - ops:
+      - respond: { status: 204 }
+"#)), Default::default(), Default::default(), Vec::new()));
+
+    let resp = testing::get(([127, 0, 0, 1], port).into(), "/").await;
+    assert_eq!(resp.status(), 204);
+}
+
+#[test]
+fn forwarded_for_is_only_believed_from_trusted_peers() {
+    let trusted: Vec<Cidr> = ["10.0.0.0/8", "fd00::/8"].iter().map(|c| Cidr::parse(c).unwrap()).collect();
+    let ip = |s: &str| s.parse::<std::net::IpAddr>().unwrap();
+    let xff = |v: &str| {
+        let mut h = http::HeaderMap::new();
+        h.insert("x-forwarded-for", v.parse().unwrap());
+        h
+    };
+
+    // untrusted peer: the header is ignored
+    assert_eq!(remote_ip(ip("203.0.113.9"), &xff("198.51.100.1"), &trusted), ip("203.0.113.9"));
+    assert_eq!(remote_ip(ip("10.0.0.2"), &xff("198.51.100.1"), &[]), ip("10.0.0.2"));
+    // trusted hops are skipped from the right; a spoofed leftmost entry is not reached
+    assert_eq!(remote_ip(ip("10.0.0.2"), &xff("1.1.1.1, 198.51.100.1, 10.0.0.3"), &trusted), ip("198.51.100.1"));
+    assert_eq!(remote_ip(ip("::ffff:10.0.0.2"), &xff("198.51.100.1"), &trusted), ip("198.51.100.1"));
+    assert_eq!(remote_ip(ip("fd00::1"), &xff("2001:db8::5"), &trusted), ip("2001:db8::5"));
+    assert_eq!(remote_ip(ip("10.0.0.2"), &xff("junk, 10.0.0.3"), &trusted), ip("10.0.0.3"));
+
+    assert!(Cidr::parse("10.0.0.0/33").is_err());
+    assert!(Cidr::parse("10.0.0.1").unwrap().contains(ip("10.0.0.1")));
+    assert!(!Cidr::parse("10.0.0.1").unwrap().contains(ip("10.0.0.2")));
+    assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(ip("192.0.2.1")));
+}
+
+#[tokio::test]
+async fn remote_ip_uses_forwarded_for_only_behind_trusted_peer() {
+    let spawn = |trusted: &str| {
+        let trusted = vec![Cidr::parse(trusted).unwrap()];
+        async move {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let svc = testing::service("handler: router\nrules:\n  - ops:\n      - respond: { status: 200, body: \"${remote.ip}\" }\n");
+            tokio::spawn(serve(listener, None, Arc::new(svc), Default::default(), Default::default(), trusted));
+            addr
+        }
+    };
+    let req = || http::Request::get("/")
+        .header(http::header::HOST, "example.com")
+        .header("x-forwarded-for", "198.51.100.1")
+        .body(http_body_util::Full::default())
+        .unwrap();
+
+    let addr = spawn("127.0.0.0/8").await;
+    assert_eq!(testing::send(addr, req()).await.body().as_ref(), b"198.51.100.1");
+
+    let addr = spawn("10.0.0.0/8").await;
+    assert_eq!(testing::send(addr, req()).await.body().as_ref(), b"127.0.0.1");
+}
+
+// --- TLS tests ---
+
+/// A self-signed certificate for `localhost` and `127.0.0.1`, written as PEM files into a temp dir.
+fn self_signed() -> (std::path::PathBuf, rcgen::CertifiedKey) {
+    let issued = rcgen::generate_simple_self_signed(vec!["localhost".into(), "127.0.0.1".into()]).unwrap();
+    let dir = testing::temp_dir(&[
+        ("cert.pem", &issued.cert.pem()),
+        ("key.pem", &issued.key_pair.serialize_pem()),
+    ]);
+    (dir, issued)
+}
+
+fn tls_server(dir: &std::path::Path, extra: &str, body: &str) -> crate::config::http_server::HttpServer {
+    serde_yaml::from_str(&format!(r#"
+bind: "127.0.0.1:0"
+tls:
+  cert_file: "{}"
+  key_file: "{}"
+{extra}
+service:
+  handler: router
+  rules:
+    - ops:
+        - respond: {{ status: 200, body: "{body}" }}
+"#, dir.join("cert.pem").display(), dir.join("key.pem").display())).unwrap()
+}
+
+fn client_config(roots: &[&rcgen::CertifiedKey]) -> tokio_rustls::rustls::ConfigBuilder<
+    tokio_rustls::rustls::ClientConfig,
+    tokio_rustls::rustls::client::WantsClientCert,
+> {
+    use tokio_rustls::rustls;
+    let mut store = rustls::RootCertStore::empty();
+    for ck in roots {
+        store.add(ck.cert.der().clone()).unwrap();
+    }
+    rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(store)
+}
+
+/// GET `/` over TLS, asking for `server_name` in the ClientHello.
+async fn tls_get(
+    addr: std::net::SocketAddr,
+    client: tokio_rustls::rustls::ClientConfig,
+    server_name: &str,
+) -> std::io::Result<http::Response<bytes::Bytes>> {
+    use http_body_util::BodyExt;
+    use rustls_pki_types::ServerName;
+
+    let tcp = tokio::net::TcpStream::connect(addr).await?;
+    let name = ServerName::try_from(server_name.to_string()).unwrap();
+    let stream = tokio_rustls::TlsConnector::from(Arc::new(client)).connect(name, tcp).await?;
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream))
+        .await
+        .map_err(std::io::Error::other)?;
+    tokio::spawn(conn);
+    let req = http::Request::get("/")
+        .header(http::header::HOST, "localhost")
+        .body(http_body_util::Full::<bytes::Bytes>::default())
+        .unwrap();
+    let resp = sender.send_request(req).await.map_err(std::io::Error::other)?;
+    let (parts, body) = resp.into_parts();
+    let bytes = body.collect().await.map_err(std::io::Error::other)?.to_bytes();
+    Ok(http::Response::from_parts(parts, bytes))
+}
+
+#[tokio::test]
+async fn tls_listener_terminates_tls() {
+    let (dir, issued) = self_signed();
+    let server = crate::build::build_http_server(tls_server(&dir, "", "${tls} ${scheme} ${sni}")).unwrap();
+    let handle = start_server(server).unwrap();
+
+    let resp = tls_get(handle.local_addr(), client_config(&[&issued]).with_no_client_auth(), "localhost").await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body().as_ref(), b"true https localhost");
+
+    // clients never send an IP address as SNI, so the variable stays empty
+    let resp = tls_get(handle.local_addr(), client_config(&[&issued]).with_no_client_auth(), "127.0.0.1").await.unwrap();
+    assert_eq!(resp.body().as_ref(), b"true https ");
+
+    handle.shutdown();
+}
+
+#[test]
+fn tls_config_needs_existing_files_and_http1_alpn() {
+    let (dir, _) = self_signed();
+    assert!(tls_server(&dir, "", "x").validate().is_ok());
+
+    let mut missing = tls_server(&dir, "", "x");
+    missing.tls.as_mut().unwrap().key_file = dir.join("nope.pem");
+    assert!(missing.validate().unwrap_err().to_string().contains("key_file"));
+
+    let h2 = tls_server(&dir, "  alpn: [h2]", "x");
+    assert!(h2.validate().unwrap_err().to_string().contains("alpn"));
+
+    let zero = tls_server(&dir, "  handshake_timeout_ms: 0", "x");
+    assert!(zero.validate().unwrap_err().to_string().contains("handshake_timeout_ms"));
+}
+
+#[tokio::test]
+async fn stalled_tls_handshake_is_dropped() {
+    use tokio::io::AsyncReadExt;
+
+    let (dir, issued) = self_signed();
+    let server = crate::build::build_http_server(tls_server(&dir, "  handshake_timeout_ms: 100", "ok")).unwrap();
+    let handle = start_server(server).unwrap();
+
+    // open TCP and never send a ClientHello
+    let mut idle = tokio::net::TcpStream::connect(handle.local_addr()).await.unwrap();
+    let mut buf = [0u8; 1];
+    let n = tokio::time::timeout(std::time::Duration::from_secs(5), idle.read(&mut buf))
+        .await
+        .expect("stalled handshake was not dropped")
+        .unwrap_or(0);
+    assert_eq!(n, 0);
+
+    let resp = tls_get(handle.local_addr(), client_config(&[&issued]).with_no_client_auth(), "localhost").await.unwrap();
+    assert_eq!(resp.body().as_ref(), b"ok");
+    handle.shutdown();
+}
+
+/// A CA plus a client certificate it issued for `alice` (SAN `alice.example`), with the CA written to `ca.pem` in `dir`.
+fn client_identity(dir: &std::path::Path) -> (rcgen::Certificate, rcgen::KeyPair) {
+    use rcgen::{BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca = CertificateParams::new(Vec::new()).unwrap();
+    ca.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    ca.distinguished_name.push(DnType::CommonName, "test client ca");
+    let ca = ca.self_signed(&ca_key).unwrap();
+    std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+
+    let key = KeyPair::generate().unwrap();
+    let mut leaf = CertificateParams::new(vec!["alice.example".into()]).unwrap();
+    leaf.distinguished_name.push(DnType::CommonName, "alice");
+    leaf.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    (leaf.signed_by(&key, &ca, &ca_key).unwrap(), key)
+}
+
+#[tokio::test]
+async fn mutual_tls_exposes_the_verified_client_certificate() {
+    use rustls_pki_types::PrivateKeyDer;
+    use sha2::{Digest, Sha256};
+
+    let (dir, server_cert) = self_signed();
+    let (client_cert, client_key) = client_identity(&dir);
+    let with_cert = || client_config(&[&server_cert])
+        .with_client_auth_cert(
+            vec![client_cert.der().clone()],
+            PrivateKeyDer::try_from(client_key.serialize_der()).unwrap(),
+        )
+        .unwrap();
+    let start = |required: bool| {
+        let extra = format!("  client_auth: {{ ca_file: \"{}\", required: {required} }}", dir.join("ca.pem").display());
+        let server = tls_server(&dir, &extra, "${cert.cn}|${cert.san}|${cert.fingerprint}");
+        start_server(crate::build::build_http_server(server).unwrap()).unwrap()
+    };
+
+    let handle = start(true);
+    let resp = tls_get(handle.local_addr(), with_cert(), "localhost").await.unwrap();
+    let fingerprint: String = Sha256::digest(client_cert.der()).iter().map(|b| format!("{b:02x}")).collect();
+    assert_eq!(resp.body().as_ref(), format!("alice|alice.example|{fingerprint}").as_bytes());
+    // no certificate, no connection
+    let anonymous = client_config(&[&server_cert]).with_no_client_auth();
+    assert!(tls_get(handle.local_addr(), anonymous, "localhost").await.is_err());
+    handle.shutdown();
+
+    let handle = start(false);
+    let anonymous = client_config(&[&server_cert]).with_no_client_auth();
+    assert_eq!(tls_get(handle.local_addr(), anonymous, "localhost").await.unwrap().body().as_ref(), b"||");
+    handle.shutdown();
+}
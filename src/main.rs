@@ -1,20 +1,33 @@
 mod build;
 mod cli;
 mod config;
+mod embedded;
 mod handler;
 mod http_server;
+mod metrics;
 mod pattern;
 mod template;
 mod util;
+#[cfg(test)]
+mod testing;
 
 use cli::Args;
 use clap::Parser;
+use http_server::ServerHandle;
 use std::path::Path;
-use tokio::task::JoinHandle;
 
 #[tokio::main]
 async fn main() {
+    // `RUST_LOG=oxidase=debug` turns on per-request logging
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+
     let args = Args::parse();
+    embedded::register_builtin();
 
     if args.watch {
         run_watch_loop(&args).await;
@@ -35,7 +48,7 @@ async fn run_once(args: &Args) {
     let handles = spawn_servers(servers);
 
     for h in handles {
-        let _ = h.await;
+        h.wait().await;
     }
 }
 
@@ -94,22 +107,26 @@ async fn run_watch_loop(args: &Args) {
                 while rx.try_recv().is_ok() {} 
                 // Abort current servers
                 for h in handles {
-                    h.abort();
+                    h.shutdown();
                 }
             }
         }
     }
 }
 
-fn spawn_servers(servers: Vec<config::http_server::HttpServer>) -> Vec<JoinHandle<()>> {
+fn spawn_servers(servers: Vec<config::http_server::HttpServer>) -> Vec<ServerHandle> {
     let mut handles = Vec::new();
     for srv in servers {
-        match build::build_http_server(srv) {
-            Ok(built) => {
-                handles.push(tokio::spawn(http_server::start_server(built)));
+        let started = build::build_http_server(srv)
+            .map_err(|e| format!("Failed to build server: {e}"))
+            .and_then(|built| http_server::start_server(built).map_err(|e| format!("Failed to start server: {e}")));
+        match started {
+            Ok(handle) => {
+                println!("Listening on {}", handle.local_addr());
+                handles.push(handle);
             }
             Err(e) => {
-                eprintln!("Failed to build server: {e}");
+                eprintln!("{e}");
             }
         }
     }
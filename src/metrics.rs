@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+/// Per-rule match counters for a single router, labelled by rule name or index,
+/// plus how often each kind of op it contains has run.
+#[derive(Debug)]
+pub struct RouterMetrics {
+    rules: Vec<RuleCounter>,
+    /// Keyed by op name, fixed at build time so recording needs no lock.
+    ops: BTreeMap<&'static str, AtomicU64>,
+}
+
+#[derive(Debug)]
+struct RuleCounter {
+    label: String,
+    matches: AtomicU64,
+}
+
+impl RouterMetrics {
+    pub fn new(
+        labels: impl IntoIterator<Item = String>,
+        op_names: impl IntoIterator<Item = &'static str>,
+    ) -> Self {
+        RouterMetrics {
+            rules: labels
+                .into_iter()
+                .map(|label| RuleCounter { label, matches: AtomicU64::new(0) })
+                .collect(),
+            ops: op_names.into_iter().map(|name| (name, AtomicU64::new(0))).collect(),
+        }
+    }
+
+    pub fn record_match(&self, idx: usize) {
+        if let Some(rc) = self.rules.get(idx) {
+            rc.matches.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_op(&self, name: &str) {
+        if let Some(runs) = self.ops.get(name) {
+            runs.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Render the counters in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::from("# TYPE oxidase_router_rule_matches_total counter\n");
+        for rc in &self.rules {
+            let label = rc.label.replace('\\', "\\\\").replace('"', "\\\"");
+            let _ = writeln!(
+                out,
+                "oxidase_router_rule_matches_total{{rule=\"{label}\"}} {}",
+                rc.matches.load(Ordering::Relaxed),
+            );
+        }
+        out.push_str("# TYPE oxidase_router_op_runs_total counter\n");
+        for (name, runs) in &self.ops {
+            let _ = writeln!(out, "oxidase_router_op_runs_total{{op=\"{name}\"}} {}", runs.load(Ordering::Relaxed));
+        }
+        out
+    }
+}
+
+/// Live connection counts for one forward service's upstream pool.
+#[derive(Debug)]
+pub struct PoolStats {
+    label: String,
+    open: AtomicU64,
+    active: AtomicU64,
+}
+
+/// Every pool still in use, rendered alongside router counters by the `metrics` op.
+static POOLS: Mutex<Vec<Weak<PoolStats>>> = Mutex::new(Vec::new());
+
+impl PoolStats {
+    /// Create stats for an upstream labelled `label` and add them to the registry.
+    pub fn register(label: String) -> Arc<Self> {
+        let stats = Arc::new(PoolStats { label, open: AtomicU64::new(0), active: AtomicU64::new(0) });
+        let mut pools = POOLS.lock().unwrap_or_else(|e| e.into_inner());
+        pools.retain(|p| p.strong_count() > 0);
+        pools.push(Arc::downgrade(&stats));
+        stats
+    }
+
+    pub fn open(&self) -> u64 {
+        self.open.load(Ordering::Relaxed)
+    }
+
+    pub fn active(&self) -> u64 {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Open connections not currently carrying a request.
+    pub fn idle(&self) -> u64 {
+        self.open().saturating_sub(self.active())
+    }
+
+    pub fn connection_opened(&self) {
+        self.open.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_closed(&self) {
+        self.open.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Count a request as active until the returned guard is dropped.
+    pub fn request_started(self: &Arc<Self>) -> ActiveRequest {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        ActiveRequest(self.clone())
+    }
+}
+
+#[derive(Debug)]
+pub struct ActiveRequest(Arc<PoolStats>);
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Render idle/active connection gauges for every registered forward pool.
+pub fn render_pools() -> String {
+    let pools = POOLS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut out = String::from("# TYPE oxidase_forward_pool_connections gauge\n");
+    for stats in pools.iter().filter_map(Weak::upgrade) {
+        let label = stats.label.replace('\\', "\\\\").replace('"', "\\\"");
+        for (state, n) in [("idle", stats.idle()), ("active", stats.active())] {
+            let _ = writeln!(out, "oxidase_forward_pool_connections{{upstream=\"{label}\",state=\"{state}\"}} {n}");
+        }
+    }
+    out
+}
@@ -1,14 +1,19 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use super::context::PatternContext;
 use super::PatternError;
 use super::context::Expand;
 use super::placeholder::parse_placeholder;
+use crate::template::Filter;
+
+/// Regex source, capture names in order, and the filters attached to each capture.
+pub type RegexSource = (String, Vec<String>, HashMap<String, Vec<Filter>>);
 
 pub fn build_regex_source<C: PatternContext>(
     input: &str, ctx: &C
-) -> Result<(String, Vec<String>), PatternError> {
+) -> Result<RegexSource, PatternError> {
     let mut out = String::from("^");
     let mut names = Vec::new();
+    let mut filters = HashMap::new();
     let mut names_seen = HashSet::new();
     let mut chars = input.chars().peekable();
     let mut tail_only_name_seen = false;
@@ -42,6 +47,9 @@ pub fn build_regex_source<C: PatternContext>(
                 if let Some(name) = ph.name {
                     if !names_seen.insert(name.clone()) { return Err(PatternError::DupName(name)); }
                     out.push_str(&format!("(?P<{}>{})", name, src));
+                    if !ph.filters.is_empty() {
+                        filters.insert(name.clone(), ph.filters);
+                    }
                     names.push(name);
                 } else {
                     out.push_str(&format!("(?:{})", src));
@@ -55,5 +63,5 @@ pub fn build_regex_source<C: PatternContext>(
     }
 
     out.push('$');
-    Ok((out, names))
+    Ok((out, names, filters))
 }
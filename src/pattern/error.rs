@@ -8,5 +8,6 @@ pub enum PatternError {
     #[error("duplicate capture name: {0}")] DupName(String),
     #[error("type `{0}` not allowed in this context")] BadTypeForCtx(&'static str),
     #[error("a tail-only placeholder must be the last component")] TailOnlyMustBeLast,
+    #[error("capture `{0}` exceeds {1} bytes after its filters")] CaptureTooLong(String, usize),
     #[error("regex compile error: {0}")] Regex(#[from] regex::Error),
 }
@@ -4,7 +4,7 @@ pub mod compiler;
 pub mod error;
 
 use std::collections::HashMap;
-use regex::Regex;
+use regex::{Regex, RegexBuilder};
 
 use context::{
     PatternContext,
@@ -14,12 +14,14 @@ use context::{
 };
 use compiler::build_regex_source;
 use error::PatternError;
+use crate::template::{Filter, apply_filters};
 
 
 #[derive(Debug, Clone)]
 pub struct CompiledPattern {
     re: Regex,
     names: Vec<String>,
+    filters: HashMap<String, Vec<Filter>>,
     pub raw: String,
 }
 impl CompiledPattern {
@@ -29,25 +31,48 @@ impl CompiledPattern {
     #[inline]
     pub fn regex(&self) -> &Regex { &self.re }
 
-    pub fn captures_map(&self, s: &str) -> Option<HashMap<String, String>> {
-        let caps = self.re.captures(s)?;
+    /// Names of the captures this pattern binds.
+    #[inline]
+    pub fn names(&self) -> &[String] { &self.names }
+
+    /// Captures by name, each run through the filters written after it; `Ok(None)`
+    /// when `s` does not match. A capture whose filters grow it past `max_len` bytes
+    /// is an error, which callers treat as a failed match.
+    pub fn captures_map(&self, s: &str, max_len: usize) -> Result<Option<HashMap<String, String>>, PatternError> {
+        let Some(caps) = self.re.captures(s) else { return Ok(None) };
         let mut out = HashMap::new();
         for n in &self.names {
-            if let Some(m) = caps.name(n) { out.insert(n.clone(), m.as_str().to_string()); }
+            if let Some(m) = caps.name(n) {
+                let val = m.as_str().to_string();
+                let val = match self.filters.get(n) {
+                    Some(filters) => apply_filters(filters, val, max_len)
+                        .ok_or_else(|| PatternError::CaptureTooLong(n.clone(), max_len))?,
+                    None => val,
+                };
+                out.insert(n.clone(), val);
+            }
         }
-        Some(out)
+        Ok(Some(out))
     }
 }
 
 pub fn compile<C: PatternContext>(input: &str, ctx: &C) -> Result<CompiledPattern, PatternError> {
-    let (regex_src, names) = build_regex_source(input, ctx)?;
+    let (regex_src, names, filters) = build_regex_source(input, ctx)?;
     let re = Regex::new(&regex_src)?;
-    Ok(CompiledPattern { re, names, raw: input.to_string() })
+    Ok(CompiledPattern { re, names, filters, raw: input.to_string() })
+}
+
+/// Like [`compile`], but letters match regardless of case; captures keep the input's case.
+pub fn compile_case_insensitive<C: PatternContext>(input: &str, ctx: &C) -> Result<CompiledPattern, PatternError> {
+    let (regex_src, names, filters) = build_regex_source(input, ctx)?;
+    let re = RegexBuilder::new(&regex_src).case_insensitive(true).build()?;
+    Ok(CompiledPattern { re, names, filters, raw: input.to_string() })
 }
 
 pub fn compile_path(input: &str)  -> Result<CompiledPattern, PatternError> { compile(input, &PathCtx) }
 pub fn compile_host(input: &str)  -> Result<CompiledPattern, PatternError> { compile(input, &HostCtx) }
 pub fn compile_value(input: &str) -> Result<CompiledPattern, PatternError> { compile(input, &ValueCtx) }
+pub fn compile_path_nocase(input: &str) -> Result<CompiledPattern, PatternError> { compile_case_insensitive(input, &PathCtx) }
 
 #[cfg(test)]
 mod tests;
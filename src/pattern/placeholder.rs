@@ -1,5 +1,6 @@
 use super::context::PatternContext;
 use super::PatternError;
+use crate::template::{Filter, parse_filter_chain};
 use crate::util::parse::parse_call;
 
 /// Built-in placeholder types (parse-time)
@@ -20,9 +21,19 @@ pub enum TypeSpec {
 pub struct Placeholder {
     pub name: Option<String>,
     pub ty: TypeSpec,
+    /// Template filters run on the captured text before it is stored: `<name:label|lower>`.
+    pub filters: Vec<Filter>,
 }
 
 pub fn parse_placeholder<C: PatternContext>(buf: &str, ctx: &C) -> Result<Placeholder, PatternError> {
+    let (buf, filters) = match filter_split(buf) {
+        Some(i) => {
+            let filters = parse_filter_chain(&buf[i + 1..])
+                .map_err(|e| PatternError::BadPlaceholder(e.to_string()))?;
+            (&buf[..i], filters)
+        }
+        None => (buf, Vec::new()),
+    };
     let (lhs, ty_raw) = if let Some(colon) = buf.find(':') {
         (&buf[..colon], Some(&buf[colon + 1..]))
     } else { (buf, None) };
@@ -33,7 +44,32 @@ pub fn parse_placeholder<C: PatternContext>(buf: &str, ctx: &C) -> Result<Placeh
         Some(t) => parse_type_spec(t, ctx)?,
     };
 
-    Ok(Placeholder { name, ty })
+    if name.is_none() && !filters.is_empty() {
+        return Err(PatternError::BadPlaceholder("filters need a named capture".into()));
+    }
+
+    Ok(Placeholder { name, ty, filters })
+}
+
+/// Position of the first `|` outside quotes and parentheses, so `regex(a|b)` stays whole.
+fn filter_split(buf: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut esc = false;
+    for (i, c) in buf.char_indices() {
+        match (quote, c) {
+            _ if esc => esc = false,
+            (Some(_), '\\') => esc = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth = depth.saturating_sub(1),
+            (None, '|') if depth == 0 => return Some(i),
+            _ => {}
+        }
+    }
+    None
 }
 
 pub fn parse_type_spec<C: PatternContext>(s: &str, ctx: &C) -> Result<TypeSpec, PatternError> {
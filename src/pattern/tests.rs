@@ -1,10 +1,11 @@
 use super::*;
+use crate::template::DEFAULT_MAX_EXPANSION;
 
 #[test]
 fn path_and_captures() {
     let p = compile_path("/post/<slug:slug>").unwrap();
     assert!(p.is_match("/post/hello-world"));
-    assert_eq!(p.captures_map("/post/hello-world").unwrap().get("slug").unwrap(), "hello-world");
+    assert_eq!(p.captures_map("/post/hello-world", DEFAULT_MAX_EXPANSION).unwrap().unwrap().get("slug").unwrap(), "hello-world");
     assert!(!p.is_match("/post/"));
 }
 
@@ -13,7 +14,7 @@ fn host_labels_ok() {
     let p = compile_host("<sub:labels>.example.com").unwrap();
     assert!(p.is_match("x.example.com"));
     assert!(p.is_match("a.b.c.example.com"));
-    assert_eq!(p.captures_map("x.example.com").unwrap().get("sub").unwrap(), "x");
+    assert_eq!(p.captures_map("x.example.com", DEFAULT_MAX_EXPANSION).unwrap().unwrap().get("sub").unwrap(), "x");
 }
 
 #[test]
@@ -42,5 +43,24 @@ fn tail_only_rule() {
 fn non_capturing_value() {
     let p = compile_value("curl/<:any>").unwrap();
     assert!(p.is_match("curl/7.86.0"));
-    assert!(p.captures_map("curl/7.86.0").unwrap().is_empty());
+    assert!(p.captures_map("curl/7.86.0", DEFAULT_MAX_EXPANSION).unwrap().unwrap().is_empty());
+}
+
+#[test]
+fn capture_filters_apply_at_match_time() {
+    let p = compile_value("<tag:slug|lower|trim_prefix(\"x-\")>").unwrap();
+    assert_eq!(p.captures_map("X-Shop", DEFAULT_MAX_EXPANSION).unwrap().unwrap().get("tag").unwrap(), "shop");
+    // a `|` inside a regex argument is not a filter separator
+    let p = compile_path("/<kind:regex(\"a|b\")|upper>").unwrap();
+    assert_eq!(p.captures_map("/b", DEFAULT_MAX_EXPANSION).unwrap().unwrap().get("kind").unwrap(), "B");
+    assert!(compile_path("/<:slug|lower>").is_err());
+    assert!(compile_path("/<x:slug|nope>").is_err());
+}
+
+#[test]
+fn oversized_filtered_capture_is_an_error() {
+    let p = compile_path("/<x:slug|repeat(4)>").unwrap();
+    assert_eq!(p.captures_map("/ab", 8).unwrap().unwrap().get("x").unwrap(), "abababab");
+    assert!(matches!(p.captures_map("/abc", 8), Err(PatternError::CaptureTooLong(..))));
+    assert!(p.captures_map("/a/b", 8).unwrap().is_none());
 }
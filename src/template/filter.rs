@@ -7,10 +7,36 @@ pub enum Filter {
     TrimPrefix(String),
     TrimSuffix(String),
     Replace { from: String, to: String },
+    ReplaceSegment { from: String, to: String },
+    FormGet(String),
+    Add(i64),
+    Sub(i64),
+    HashMod(u64),
+    Repeat(usize),
+    Reverse,
+    Slugify,
+    Hex,
+    Unhex,
+    /// Characters `start..end`; a missing `end` runs to the end of the value.
+    Substring { start: usize, end: Option<usize> },
+    IfPresent(String),
+    IfEmpty(String),
 }
 
+impl Filter {
+    /// Filters that decide what an empty value becomes, so a router-wide
+    /// `template_missing_default` must not pre-empt them.
+    pub fn handles_empty(&self) -> bool {
+        matches!(self, Filter::Default(_) | Filter::IfPresent(_) | Filter::IfEmpty(_))
+    }
+}
+
+/// Largest count `repeat(n)` accepts.
+pub const MAX_REPEAT: usize = 10_000;
+
 pub struct FilterSpec {
     pub name: &'static str,
+    /// Most arguments the filter takes; [`build_filter`] rejects calls missing required ones.
     pub arity: usize,
 }
 
@@ -22,6 +48,19 @@ pub const FILTER_SPECS: &[FilterSpec] = &[
     FilterSpec { name: "trim_prefix", arity: 1 },
     FilterSpec { name: "trim_suffix", arity: 1 },
     FilterSpec { name: "replace", arity: 2 },
+    FilterSpec { name: "replace_segment", arity: 2 },
+    FilterSpec { name: "form_get", arity: 1 },
+    FilterSpec { name: "add", arity: 1 },
+    FilterSpec { name: "sub", arity: 1 },
+    FilterSpec { name: "hash_mod", arity: 1 },
+    FilterSpec { name: "repeat", arity: 1 },
+    FilterSpec { name: "reverse", arity: 0 },
+    FilterSpec { name: "slugify", arity: 0 },
+    FilterSpec { name: "hex", arity: 0 },
+    FilterSpec { name: "unhex", arity: 0 },
+    FilterSpec { name: "substring", arity: 2 },
+    FilterSpec { name: "if_present", arity: 1 },
+    FilterSpec { name: "if_empty", arity: 1 },
 ];
 
 pub fn build_filter(name: &str, args: &[String]) -> Option<Filter> {
@@ -29,14 +68,38 @@ pub fn build_filter(name: &str, args: &[String]) -> Option<Filter> {
         "lower" => Some(Filter::Lower),
         "upper" => Some(Filter::Upper),
         "url_encode" => Some(Filter::UrlEncode),
+        "reverse" => Some(Filter::Reverse),
+        "slugify" => Some(Filter::Slugify),
+        "hex" => Some(Filter::Hex),
+        "unhex" => Some(Filter::Unhex),
         "default" => args.get(0).map(|v| Filter::Default(v.clone())),
         "trim_prefix" => args.get(0).map(|v| Filter::TrimPrefix(v.clone())),
         "trim_suffix" => args.get(0).map(|v| Filter::TrimSuffix(v.clone())),
+        "form_get" => args.first().map(|v| Filter::FormGet(v.clone())),
+        "if_present" => args.first().map(|v| Filter::IfPresent(v.clone())),
+        "if_empty" => args.first().map(|v| Filter::IfEmpty(v.clone())),
+        "add" => args.first().and_then(|v| v.trim().parse().ok()).map(Filter::Add),
+        "sub" => args.first().and_then(|v| v.trim().parse().ok()).map(Filter::Sub),
+        "hash_mod" => args.first().and_then(|v| v.trim().parse().ok()).filter(|n| *n > 0).map(Filter::HashMod),
+        "repeat" => args.first().and_then(|v| v.trim().parse().ok()).filter(|n| *n <= MAX_REPEAT).map(Filter::Repeat),
         "replace" => {
             if args.len() == 2 {
                 Some(Filter::Replace { from: args[0].clone(), to: args[1].clone() })
             } else { None }
         }
+        "substring" => {
+            // negative offsets clamp to the start of the value
+            let offset = |v: &String| v.trim().parse::<i64>().ok().map(|n| n.max(0) as usize);
+            match args {
+                [start] => offset(start).map(|start| Filter::Substring { start, end: None }),
+                [start, end] => Some(Filter::Substring { start: offset(start)?, end: Some(offset(end)?) }),
+                _ => None,
+            }
+        }
+        "replace_segment" => match args {
+            [from, to] => Some(Filter::ReplaceSegment { from: from.clone(), to: to.clone() }),
+            _ => None,
+        },
         _ => None,
     }
 }
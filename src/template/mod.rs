@@ -3,11 +3,54 @@ mod filter;
 use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 pub use filter::{Filter, FILTER_SPECS, build_filter};
 use crate::util::parse::parse_call;
+use crate::util::http::parse_form;
 
 #[derive(Debug, Clone)]
 pub enum TemplateSegment {
     Literal(String),
-    Expr { var: String, filters: Vec<Filter> },
+    Expr { source: ExprSource, filters: Vec<Filter> },
+}
+
+/// Where an expression's value comes from, before filters run.
+#[derive(Debug, Clone)]
+pub enum ExprSource {
+    Var(String),
+    /// `capture_or(name, "literal")`: the variable if the provider has it, even when empty.
+    CaptureOr { name: String, fallback: String },
+    /// A quoted string such as `"-"`, e.g. to feed `repeat`.
+    Literal(String),
+    /// `a ?? b ?? "literal"`: the first alternative that expands to a non-empty value.
+    FirstNonEmpty(Vec<ExprSource>),
+}
+
+impl ExprSource {
+    /// `None` when the value is not set at all (for a chain: when no alternative is).
+    fn eval<T: ValueProvider>(&self, provider: &T) -> Option<String> {
+        match self {
+            ExprSource::Var(var) => provider.get(var),
+            ExprSource::CaptureOr { name, fallback } => Some(provider.get(name).unwrap_or_else(|| fallback.clone())),
+            ExprSource::Literal(s) => Some(s.clone()),
+            ExprSource::FirstNonEmpty(alts) => {
+                let mut any_set = false;
+                for alt in alts {
+                    match alt.eval(provider) {
+                        Some(v) if !v.is_empty() => return Some(v),
+                        Some(_) => any_set = true,
+                        None => {}
+                    }
+                }
+                any_set.then(String::new)
+            }
+        }
+    }
+
+    fn reads_var(&self, prefix: &str) -> bool {
+        match self {
+            ExprSource::Var(name) | ExprSource::CaptureOr { name, .. } => name.starts_with(prefix),
+            ExprSource::Literal(_) => false,
+            ExprSource::FirstNonEmpty(alts) => alts.iter().any(|a| a.reads_var(prefix)),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -15,6 +58,16 @@ pub struct CompiledTemplate {
     segments: Vec<TemplateSegment>,
 }
 
+impl CompiledTemplate {
+    /// Whether any expression reads a variable whose name starts with `prefix`.
+    pub fn reads_var(&self, prefix: &str) -> bool {
+        self.segments.iter().any(|seg| match seg {
+            TemplateSegment::Literal(_) => false,
+            TemplateSegment::Expr { source, .. } => source.reads_var(prefix),
+        })
+    }
+}
+
 #[derive(Debug)]
 pub enum TemplateError {
     Invalid(String),
@@ -70,7 +123,7 @@ pub fn compile_template(src: &str) -> Result<CompiledTemplate, TemplateError> {
                 return Err(TemplateError::Invalid("unclosed `${`".to_string()));
             }
             segments.push(TemplateSegment::Expr {
-                var: parse_var(&expr)?,
+                source: parse_source(&expr)?,
                 filters: parse_filters(&expr)?,
             });
         } else {
@@ -85,46 +138,132 @@ pub fn compile_template(src: &str) -> Result<CompiledTemplate, TemplateError> {
     Ok(CompiledTemplate { segments })
 }
 
+/// Default cap, in bytes, on the output of a single template expansion.
+pub const DEFAULT_MAX_EXPANSION: usize = 64 * 1024;
+
+/// Settings shared by every expansion of one router.
+#[derive(Debug, Clone)]
+pub struct ExpandOptions {
+    /// Cap, in bytes, on the output and on every intermediate filter value.
+    pub max_len: usize,
+    /// Used for a variable that is not set instead of an empty string; an
+    /// expression with its own `default`/`if_present`/`if_empty` filter keeps using that.
+    pub missing_default: Option<String>,
+}
+
+impl Default for ExpandOptions {
+    fn default() -> Self {
+        ExpandOptions { max_len: DEFAULT_MAX_EXPANSION, missing_default: None }
+    }
+}
+
+/// Expand `tpl`, failing once the output or any intermediate filter value exceeds `opts.max_len` bytes.
 pub fn expand_template<T: ValueProvider>(
     tpl: &CompiledTemplate,
     provider: &T,
+    opts: &ExpandOptions,
 ) -> Result<String, TemplateError> {
+    let max_len = opts.max_len;
+    let too_long = || TemplateError::Invalid(format!("expansion exceeds {max_len} bytes"));
     let mut out = String::new();
     for seg in &tpl.segments {
         match seg {
             TemplateSegment::Literal(s) => out.push_str(s),
-            TemplateSegment::Expr { var, filters } => {
-                let mut val = provider.get(var).unwrap_or_default();
+            TemplateSegment::Expr { source, filters } => {
+                let mut val = source.eval(provider).unwrap_or_else(|| {
+                    let own_default = filters.iter().any(Filter::handles_empty);
+                    opts.missing_default.as_ref().filter(|_| !own_default).cloned().unwrap_or_default()
+                });
                 for f in filters {
-                    val = apply_filter(f, val);
+                    val = apply_filter(f, val, max_len).ok_or_else(too_long)?;
                 }
                 out.push_str(&val);
             }
         }
+        if out.len() > max_len {
+            return Err(too_long());
+        }
     }
     Ok(out)
 }
 
-fn parse_var(expr: &str) -> Result<String, TemplateError> {
-    let var = expr.split('|').next().unwrap_or("").trim();
+/// Split `s` at each `sep` outside quotes and parentheses, so `"what??"` or
+/// `replace("|", "/")` stay whole.
+fn split_unquoted<'a>(s: &'a str, sep: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let (mut start, mut depth, mut quote, mut esc) = (0, 0usize, None, false);
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            _ if i < start => {}
+            _ if esc => esc = false,
+            (Some(_), '\\') => esc = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth = depth.saturating_sub(1),
+            (None, _) if depth == 0 && s[i..].starts_with(sep) => {
+                parts.push(&s[start..i]);
+                start = i + sep.len();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+fn parse_source(expr: &str) -> Result<ExprSource, TemplateError> {
+    let source = split_unquoted(expr, "|")[0];
+    let alts = split_unquoted(source, "??");
+    if alts.len() > 1 {
+        let alts = alts.into_iter().map(parse_single_source).collect::<Result<Vec<_>, _>>()?;
+        return Ok(ExprSource::FirstNonEmpty(alts));
+    }
+    parse_single_source(source)
+}
+
+fn parse_single_source(source: &str) -> Result<ExprSource, TemplateError> {
+    let var = source.trim();
     if var.is_empty() {
         return Err(TemplateError::Invalid("empty variable".to_string()));
     }
-    Ok(var.to_string())
+    if let Some(lit) = var.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        return Ok(ExprSource::Literal(lit.to_string()));
+    }
+    if !var.contains('(') {
+        return Ok(ExprSource::Var(var.to_string()));
+    }
+    let (name, args) = parse_call(var).map_err(|e| TemplateError::Invalid(e.to_string()))?;
+    match (name.as_str(), args.as_slice()) {
+        ("capture_or", [capture, fallback]) if !capture.is_empty() => Ok(ExprSource::CaptureOr {
+            name: capture.clone(),
+            fallback: fallback.clone(),
+        }),
+        _ => Err(TemplateError::Invalid(format!("unknown function or args: {var}"))),
+    }
 }
 
 fn parse_filters(expr: &str) -> Result<Vec<Filter>, TemplateError> {
+    // skip var
+    let source = split_unquoted(expr, "|")[0];
+    match expr.get(source.len() + 1..) {
+        Some(chain) => parse_filter_chain(chain),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Parse `f1 | f2(arg) | ...` on its own, as used after a pattern capture name.
+pub fn parse_filter_chain(chain: &str) -> Result<Vec<Filter>, TemplateError> {
     let mut filters = Vec::new();
-    let mut parts = expr.split('|');
-    parts.next(); // skip var
-    for raw in parts {
+    for raw in split_unquoted(chain, "|") {
         let raw = raw.trim();
         if raw.is_empty() { continue; }
         let (name, args) = parse_call(raw).map_err(|e| TemplateError::Invalid(e.to_string()))?;
         let name_str = name.as_str();
         let arity = FILTER_SPECS.iter().find(|spec| spec.name == name_str).map(|spec| spec.arity);
         let filt = match arity {
-            Some(n) if args.len() == n => build_filter(name_str, &args),
+            Some(n) if args.len() <= n => build_filter(name_str, &args),
             _ => None,
         }.ok_or_else(|| TemplateError::Invalid(format!("unknown filter or args: {raw}")))?;
         filters.push(filt);
@@ -132,8 +271,20 @@ fn parse_filters(expr: &str) -> Result<Vec<Filter>, TemplateError> {
     Ok(filters)
 }
 
-fn apply_filter(f: &Filter, val: String) -> String {
-    match f {
+/// Run `val` through each filter in turn, or `None` once a value exceeds `max_len` bytes.
+pub fn apply_filters(filters: &[Filter], val: String, max_len: usize) -> Option<String> {
+    filters.iter().try_fold(val, |v, f| apply_filter(f, v, max_len))
+}
+
+/// Apply one filter, or `None` when its result would exceed `max_len` bytes; `repeat`
+/// is checked before anything is allocated.
+fn apply_filter(f: &Filter, val: String, max_len: usize) -> Option<String> {
+    if let Filter::Repeat(n) = f
+        && val.len().saturating_mul(*n) > max_len
+    {
+        return None;
+    }
+    let out = match f {
         Filter::Default(v) => if val.is_empty() { v.clone() } else { val },
         Filter::Lower => val.to_lowercase(),
         Filter::Upper => val.to_uppercase(),
@@ -141,7 +292,106 @@ fn apply_filter(f: &Filter, val: String) -> String {
         Filter::TrimPrefix(p) => val.strip_prefix(p).unwrap_or(&val).to_string(),
         Filter::TrimSuffix(p) => val.strip_suffix(p).unwrap_or(&val).to_string(),
         Filter::Replace { from, to } => val.replace(from, to),
+        Filter::ReplaceSegment { from, to } => val
+            .split('/')
+            .map(|seg| if seg == from { to.as_str() } else { seg })
+            .collect::<Vec<_>>()
+            .join("/"),
+        Filter::FormGet(key) => form_get(&val, key).unwrap_or_default(),
+        Filter::Add(n) => map_int(val, |v| v.checked_add(*n)),
+        Filter::Sub(n) => map_int(val, |v| v.checked_sub(*n)),
+        Filter::HashMod(n) => (fnv1a(val.as_bytes()) % n).to_string(),
+        Filter::Repeat(n) => val.repeat(*n),
+        Filter::Reverse => val.chars().rev().collect(),
+        Filter::Slugify => slugify(&val),
+        Filter::Hex => val.bytes().map(|b| format!("{b:02x}")).collect(),
+        Filter::Unhex => unhex(&val).map(|b| String::from_utf8_lossy(&b).into_owned()).unwrap_or_default(),
+        Filter::Substring { start, end } => {
+            let take = end.map_or(usize::MAX, |end| end.saturating_sub(*start));
+            val.chars().skip(*start).take(take).collect()
+        }
+        Filter::IfPresent(lit) => if val.is_empty() { val } else { lit.clone() },
+        Filter::IfEmpty(lit) => if val.is_empty() { lit.clone() } else { String::new() },
+    };
+    (out.len() <= max_len).then_some(out)
+}
+
+/// Lowercase ASCII words joined by single hyphens; common Latin accents are
+/// transliterated (`é` to `e`, `ß` to `ss`) and anything else separates words.
+fn slugify(val: &str) -> String {
+    let mut out = String::with_capacity(val.len());
+    let mut gap = false;
+    for c in val.to_lowercase().chars() {
+        let mut buf = [0u8; 4];
+        let piece = if c.is_ascii_alphanumeric() { Some(&*c.encode_utf8(&mut buf)) } else { fold_accent(c) };
+        match piece {
+            Some(p) => {
+                if gap && !out.is_empty() {
+                    out.push('-');
+                }
+                gap = false;
+                out.push_str(p);
+            }
+            None => gap = true,
+        }
+    }
+    out
+}
+
+/// ASCII spelling of a lowercase accented Latin letter.
+fn fold_accent(c: char) -> Option<&'static str> {
+    Some(match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ą' => "a",
+        'æ' => "ae",
+        'ç' | 'ć' | 'č' => "c",
+        'ď' | 'đ' | 'ð' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ę' | 'ě' => "e",
+        'ì' | 'í' | 'î' | 'ï' | 'ī' => "i",
+        'ł' => "l",
+        'ñ' | 'ń' | 'ň' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => "o",
+        'œ' => "oe",
+        'ř' => "r",
+        'ś' | 'š' => "s",
+        'ß' => "ss",
+        'ť' => "t",
+        'þ' => "th",
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' => "u",
+        'ý' | 'ÿ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    })
+}
+
+/// Bytes spelled by a hex string of either case; `None` for odd lengths or non-hex digits.
+fn unhex(val: &str) -> Option<Vec<u8>> {
+    if !val.len().is_multiple_of(2) {
+        return None;
     }
+    (0..val.len())
+        .step_by(2)
+        .map(|i| val.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect()
+}
+
+/// 64-bit FNV-1a; stable across runs and platforms, so buckets never move.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Apply `f` to an integer value; non-numeric input or overflow leaves it unchanged.
+fn map_int(val: String, f: impl FnOnce(i64) -> Option<i64>) -> String {
+    match val.trim().parse::<i64>().ok().and_then(f) {
+        Some(n) => n.to_string(),
+        None => val,
+    }
+}
+
+/// Look up `key` in an `a=b&c=d` string, percent-decoding keys and values (`+` is a space).
+fn form_get(input: &str, key: &str) -> Option<String> {
+    parse_form(input).find(|(k, _)| k == key).map(|(_, v)| v)
 }
 
 #[cfg(test)]
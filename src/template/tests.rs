@@ -12,7 +12,7 @@ fn compile_and_expand_with_filters() {
     let mut m = std::collections::HashMap::new();
     m.insert("name".into(), "bob".into());
     let ctx = MapProvider(m);
-    let out = expand_template(&tpl, &ctx).unwrap();
+    let out = expand_template(&tpl, &ctx, &ExpandOptions::default()).unwrap();
     assert_eq!(out, "hi BOB, x!");
 }
 
@@ -22,6 +22,240 @@ fn parse_filters_with_args() {
     let mut m = std::collections::HashMap::new();
     m.insert("slug".into(), "/api/v1/users".into());
     let ctx = MapProvider(m);
-    let out = expand_template(&tpl, &ctx).unwrap();
+    let out = expand_template(&tpl, &ctx, &ExpandOptions::default()).unwrap();
     assert_eq!(out, "v1-users");
 }
+
+#[test]
+fn form_get_extracts_decoded_field() {
+    let tpl = compile_template("[${p|form_get(\"id\")}][${p|form_get(\"name\")}][${p|form_get(\"missing\")}]").unwrap();
+    let mut m = std::collections::HashMap::new();
+    m.insert("p".into(), "id=42&name=J%C3%BCrgen+M&flag".into());
+    let ctx = MapProvider(m);
+    let out = expand_template(&tpl, &ctx, &ExpandOptions::default()).unwrap();
+    assert_eq!(out, "[42][Jürgen M][]");
+}
+
+#[test]
+fn add_and_sub_on_integers() {
+    let tpl = compile_template("${page|add(1)},${page|sub(\"3\")},${name|add(1)}").unwrap();
+    let mut m = std::collections::HashMap::new();
+    m.insert("page".into(), "2".into());
+    m.insert("name".into(), "two".into());
+    let ctx = MapProvider(m);
+    assert_eq!(expand_template(&tpl, &ctx, &ExpandOptions::default()).unwrap(), "3,-1,two");
+
+    assert!(compile_template("${page|add(\"x\")}").is_err());
+}
+
+#[test]
+fn hash_mod_is_stable_and_spreads_keys() {
+    let tpl = compile_template("${key|hash_mod(16)}").unwrap();
+    let bucket = |key: &str| {
+        let mut m = std::collections::HashMap::new();
+        m.insert("key".to_string(), key.to_string());
+        expand_template(&tpl, &MapProvider(m), &ExpandOptions::default()).unwrap().parse::<u64>().unwrap()
+    };
+
+    assert_eq!(bucket("user-42"), bucket("user-42"));
+    // FNV-1a of the empty string is the offset basis
+    assert_eq!(bucket(""), 0xcbf2_9ce4_8422_2325 % 16);
+
+    let mut counts = [0u32; 16];
+    for i in 0..1600 {
+        let b = bucket(&format!("user-{i}"));
+        assert!(b < 16);
+        counts[b as usize] += 1;
+    }
+    assert!(counts.iter().all(|&c| (50..=150).contains(&c)), "{counts:?}");
+
+    assert!(compile_template("${key|hash_mod(0)}").is_err());
+}
+
+#[test]
+fn replace_segment_only_touches_whole_segments() {
+    let tpl = compile_template("${p|replace_segment(\"a\", \"x\")}").unwrap();
+    let mut m = std::collections::HashMap::new();
+    m.insert("p".into(), "/a/abc/a".into());
+    let ctx = MapProvider(m);
+    assert_eq!(expand_template(&tpl, &ctx, &ExpandOptions::default()).unwrap(), "/x/abc/x");
+
+    let tpl = compile_template("${p|replace(\"a\", \"x\")}").unwrap();
+    assert_eq!(expand_template(&tpl, &ctx, &ExpandOptions::default()).unwrap(), "/x/xbc/x");
+}
+
+#[test]
+fn capture_or_uses_presence_not_emptiness() {
+    let tpl = compile_template("[${ capture_or(tenant, \"public\") }][${capture_or(tenant, \"public\")|upper}]").unwrap();
+    let expand = |tenant: Option<&str>| {
+        let mut m = std::collections::HashMap::new();
+        if let Some(t) = tenant {
+            m.insert("tenant".into(), t.into());
+        }
+        expand_template(&tpl, &MapProvider(m), &ExpandOptions::default()).unwrap()
+    };
+    assert_eq!(expand(Some("acme")), "[acme][ACME]");
+    assert_eq!(expand(Some("")), "[][]");
+    assert_eq!(expand(None), "[public][PUBLIC]");
+
+    assert!(compile_template("${capture_or(tenant)}").is_err());
+    assert!(compile_template("${nope(tenant, \"x\")}").is_err());
+}
+
+#[test]
+fn over_long_expansion_is_rejected() {
+    let tpl = compile_template("${a}${a}${a}").unwrap();
+    let mut m = std::collections::HashMap::new();
+    m.insert("a".into(), "x".repeat(4));
+    let ctx = MapProvider(m);
+    assert_eq!(expand_template(&tpl, &ctx, &ExpandOptions { max_len: 12, ..Default::default() }).unwrap().len(), 12);
+    assert!(expand_template(&tpl, &ctx, &ExpandOptions { max_len: 11, ..Default::default() }).is_err());
+
+    let mut m = std::collections::HashMap::new();
+    m.insert("a".into(), "x".repeat(DEFAULT_MAX_EXPANSION + 1));
+    assert!(expand_template(&compile_template("${a}").unwrap(), &MapProvider(m), &ExpandOptions::default()).is_err());
+}
+
+#[test]
+fn repeat_filter_is_bounded() {
+    let expand = |src: &str| expand_template(&compile_template(src).unwrap(), &MapProvider::default(), &ExpandOptions::default()).unwrap();
+    assert_eq!(expand("${\"-\" | repeat(10)}"), "----------");
+    assert_eq!(expand("[${\"ab\"|repeat(0)}]"), "[]");
+    assert_eq!(expand("${\"x\" | repeat(10000)}").len(), filter::MAX_REPEAT);
+    assert!(compile_template("${\"x\" | repeat(10001)}").is_err());
+    assert!(compile_template("${\"x\" | repeat(-1)}").is_err());
+
+    // rejected against max_len before the repeated value is built
+    let mut m = std::collections::HashMap::new();
+    m.insert("a".into(), "x".repeat(DEFAULT_MAX_EXPANSION));
+    let tpl = compile_template("${a | repeat(10000)}").unwrap();
+    assert!(expand_template(&tpl, &MapProvider(m), &ExpandOptions::default()).is_err());
+    let opts = ExpandOptions { max_len: 9, ..Default::default() };
+    assert!(expand_template(&compile_template("${\"abc\" | repeat(4)}").unwrap(), &MapProvider::default(), &opts).is_err());
+}
+
+#[test]
+fn reverse_filter_reverses_scalar_values() {
+    let mut m = std::collections::HashMap::new();
+    m.insert("a".into(), "example.com".into());
+    m.insert("u".into(), "añb日本🦀".into());
+    let tpl = compile_template("${a|reverse}|${u|reverse}").unwrap();
+    let out = expand_template(&tpl, &MapProvider(m), &ExpandOptions::default()).unwrap();
+    assert_eq!(out, "moc.elpmaxe|🦀本日bña");
+}
+
+#[test]
+fn slugify_filter_builds_url_slugs() {
+    let slug = |title: &str| {
+        let m = std::iter::once(("t".to_string(), title.to_string())).collect();
+        expand_template(&compile_template("${t|slugify}").unwrap(), &MapProvider(m), &ExpandOptions::default()).unwrap()
+    };
+    assert_eq!(slug("Hello   World"), "hello-world");
+    assert_eq!(slug("  -- Rust 2024: what's new?! --"), "rust-2024-what-s-new");
+    assert_eq!(slug("Crème Brûlée à la Straße"), "creme-brulee-a-la-strasse");
+    assert_eq!(slug("Ærøskøbing / Łódź"), "aeroskobing-lodz");
+    assert_eq!(slug("日本語"), "");
+}
+
+#[test]
+fn hex_and_unhex_round_trip() {
+    let expand = |src: &str, val: &str| {
+        let m = std::iter::once(("v".to_string(), val.to_string())).collect();
+        expand_template(&compile_template(src).unwrap(), &MapProvider(m), &ExpandOptions::default()).unwrap()
+    };
+    assert_eq!(expand("${v|hex}", "Hi!"), "486921");
+    assert_eq!(expand("${v|hex}", "é"), "c3a9");
+    assert_eq!(expand("${v|hex|unhex}", "key:42/é"), "key:42/é");
+    assert_eq!(expand("${v|unhex}", "4A4b"), "JK");
+    // bytes that are not UTF-8 decode lossily to U+FFFD
+    assert_eq!(expand("${v|unhex|hex}", "DEADBEEF"), "deadefbfbdefbfbd");
+}
+
+#[test]
+fn invalid_unhex_gives_empty() {
+    let expand = |val: &str| {
+        let m = std::iter::once(("v".to_string(), val.to_string())).collect();
+        expand_template(&compile_template("[${v|unhex}]").unwrap(), &MapProvider(m), &ExpandOptions::default()).unwrap()
+    };
+    assert_eq!(expand("abc"), "[]");
+    assert_eq!(expand("zz"), "[]");
+    assert_eq!(expand("0é"), "[]");
+    assert_eq!(expand(""), "[]");
+}
+
+#[test]
+fn if_present_and_if_empty_pick_literals() {
+    let mut m = std::collections::HashMap::new();
+    m.insert("set".into(), "value".into());
+    m.insert("blank".into(), String::new());
+    let tpl = compile_template(
+        "${set|if_present(\"yes\")},${blank|if_present(\"yes\")},${nope|if_present(\"yes\")};\
+         ${set|if_empty(\"none\")},${blank|if_empty(\"none\")},${nope|if_empty(\"none\")}",
+    ).unwrap();
+    let out = expand_template(&tpl, &MapProvider(m.clone()), &ExpandOptions::default()).unwrap();
+    assert_eq!(out, "yes,,;,none,none");
+
+    // they see the unset value, not the router-wide fallback
+    let opts = ExpandOptions { missing_default: Some("?".into()), ..Default::default() };
+    let tpl = compile_template("${nope|if_present(\"yes\")}|${nope|if_empty(\"none\")}").unwrap();
+    assert_eq!(expand_template(&tpl, &MapProvider(m), &opts).unwrap(), "|none");
+    assert!(compile_template("${set|if_present}").is_err());
+}
+
+#[test]
+fn missing_default_fills_unset_variables_only() {
+    let opts = ExpandOptions { missing_default: Some("?".into()), ..Default::default() };
+    let expand = |src: &str| {
+        let m = std::iter::once(("empty".to_string(), String::new())).collect();
+        expand_template(&compile_template(src).unwrap(), &MapProvider(m), &opts).unwrap()
+    };
+    assert_eq!(expand("[${nope}|${empty}|${nope|upper}]"), "[?||?]");
+    // a per-template default still wins
+    assert_eq!(expand("${nope|default(\"d\")}"), "d");
+    assert_eq!(expand("${nope ?? other}|${nope ?? empty}|${capture_or(nope, \"c\")}"), "?||c");
+}
+
+#[test]
+fn null_coalescing_chain_takes_first_non_empty() {
+    let tpl = compile_template("${ header.x-real-host ?? host ?? \"localhost\" | upper }").unwrap();
+    let expand = |pairs: &[(&str, &str)]| {
+        let m = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        expand_template(&tpl, &MapProvider(m), &ExpandOptions::default()).unwrap()
+    };
+    assert_eq!(expand(&[("header.x-real-host", "real"), ("host", "h")]), "REAL");
+    assert_eq!(expand(&[("header.x-real-host", ""), ("host", "h")]), "H");
+    assert_eq!(expand(&[]), "LOCALHOST");
+
+    assert!(compile_template("${ a ?? }").is_err());
+}
+
+#[test]
+fn quoted_fallback_may_contain_separators() {
+    let expand = |src: &str| {
+        let tpl = compile_template(src).unwrap();
+        expand_template(&tpl, &MapProvider(Default::default()), &ExpandOptions::default()).unwrap()
+    };
+    assert_eq!(expand("${a ?? \"what??\"}"), "what??");
+    assert_eq!(expand("${a ?? \"x ?? y\" ?? b}"), "x ?? y");
+    assert_eq!(expand("${a ?? \"a|b\" | upper}"), "A|B");
+    assert_eq!(expand("${\"a|b\" | replace(\"|\", \"/\")}"), "a/b");
+}
+
+#[test]
+fn substring_counts_chars_and_clamps() {
+    let expand = |src: &str, val: &str| {
+        let m = std::iter::once(("v".to_string(), val.to_string())).collect();
+        expand_template(&compile_template(src).unwrap(), &MapProvider(m), &ExpandOptions::default()).unwrap()
+    };
+    assert_eq!(expand("${v|substring(0, 8)}", "/api/v1/users"), "/api/v1/");
+    assert_eq!(expand("${v|substring(4)}", "/api/v1/users"), "/v1/users");
+    assert_eq!(expand("${v|substring(1, 3)}", "汉字ab"), "字a");
+    assert_eq!(expand("${v|substring(-2, 2)}", "héllo"), "hé");
+    assert_eq!(expand("[${v|substring(3, 100)}]", "héllo"), "[lo]");
+    assert_eq!(expand("[${v|substring(10)}]", "héllo"), "[]");
+    assert_eq!(expand("[${v|substring(4, 2)}]", "héllo"), "[]");
+
+    assert!(compile_template("${v|substring}").is_err());
+    assert!(compile_template("${v|substring(\"x\", 2)}").is_err());
+    assert!(compile_template("${v|substring(1, 2, 3)}").is_err());
+}
@@ -0,0 +1,116 @@
+//! Helpers for driving services over a real loopback connection in tests.
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::http;
+use hyper_util::rt::TokioIo;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::build::service::{LoadedService, build_service};
+use crate::config::service::{Service, validate_service};
+
+/// Build a service from an inline YAML `Service` definition.
+pub fn service(yaml: &str) -> LoadedService {
+    let cfg: Service = serde_yaml::from_str(yaml).expect("invalid service yaml");
+    validate_service(&cfg, Path::new(".")).expect("service failed validation");
+    build_service(&cfg, Path::new(".")).expect("failed to build service")
+}
+
+/// Serve `svc` on an ephemeral loopback port and return its address.
+pub async fn spawn(svc: LoadedService) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind failed");
+    let addr = listener.local_addr().expect("no local addr");
+    tokio::spawn(crate::http_server::serve(listener, None, Arc::new(svc), Default::default(), Default::default(), Vec::new()));
+    addr
+}
+
+/// Send a request over a fresh connection, returning the response with its body unread.
+pub async fn send_streaming<B>(addr: SocketAddr, req: http::Request<B>) -> http::Response<hyper::body::Incoming>
+where
+    B: hyper::body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let stream = TcpStream::connect(addr).await.expect("connect failed");
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .expect("handshake failed");
+    tokio::spawn(conn);
+    sender.send_request(req).await.expect("request failed")
+}
+
+/// Send a request over a fresh connection and collect the response body.
+pub async fn send(addr: SocketAddr, req: http::Request<Full<Bytes>>) -> http::Response<Bytes> {
+    let resp = send_streaming(addr, req).await;
+    let (parts, body) = resp.into_parts();
+    let bytes = body.collect().await.expect("body failed").to_bytes();
+    http::Response::from_parts(parts, bytes)
+}
+
+/// Shorthand for a bodyless GET with a Host header.
+pub async fn get(addr: SocketAddr, path: &str) -> http::Response<Bytes> {
+    let req = http::Request::get(path)
+        .header(http::header::HOST, "example.com")
+        .body(Full::default())
+        .unwrap();
+    send(addr, req).await
+}
+
+/// Create a fresh, empty directory under the system temp dir populated with `files`.
+pub fn temp_dir(files: &[(&str, &str)]) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "oxidase-test-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed),
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    for (rel, content) in files {
+        let path = dir.join(rel);
+        std::fs::create_dir_all(path.parent().unwrap()).expect("create dir failed");
+        std::fs::write(&path, content).expect("write failed");
+    }
+    std::fs::create_dir_all(&dir).expect("create dir failed");
+    dir
+}
+
+/// A `tracing` writer that keeps everything written to it in memory.
+#[derive(Clone, Default)]
+pub struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    /// Install a subscriber at `level` for the current thread, capturing its output.
+    pub fn install(level: tracing::Level) -> (Self, tracing::subscriber::DefaultGuard) {
+        let logs = CapturedLogs::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(level)
+            .with_ansi(false)
+            .with_writer(logs.clone())
+            .finish();
+        (logs, tracing::subscriber::set_default(subscriber))
+    }
+
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+}
+
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for CapturedLogs {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer { self.clone() }
+}
@@ -0,0 +1,41 @@
+//! Source of the current time for time-dependent router features, so tests can pin it.
+
+use std::fmt::Debug;
+use std::time::SystemTime;
+
+pub trait Clock: Send + Sync + Debug {
+    fn now(&self) -> SystemTime;
+}
+
+/// The wall clock; what every built router uses.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct MockClock(std::sync::Mutex<SystemTime>);
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new(at: SystemTime) -> Self {
+        Self(std::sync::Mutex::new(at))
+    }
+
+    pub fn advance(&self, by: std::time::Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
+}
@@ -0,0 +1,40 @@
+/// Match `text` against a glob supporting `*` (any run of chars except `/`) and `?` (one char except `/`).
+/// A pattern without `/` is matched against the last path segment only, so `*.js` matches `/a/b.js`.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let text = if pattern.contains('/') {
+        text
+    } else {
+        text.rsplit('/').next().unwrap_or(text)
+    };
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        match p.get(pi) {
+            Some('*') => {
+                star = Some((pi, ti));
+                pi += 1;
+            }
+            Some('?') if t[ti] != '/' => {
+                pi += 1;
+                ti += 1;
+            }
+            Some(&c) if c != '?' && c == t[ti] => {
+                pi += 1;
+                ti += 1;
+            }
+            _ => match star {
+                // let the last `*` absorb one more char, unless that char is a separator
+                Some((sp, st)) if t[st] != '/' => {
+                    star = Some((sp, st + 1));
+                    pi = sp + 1;
+                    ti = st + 1;
+                }
+                _ => return false,
+            },
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
@@ -1,9 +1,117 @@
-use bytes::Bytes;
-use http_body_util::Full;
 use hyper::http;
+use percent_encoding::percent_decode_str;
 
-pub fn make_error_resp(status: http::StatusCode, msg: &str) -> http::Response<Full<Bytes>> {
-    let mut resp = http::Response::new(Full::from(msg.to_string()));
+use crate::handler::{ResponseBody, full};
+
+pub fn make_error_resp(status: http::StatusCode, msg: &str) -> http::Response<ResponseBody> {
+    let mut resp = http::Response::new(full(msg.to_string()));
     *resp.status_mut() = status;
+    set_content_length(&mut resp, msg.len());
     resp
 }
+
+/// Set `Content-Length` for an in-memory body instead of relying on the body's size
+/// hint surviving later wrapping. Statuses that cannot carry a body are left alone.
+pub fn set_content_length<B>(resp: &mut http::Response<B>, len: usize) {
+    if resp.status().is_informational() || resp.status() == http::StatusCode::NO_CONTENT {
+        return;
+    }
+    resp.headers_mut().insert(http::header::CONTENT_LENGTH, http::HeaderValue::from(len));
+}
+
+/// Split a `Host` header into host and optional port. IPv6 literals keep their
+/// brackets (`[::1]:8080` gives `[::1]`). Returns `None` for values that are not
+/// a valid `host[:port]`, such as an unbracketed IPv6 address or one with userinfo.
+pub fn parse_host_header(value: &str) -> Option<(String, Option<u16>)> {
+    let authority: http::uri::Authority = value.parse().ok()?;
+    if authority.as_str().contains('@') || authority.host().is_empty() {
+        return None;
+    }
+    Some((authority.host().to_string(), authority.port_u16()))
+}
+
+/// Split an `application/x-www-form-urlencoded` string into decoded key/value pairs,
+/// treating `+` as a space.
+pub fn parse_form(input: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    let decode = |s: &str| percent_decode_str(&s.replace('+', " ")).decode_utf8_lossy().into_owned();
+    input
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(move |pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(k), decode(v))
+        })
+}
+
+/// Percent-decode a URL path, leaving `%2F` encoded so decoding never introduces a
+/// segment separator. Returns `None` if the decoded bytes are not UTF-8.
+pub fn decode_path(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(b'/')) => {
+                out.extend_from_slice(b"%2F");
+                i += 3;
+            }
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// The `boundary` parameter of a `multipart/form-data` content type.
+pub fn multipart_boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    if !params.next()?.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params
+        .filter_map(|p| p.split_once('='))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, v)| v.trim().trim_matches('"').to_string())
+        .filter(|b| !b.is_empty())
+}
+
+/// Field names of the multipart parts whose headers lie entirely within `body`,
+/// taken from each part's `Content-Disposition: form-data; name="..."`.
+pub fn multipart_field_names(body: &[u8], boundary: &str) -> Vec<String> {
+    let delim = format!("--{boundary}");
+    let mut names = Vec::new();
+    let mut rest = body;
+    while let Some(at) = find(rest, delim.as_bytes()) {
+        rest = &rest[at + delim.len()..];
+        if rest.starts_with(b"--") {
+            break;
+        }
+        let Some(end) = find(rest, b"\r\n\r\n") else { break };
+        let headers = String::from_utf8_lossy(&rest[..end]);
+        for line in headers.split("\r\n") {
+            let Some((name, value)) = line.split_once(':') else { continue };
+            if !name.trim().eq_ignore_ascii_case("content-disposition") {
+                continue;
+            }
+            let field = value
+                .split(';')
+                .filter_map(|p| p.split_once('='))
+                .find(|(k, _)| k.trim().eq_ignore_ascii_case("name"))
+                .map(|(_, v)| v.trim().trim_matches('"').to_string());
+            names.extend(field);
+        }
+        rest = &rest[end + 4..];
+    }
+    names
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
@@ -1,2 +1,8 @@
 pub mod parse;
 pub mod http;
+pub mod glob;
+pub mod jwt;
+pub mod cookie_sig;
+pub mod clock;
+pub mod cidr;
+pub mod random;
@@ -0,0 +1,7 @@
+//! Unpredictable bytes for values that must not be guessable, such as CSP nonces.
+
+/// Fill `buf` from the OS random source. There is no weaker fallback: callers fail
+/// the request instead.
+pub fn fill(buf: &mut [u8]) -> std::io::Result<()> {
+    getrandom::fill(buf).map_err(Into::into)
+}